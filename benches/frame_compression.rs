@@ -0,0 +1,96 @@
+//! Throughput and ratio of the frame payload encodings on synthetic 16-bit
+//! sensor frames (sky background, read noise and a sprinkling of stars).
+//!
+//! Run with `cargo bench --bench frame_compression --features full`.
+
+use std::time::{Duration, Instant};
+
+use astrotools::frame::{
+    compress_payload, decompress_payload, FrameEncoding, FrameHeader, PixelFormat, MAX_PAYLOAD_LEN,
+};
+
+const SIZES: [(u32, u32); 3] = [(1920, 1080), (4144, 2822), (6248, 4176)];
+const MIN_TIME: Duration = Duration::from_millis(500);
+
+fn synthetic_frame(width: u32, height: u32) -> Vec<u8> {
+    let mut state = 0x2545_f491u32;
+    let mut next = move || {
+        state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        state >> 16
+    };
+    let mut pixels: Vec<u16> = (0..width * height)
+        .map(|_| 1200 + (next() % 64) as u16)
+        .collect();
+    for _ in 0..(width * height / 20_000) {
+        let cx = next() % width;
+        let cy = next() % height;
+        let peak = 2000 + next() % 50_000;
+        for dy in 0..5u32 {
+            for dx in 0..5u32 {
+                let (x, y) = (cx + dx, cy + dy);
+                if x < width && y < height {
+                    let d2 = (dx as i32 - 2).pow(2) + (dy as i32 - 2).pow(2);
+                    let i = (y * width + x) as usize;
+                    pixels[i] = pixels[i].saturating_add((peak >> (d2 * 2)) as u16);
+                }
+            }
+        }
+    }
+    pixels.iter().flat_map(|p| p.to_le_bytes()).collect()
+}
+
+/// Run `f` until `MIN_TIME` has elapsed and return the mean time per call.
+fn time<T>(mut f: impl FnMut() -> T) -> Duration {
+    let start = Instant::now();
+    let mut runs = 0u32;
+    while start.elapsed() < MIN_TIME || runs == 0 {
+        std::hint::black_box(f());
+        runs += 1;
+    }
+    start.elapsed() / runs
+}
+
+fn mb_per_sec(bytes: usize, elapsed: Duration) -> f64 {
+    bytes as f64 / 1e6 / elapsed.as_secs_f64()
+}
+
+fn main() {
+    let mut encodings = vec![FrameEncoding::Rice];
+    if cfg!(feature = "zstd") {
+        encodings.push(FrameEncoding::Zstd);
+    }
+    if cfg!(feature = "lz4") {
+        encodings.push(FrameEncoding::Lz4);
+    }
+
+    println!(
+        "{:>10} {:>6} {:>8} {:>12} {:>12}",
+        "frame", "codec", "ratio", "enc MB/s", "dec MB/s"
+    );
+    for (width, height) in SIZES {
+        let data = synthetic_frame(width, height);
+        let header = FrameHeader {
+            width,
+            height,
+            bit_depth: 16,
+            pixel_format: Some(PixelFormat::Mono16Le),
+            ..Default::default()
+        };
+        for &encoding in &encodings {
+            let mut packed_header = header.clone();
+            let packed = compress_payload(&mut packed_header, &data, encoding).unwrap();
+            let encode = time(|| compress_payload(&mut header.clone(), &data, encoding).unwrap());
+            let decode = time(|| {
+                decompress_payload(&mut packed_header.clone(), &packed, MAX_PAYLOAD_LEN).unwrap()
+            });
+            println!(
+                "{:>10} {:>6} {:>8.2} {:>12.1} {:>12.1}",
+                format!("{width}x{height}"),
+                format!("{encoding:?}").to_lowercase(),
+                data.len() as f64 / packed.len() as f64,
+                mb_per_sec(data.len(), encode),
+                mb_per_sec(data.len(), decode),
+            );
+        }
+    }
+}
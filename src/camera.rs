@@ -0,0 +1,397 @@
+//! Camera trait for imaging devices ([`DeviceType::Ccd`](crate::DeviceType)).
+//!
+//! Drivers implement [`Camera`] next to [`LightspeedDevice`](crate::device::LightspeedDevice)
+//! so servers can drive any camera the same way. Optional capabilities
+//! (readout modes, gain, offset, cooling) have defaults that report
+//! [`LightspeedError::NotSupported`].
+
+use std::time::Duration;
+
+use crate::frame::{BayerPattern, FrameHeader, FrameType};
+use crate::frame_pool::FrameBuffer;
+use crate::imaging::ExposureState;
+use crate::properties::PropertyErrorType;
+use crate::LightspeedError;
+
+/// Fixed sensor characteristics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorInfo {
+    /// Unbinned sensor width in pixels.
+    pub width: u32,
+    /// Unbinned sensor height in pixels.
+    pub height: u32,
+    pub pixel_size_x_um: f64,
+    pub pixel_size_y_um: f64,
+    /// Largest pixel value the camera delivers, e.g. 65535 or 4095.
+    pub max_adu: u32,
+    /// Bits per pixel of delivered frames.
+    pub bit_depth: u8,
+    /// None for mono sensors.
+    pub bayer: Option<BayerPattern>,
+    pub max_bin_x: u16,
+    pub max_bin_y: u16,
+}
+
+/// Subframe in unbinned sensor pixels, like [`FrameHeader::roi_x`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Roi {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Roi {
+    /// The whole sensor.
+    pub fn full(info: &SensorInfo) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width: info.width,
+            height: info.height,
+        }
+    }
+
+    /// True if the ROI is non-empty and lies on the sensor.
+    pub fn fits(&self, info: &SensorInfo) -> bool {
+        self.width > 0
+            && self.height > 0
+            && self
+                .x
+                .checked_add(self.width)
+                .is_some_and(|r| r <= info.width)
+            && self
+                .y
+                .checked_add(self.height)
+                .is_some_and(|b| b <= info.height)
+    }
+}
+
+/// Cooler readback.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CoolerStatus {
+    pub on: bool,
+    /// Set-point in degrees Celsius.
+    pub target_c: f64,
+    /// Current sensor temperature in degrees Celsius.
+    pub temperature_c: f64,
+    /// Cooler drive, 0..=100. None if the camera does not report it.
+    pub power_percent: Option<f64>,
+}
+
+pub trait Camera {
+    fn sensor_info(&self) -> SensorInfo;
+
+    /// Start an exposure with the current binning, ROI, gain and offset.
+    fn start_exposure(
+        &mut self,
+        duration: Duration,
+        frame_type: FrameType,
+    ) -> Result<(), LightspeedError>;
+    fn abort_exposure(&mut self) -> Result<(), LightspeedError>;
+    fn exposure_state(&self) -> &ExposureState;
+
+    /// Hand over the completed frame's buffer, usually taken from a
+    /// [`FramePool`](crate::frame_pool::FramePool), with its header. No
+    /// copy is made; dropping the buffer returns it to its pool. `Ok(None)`
+    /// while no frame is ready.
+    fn read_frame(&mut self) -> Result<Option<(FrameHeader, FrameBuffer)>, LightspeedError>;
+
+    fn binning(&self) -> (u16, u16);
+    fn set_binning(&mut self, x: u16, y: u16) -> Result<(), LightspeedError>;
+
+    fn roi(&self) -> Roi;
+    fn set_roi(&mut self, roi: Roi) -> Result<(), LightspeedError>;
+
+    /// Readout mode names, indexed by [`Camera::set_readout_mode`].
+    fn readout_modes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn readout_mode(&self) -> usize {
+        0
+    }
+
+    fn set_readout_mode(&mut self, _index: usize) -> Result<(), LightspeedError> {
+        Err(LightspeedError::NotSupported)
+    }
+
+    fn gain(&self) -> Option<f64> {
+        None
+    }
+
+    /// `(min, max)` accepted by [`Camera::set_gain`].
+    fn gain_range(&self) -> Option<(f64, f64)> {
+        None
+    }
+
+    fn set_gain(&mut self, _gain: f64) -> Result<(), LightspeedError> {
+        Err(LightspeedError::NotSupported)
+    }
+
+    fn offset(&self) -> Option<i32> {
+        None
+    }
+
+    /// `(min, max)` accepted by [`Camera::set_offset`].
+    fn offset_range(&self) -> Option<(i32, i32)> {
+        None
+    }
+
+    fn set_offset(&mut self, _offset: i32) -> Result<(), LightspeedError> {
+        Err(LightspeedError::NotSupported)
+    }
+
+    /// Sensor temperature in degrees Celsius, cooled or not.
+    fn sensor_temperature(&self) -> Option<f64> {
+        self.cooler().map(|c| c.temperature_c)
+    }
+
+    /// None for uncooled cameras.
+    fn cooler(&self) -> Option<CoolerStatus> {
+        None
+    }
+
+    fn set_cooler(&mut self, _on: bool) -> Result<(), LightspeedError> {
+        Err(LightspeedError::NotSupported)
+    }
+
+    fn set_cooler_target(&mut self, _celsius: f64) -> Result<(), LightspeedError> {
+        Err(LightspeedError::NotSupported)
+    }
+
+    /// Header for a frame taken with the current settings. Drivers fill in
+    /// the ids, timestamp, exposure and frame type.
+    fn frame_template(&self) -> FrameHeader {
+        let info = self.sensor_info();
+        let (bin_x, bin_y) = self.binning();
+        let roi = self.roi();
+        FrameHeader {
+            width: roi.width / bin_x.max(1) as u32,
+            height: roi.height / bin_y.max(1) as u32,
+            bit_depth: info.bit_depth,
+            bayer: info.bayer,
+            gain: self.gain(),
+            offset: self.offset(),
+            bin_x: Some(bin_x),
+            bin_y: Some(bin_y),
+            roi_x: Some(roi.x),
+            roi_y: Some(roi.y),
+            sensor_temp_c: self.sensor_temperature(),
+            pixel_size_x_um: Some(info.pixel_size_x_um),
+            pixel_size_y_um: Some(info.pixel_size_y_um),
+            ..Default::default()
+        }
+    }
+}
+
+/// Check a binning request against the sensor limits.
+pub fn validate_binning(info: &SensorInfo, x: u16, y: u16) -> Result<(), LightspeedError> {
+    if x == 0 || y == 0 || x > info.max_bin_x || y > info.max_bin_y {
+        return Err(PropertyErrorType::ValueOutOfRange.into());
+    }
+    Ok(())
+}
+
+/// Check an ROI against the sensor size.
+pub fn validate_roi(info: &SensorInfo, roi: &Roi) -> Result<(), LightspeedError> {
+    if !roi.fits(info) {
+        return Err(PropertyErrorType::ValueOutOfRange.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame_pool::FramePool;
+    use std::time::Instant;
+
+    struct FakeCamera {
+        state: ExposureState,
+        bin: (u16, u16),
+        roi: Roi,
+        gain: f64,
+    }
+
+    impl FakeCamera {
+        fn new() -> Self {
+            Self {
+                state: ExposureState::default(),
+                bin: (1, 1),
+                roi: Roi::full(&Self::info()),
+                gain: 0.0,
+            }
+        }
+
+        fn info() -> SensorInfo {
+            SensorInfo {
+                width: 6248,
+                height: 4176,
+                pixel_size_x_um: 3.76,
+                pixel_size_y_um: 3.76,
+                max_adu: 65535,
+                bit_depth: 16,
+                bayer: Some(BayerPattern::Rggb),
+                max_bin_x: 4,
+                max_bin_y: 4,
+            }
+        }
+    }
+
+    impl Camera for FakeCamera {
+        fn sensor_info(&self) -> SensorInfo {
+            Self::info()
+        }
+
+        fn start_exposure(
+            &mut self,
+            duration: Duration,
+            _frame_type: FrameType,
+        ) -> Result<(), LightspeedError> {
+            self.state
+                .start(duration, Instant::now())
+                .map(|_| ())
+                .map_err(|_| LightspeedError::QueueFull)
+        }
+
+        fn abort_exposure(&mut self) -> Result<(), LightspeedError> {
+            self.state = ExposureState::Idle;
+            Ok(())
+        }
+
+        fn exposure_state(&self) -> &ExposureState {
+            &self.state
+        }
+
+        fn read_frame(&mut self) -> Result<Option<(FrameHeader, FrameBuffer)>, LightspeedError> {
+            Ok(self
+                .state
+                .take_frame()
+                .map(|(data, _)| (self.frame_template(), data)))
+        }
+
+        fn binning(&self) -> (u16, u16) {
+            self.bin
+        }
+
+        fn set_binning(&mut self, x: u16, y: u16) -> Result<(), LightspeedError> {
+            validate_binning(&self.sensor_info(), x, y)?;
+            self.bin = (x, y);
+            Ok(())
+        }
+
+        fn roi(&self) -> Roi {
+            self.roi
+        }
+
+        fn set_roi(&mut self, roi: Roi) -> Result<(), LightspeedError> {
+            validate_roi(&self.sensor_info(), &roi)?;
+            self.roi = roi;
+            Ok(())
+        }
+
+        fn gain(&self) -> Option<f64> {
+            Some(self.gain)
+        }
+
+        fn gain_range(&self) -> Option<(f64, f64)> {
+            Some((0.0, 300.0))
+        }
+
+        fn set_gain(&mut self, gain: f64) -> Result<(), LightspeedError> {
+            self.gain = gain;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn frame_template_reflects_settings() {
+        let mut cam = FakeCamera::new();
+        cam.set_binning(2, 2).unwrap();
+        cam.set_roi(Roi {
+            x: 100,
+            y: 200,
+            width: 1000,
+            height: 800,
+        })
+        .unwrap();
+        cam.set_gain(100.0).unwrap();
+
+        let h = cam.frame_template();
+        assert_eq!((h.width, h.height), (500, 400));
+        assert_eq!((h.bin_x, h.roi_x, h.roi_y), (Some(2), Some(100), Some(200)));
+        assert_eq!(h.gain, Some(100.0));
+        assert_eq!(h.offset, None);
+        assert_eq!(h.bayer, Some(BayerPattern::Rggb));
+        assert_eq!(h.sensor_temp_c, None);
+    }
+
+    #[test]
+    fn validates_binning_and_roi() {
+        let mut cam = FakeCamera::new();
+        assert!(matches!(
+            cam.set_binning(8, 8),
+            Err(LightspeedError::PropertyError(
+                PropertyErrorType::ValueOutOfRange
+            ))
+        ));
+        assert!(cam.set_binning(0, 1).is_err());
+        assert!(cam
+            .set_roi(Roi {
+                x: 6000,
+                y: 0,
+                width: 500,
+                height: 10
+            })
+            .is_err());
+        assert!(cam
+            .set_roi(Roi {
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 10
+            })
+            .is_err());
+        assert_eq!(cam.roi(), Roi::full(&FakeCamera::info()));
+    }
+
+    #[test]
+    fn optional_capabilities_default_to_not_supported() {
+        let mut cam = FakeCamera::new();
+        assert!(cam.readout_modes().is_empty());
+        assert!(matches!(
+            cam.set_readout_mode(1),
+            Err(LightspeedError::NotSupported)
+        ));
+        assert!(matches!(
+            cam.set_cooler(true),
+            Err(LightspeedError::NotSupported)
+        ));
+        assert!(cam.cooler().is_none());
+
+        assert!(cam.read_frame().unwrap().is_none());
+        cam.start_exposure(Duration::from_secs(1), FrameType::Light)
+            .unwrap();
+        assert!(cam.exposure_state().is_exposing());
+    }
+
+    #[test]
+    fn read_frame_hands_over_the_pooled_buffer() {
+        let pool = FramePool::new(1, 8);
+        let mut buf = pool.try_get().unwrap();
+        buf.clear();
+        buf.extend_from_slice(&[1, 2, 3, 4]);
+        let mut cam = FakeCamera::new();
+        cam.state = ExposureState::Complete((buf, 2));
+
+        let (header, data) = cam.read_frame().unwrap().unwrap();
+        assert_eq!(header.width, 6248);
+        assert!(data.is_pooled());
+        assert_eq!(&data[..], &[1, 2, 3, 4]);
+        assert_eq!(pool.metrics().in_use, 1);
+        drop(data);
+        assert_eq!(pool.metrics().in_use, 0);
+        assert!(cam.read_frame().unwrap().is_none());
+    }
+}
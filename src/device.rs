@@ -1,17 +1,17 @@
+use crate::frame::FrameHeader;
+use crate::presence::DevicePresence;
+use crate::protocol::ErrorEnvelope;
 use crate::LightspeedError;
-use std::sync::mpsc::SyncSender;
+
+pub use crate::DeviceType;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Arc;
 use uuid::Uuid;
 
-pub enum DeviceType {
-    Ccd,
-    Mount,
-    Focuser,
-    FilterWheel,
-    PowerBox,
-    Dome,     // NEW in 0.12
-    Weather,  // NEW in 0.12
-    AuxBox,   // NEW in 0.12 — generic catch-all
-}
+/// Routes an MQTT `(action, payload)` pair to a device's command channel.
+pub type Dispatcher = Box<dyn Fn(&str, &[u8]) -> Result<(), LightspeedError> + Send + Sync>;
 
 /// Unified lifecycle trait for all Lightspeed-compliant devices.
 ///
@@ -24,6 +24,26 @@ pub trait LightspeedDevice: Send + 'static {
     fn name(&self) -> &str;
     fn dev_type(&self) -> DeviceType;
 
+    /// Hardware model, e.g. `"QHY268M"`. Published in `DeviceStatus`.
+    fn model(&self) -> Option<&str> {
+        None
+    }
+
+    /// Firmware version reported by the hardware.
+    fn firmware_version(&self) -> Option<&str> {
+        None
+    }
+
+    /// Hardware serial number.
+    fn serial_number(&self) -> Option<&str> {
+        None
+    }
+
+    /// Serial or USB port the device is attached to, e.g. `"/dev/ttyUSB0"`.
+    fn port(&self) -> Option<&str> {
+        None
+    }
+
     /// Use this namespace to build reproducible UUIDs based on some device
     /// attributes e.g. the device internal id.
     fn uuid_namespace(&self) -> Uuid {
@@ -41,7 +61,7 @@ pub trait LightspeedDevice: Send + 'static {
     ///
     /// Call this **before** moving the device into its thread. The closure captures
     /// the internal `SyncSender` and is safe to call from any thread.
-    fn dispatcher(&self) -> Box<dyn Fn(&str, &[u8]) -> Result<(), LightspeedError> + Send + Sync>;
+    fn dispatcher(&self) -> Dispatcher;
 
     /// Called by the device thread on each tick interval.
     ///
@@ -52,6 +72,301 @@ pub trait LightspeedDevice: Send + 'static {
     /// 4. Push current state: `state_tx.try_send((self.id(), self.state_json()))`
     fn tick(&mut self, state_tx: &SyncSender<(Uuid, String)>);
 
+    /// Presence published on `devices/{uuid}/status`. Checked after every
+    /// tick; the runner republishes the retained status when it changes.
+    /// Default: `Online`.
+    fn presence(&self) -> DevicePresence {
+        DevicePresence::default()
+    }
+
+    /// Hand a finished frame to the runner. Checked after every tick; the
+    /// runner chunks it and publishes it on `devices/{uuid}/frame`.
+    /// Default: `None`.
+    fn take_frame(&mut self) -> Option<(FrameHeader, Vec<u8>)> {
+        None
+    }
+
+    /// Hand a preview payload to the runner, e.g. an encoded
+    /// `preview::Preview` of the latest stream frame. Called after a tick
+    /// at most every `RunnerConfig::preview_interval_ms`; the runner
+    /// publishes it unchanged on `devices/{uuid}/preview`. Default: `None`.
+    fn take_preview(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Whether an operation that should not be interrupted is in progress,
+    /// e.g. an exposure or a readout. During shutdown the runner keeps
+    /// ticking a busy device until it goes idle or the deadline passes.
+    fn is_busy(&self) -> bool {
+        false
+    }
+
+    /// Abort the in-progress operation. Called during shutdown if the device
+    /// is still busy when the deadline passes.
+    fn abort(&mut self) {}
+
     /// Clean shutdown. Called by the device thread before it exits.
     fn close(&mut self);
 }
+
+/// Command channel behind the generic device adapters (filter wheel, mount,
+/// focuser). The dispatcher parses MQTT payloads into commands and queues
+/// them; [`CommandQueue::drain`] applies them on the device thread and keeps
+/// the last failure for the published state.
+///
+/// A stop command set with [`CommandQueue::with_stop`] never waits in the
+/// queue: the dispatcher only raises a flag, and the next drain applies the
+/// stop first and discards the commands queued before it.
+pub struct CommandQueue<C> {
+    tx: SyncSender<(u64, C)>,
+    rx: Receiver<(u64, C)>,
+    stop: Option<C>,
+    stops: Arc<AtomicU64>,
+    stops_seen: u64,
+    last_error: Option<ErrorEnvelope>,
+}
+
+impl<C> CommandQueue<C>
+where
+    C: Clone + PartialEq + fmt::Debug + Send + Sync + 'static,
+{
+    /// Commands the dispatcher accepts before replying `QueueFull`.
+    pub const CAPACITY: usize = 16;
+
+    pub fn new() -> Self {
+        let (tx, rx) = sync_channel(Self::CAPACITY);
+        Self {
+            tx,
+            rx,
+            stop: None,
+            stops: Arc::new(AtomicU64::new(0)),
+            stops_seen: 0,
+            last_error: None,
+        }
+    }
+
+    /// Queue whose `stop` command bypasses the queue.
+    pub fn with_stop(stop: C) -> Self {
+        Self {
+            stop: Some(stop),
+            ..Self::new()
+        }
+    }
+
+    /// Dispatcher that parses `(action, payload)` with `parse` and queues
+    /// the command.
+    pub fn dispatcher(&self, parse: fn(&str, &[u8]) -> Result<C, LightspeedError>) -> Dispatcher {
+        let tx = self.tx.clone();
+        let stop = self.stop.clone();
+        let stops = self.stops.clone();
+        Box::new(move |action, payload| {
+            let command = parse(action, payload)?;
+            if stop.as_ref() == Some(&command) {
+                stops.fetch_add(1, Ordering::AcqRel);
+                return Ok(());
+            }
+            let generation = stops.load(Ordering::Acquire);
+            tx.try_send((generation, command))
+                .map_err(|_| LightspeedError::QueueFull)
+        })
+    }
+
+    /// Apply a pending stop, then every queued command, to `device` with
+    /// `apply`. `queue` picks this queue out of the device.
+    pub fn drain<D: LightspeedDevice>(
+        device: &mut D,
+        queue: fn(&mut D) -> &mut Self,
+        apply: fn(&mut D, C) -> Result<(), LightspeedError>,
+    ) {
+        let q = queue(device);
+        let stops = q.stops.load(Ordering::Acquire);
+        let stop = q.stop.clone().filter(|_| stops != q.stops_seen);
+        q.stops_seen = stops;
+        if let Some(stop) = stop {
+            let result = apply(device, stop.clone());
+            Self::record(device, queue, &stop, result);
+        }
+        while let Ok((generation, command)) = queue(device).rx.try_recv() {
+            if generation < stops {
+                log::debug!("{}: {command:?} dropped by stop", device.name());
+                continue;
+            }
+            let result = apply(device, command.clone());
+            Self::record(device, queue, &command, result);
+        }
+    }
+
+    fn record<D: LightspeedDevice>(
+        device: &mut D,
+        queue: fn(&mut D) -> &mut Self,
+        command: &C,
+        result: Result<(), LightspeedError>,
+    ) {
+        match result {
+            Ok(()) => queue(device).last_error = None,
+            Err(e) => {
+                let name = device.name().to_owned();
+                queue(device).fail(&name, &format!("{command:?}"), &e);
+            }
+        }
+    }
+
+    /// Log a failure of `what` and keep it as the last error.
+    pub fn fail(&mut self, device: &str, what: &str, error: &LightspeedError) {
+        log::warn!("{device}: {what} failed: {error:?}");
+        self.last_error = Some(error.into());
+    }
+
+    /// Why the last command failed. Cleared when a later command succeeds.
+    pub fn last_error(&self) -> Option<&ErrorEnvelope> {
+        self.last_error.as_ref()
+    }
+}
+
+impl<C> Default for CommandQueue<C>
+where
+    C: Clone + PartialEq + fmt::Debug + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ErrorCode;
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Cmd {
+        Go(u32),
+        Stop,
+    }
+
+    fn parse(action: &str, payload: &[u8]) -> Result<Cmd, LightspeedError> {
+        match action {
+            "go" => Ok(Cmd::Go(
+                serde_json::from_slice(payload).map_err(|_| LightspeedError::ParseError)?,
+            )),
+            "stop" => Ok(Cmd::Stop),
+            _ => Err(LightspeedError::UnknownCommand),
+        }
+    }
+
+    struct Fake {
+        commands: CommandQueue<Cmd>,
+        applied: Vec<Cmd>,
+    }
+
+    impl Fake {
+        fn new(commands: CommandQueue<Cmd>) -> Self {
+            Self {
+                commands,
+                applied: Vec::new(),
+            }
+        }
+
+        fn apply(&mut self, command: Cmd) -> Result<(), LightspeedError> {
+            if command == Cmd::Go(0) {
+                return Err(LightspeedError::NotSupported);
+            }
+            self.applied.push(command);
+            Ok(())
+        }
+    }
+
+    impl LightspeedDevice for Fake {
+        fn id(&self) -> Uuid {
+            Uuid::nil()
+        }
+
+        fn name(&self) -> &str {
+            "fake"
+        }
+
+        fn dev_type(&self) -> DeviceType {
+            DeviceType::AuxBox
+        }
+
+        fn command_topics(&self) -> &[&str] {
+            &["go", "stop"]
+        }
+
+        fn state_json(&self) -> String {
+            String::new()
+        }
+
+        fn dispatcher(&self) -> Dispatcher {
+            self.commands.dispatcher(parse)
+        }
+
+        fn tick(&mut self, _state_tx: &SyncSender<(Uuid, String)>) {
+            CommandQueue::drain(self, |d| &mut d.commands, Self::apply);
+        }
+
+        fn close(&mut self) {}
+    }
+
+    fn tick(device: &mut Fake) {
+        let (state_tx, _state_rx) = sync_channel(1);
+        device.tick(&state_tx);
+    }
+
+    #[test]
+    fn applies_in_order_and_tracks_last_error() {
+        let mut device = Fake::new(CommandQueue::new());
+        let dispatch = device.dispatcher();
+        assert!(matches!(
+            dispatch("go", b"x"),
+            Err(LightspeedError::ParseError)
+        ));
+        assert!(matches!(
+            dispatch("jump", b""),
+            Err(LightspeedError::UnknownCommand)
+        ));
+
+        dispatch("go", b"1").unwrap();
+        dispatch("go", b"0").unwrap();
+        tick(&mut device);
+        assert_eq!(device.applied, [Cmd::Go(1)]);
+        assert_eq!(
+            device.commands.last_error().unwrap().code,
+            ErrorCode::Validation
+        );
+
+        dispatch("go", b"2").unwrap();
+        tick(&mut device);
+        assert!(device.commands.last_error().is_none());
+    }
+
+    #[test]
+    fn full_queue_is_reported() {
+        let device = Fake::new(CommandQueue::new());
+        let dispatch = device.dispatcher();
+        for _ in 0..CommandQueue::<Cmd>::CAPACITY {
+            dispatch("go", b"1").unwrap();
+        }
+        assert!(matches!(
+            dispatch("go", b"1"),
+            Err(LightspeedError::QueueFull)
+        ));
+    }
+
+    #[test]
+    fn stop_bypasses_a_full_queue_and_drops_earlier_commands() {
+        let mut device = Fake::new(CommandQueue::with_stop(Cmd::Stop));
+        let dispatch = device.dispatcher();
+        for _ in 0..CommandQueue::<Cmd>::CAPACITY {
+            dispatch("go", b"1").unwrap();
+        }
+        dispatch("stop", b"").unwrap();
+        tick(&mut device);
+        assert_eq!(device.applied, [Cmd::Stop]);
+
+        dispatch("go", b"2").unwrap();
+        dispatch("stop", b"").unwrap();
+        dispatch("go", b"3").unwrap();
+        tick(&mut device);
+        assert_eq!(device.applied, [Cmd::Stop, Cmd::Stop, Cmd::Go(3)]);
+    }
+}
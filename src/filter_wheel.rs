@@ -1,6 +1,449 @@
+//! Filter wheel trait ([`DeviceType::FilterWheel`](crate::DeviceType)) and a
+//! generic [`LightspeedDevice`] adapter.
+//!
+//! Slots are 0-based. Moving is asynchronous: [`FilterWheel::set_slot`]
+//! starts the move and [`FilterWheel::is_moving`] reports when it is over.
+//! Each slot carries a [`Filter`] with a name and the focuser offset, in
+//! steps, to apply after switching to it.
+
+use std::io;
+use std::sync::mpsc::SyncSender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::device::{CommandQueue, Dispatcher, LightspeedDevice};
+use crate::properties::PropertyErrorType;
+use crate::protocol::ErrorEnvelope;
+use crate::{DeviceType, LightspeedError};
+
+/// Command topics handled by [`FilterWheelDevice`].
+pub const FILTER_WHEEL_TOPICS: &[&str] = &[
+    "set_slot",
+    "set_filter",
+    "set_filters",
+    "set_unidirectional",
+];
+
+/// Filter in one wheel slot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Filter {
+    pub name: String,
+    /// Focuser steps relative to the reference filter.
+    #[serde(default)]
+    pub focus_offset: i32,
+}
+
+impl Filter {
+    pub fn new(name: impl Into<String>, focus_offset: i32) -> Self {
+        Self {
+            name: name.into(),
+            focus_offset,
+        }
+    }
+}
+
+/// `"Filter 1"`, `"Filter 2"`, ... with no offsets.
+pub fn default_filters(slots: usize) -> Vec<Filter> {
+    (1..=slots)
+        .map(|i| Filter::new(format!("Filter {i}"), 0))
+        .collect()
+}
+
 pub trait FilterWheel {
-    fn actual_slot(&self) -> i32;
-    fn set_slot(&self, slot: i32);
-    fn set_unidirection(&self, flag: bool);
-    fn is_unidirectional(&self) -> bool;
+    fn slot_count(&self) -> usize;
+
+    /// Slot in the light path. None while moving or when unknown.
+    fn actual_slot(&self) -> Option<usize>;
+
+    /// Start moving to `slot`. Returns once the move has been accepted.
+    fn set_slot(&mut self, slot: usize) -> Result<(), LightspeedError>;
+
+    fn is_moving(&self) -> bool;
+
+    /// One [`Filter`] per slot.
+    fn filters(&self) -> Vec<Filter> {
+        default_filters(self.slot_count())
+    }
+
+    /// Replace the filter names and offsets, one per slot.
+    fn set_filters(&mut self, _filters: Vec<Filter>) -> Result<(), LightspeedError> {
+        Err(LightspeedError::NotSupported)
+    }
+
+    fn is_unidirectional(&self) -> bool {
+        false
+    }
+
+    fn set_unidirectional(&mut self, _flag: bool) -> Result<(), LightspeedError> {
+        Err(LightspeedError::NotSupported)
+    }
+
+    /// Filter in the light path. None while moving.
+    fn current_filter(&self) -> Option<Filter> {
+        let slot = self.actual_slot()?;
+        self.filters().into_iter().nth(slot)
+    }
+
+    /// Slot holding the filter called `name`, ignoring case.
+    fn slot_of(&self, name: &str) -> Option<usize> {
+        self.filters()
+            .iter()
+            .position(|f| f.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Start moving to the filter called `name`.
+    fn set_filter(&mut self, name: &str) -> Result<(), LightspeedError> {
+        let slot = self.slot_of(name).ok_or(PropertyErrorType::InvalidChoice)?;
+        self.set_slot(slot)
+    }
+
+    /// Focuser steps to move by when switching from slot `from` to `to`.
+    fn focus_offset_change(&self, from: usize, to: usize) -> Option<i32> {
+        let filters = self.filters();
+        Some(filters.get(to)?.focus_offset - filters.get(from)?.focus_offset)
+    }
+
+    /// Poll [`FilterWheel::is_moving`] every `poll_interval` until the move
+    /// is over. Fails with a `TimedOut` I/O error after `timeout`.
+    fn wait_until_stopped(
+        &self,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> Result<(), LightspeedError> {
+        let deadline = Instant::now() + timeout;
+        while self.is_moving() {
+            if Instant::now() >= deadline {
+                return Err(io::Error::from(io::ErrorKind::TimedOut).into());
+            }
+            thread::sleep(poll_interval);
+        }
+        Ok(())
+    }
+}
+
+/// Check a slot index against the wheel size.
+pub fn validate_slot(slot_count: usize, slot: usize) -> Result<(), LightspeedError> {
+    if slot >= slot_count {
+        return Err(PropertyErrorType::ValueOutOfRange.into());
+    }
+    Ok(())
+}
+
+/// Check a filter list against the wheel size.
+pub fn validate_filters(slot_count: usize, filters: &[Filter]) -> Result<(), LightspeedError> {
+    if filters.len() != slot_count || filters.iter().any(|f| f.name.trim().is_empty()) {
+        return Err(PropertyErrorType::InvalidValue.into());
+    }
+    Ok(())
+}
+
+/// Commands accepted by [`FilterWheelDevice`], one per topic in
+/// [`FILTER_WHEEL_TOPICS`]. Payloads are bare JSON values, e.g. `2`,
+/// `"Ha"`, `true` or a list of [`Filter`]s.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterWheelCommand {
+    SetSlot(usize),
+    SetFilter(String),
+    SetFilters(Vec<Filter>),
+    SetUnidirectional(bool),
+}
+
+impl FilterWheelCommand {
+    pub fn parse(action: &str, payload: &[u8]) -> Result<Self, LightspeedError> {
+        let parse_err = |_| LightspeedError::ParseError;
+        Ok(match action {
+            "set_slot" => {
+                FilterWheelCommand::SetSlot(serde_json::from_slice(payload).map_err(parse_err)?)
+            }
+            "set_filter" => {
+                FilterWheelCommand::SetFilter(serde_json::from_slice(payload).map_err(parse_err)?)
+            }
+            "set_filters" => {
+                FilterWheelCommand::SetFilters(serde_json::from_slice(payload).map_err(parse_err)?)
+            }
+            "set_unidirectional" => FilterWheelCommand::SetUnidirectional(
+                serde_json::from_slice(payload).map_err(parse_err)?,
+            ),
+            _ => return Err(LightspeedError::UnknownCommand),
+        })
+    }
+}
+
+/// State published by [`FilterWheelDevice`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FilterWheelState {
+    pub slot: Option<usize>,
+    pub filter: Option<String>,
+    pub focus_offset: Option<i32>,
+    pub moving: bool,
+    /// Slot of the move in progress.
+    pub target: Option<usize>,
+    pub slot_count: usize,
+    pub filters: Vec<Filter>,
+    pub unidirectional: bool,
+    /// See [`CommandQueue::last_error`].
+    pub last_error: Option<ErrorEnvelope>,
+}
+
+/// Runs any [`FilterWheel`] as a [`LightspeedDevice`], tracking the slot
+/// of the move in progress.
+pub struct FilterWheelDevice<F> {
+    id: Uuid,
+    name: String,
+    wheel: F,
+    commands: CommandQueue<FilterWheelCommand>,
+    target: Option<usize>,
+}
+
+impl<F: FilterWheel> FilterWheelDevice<F> {
+    pub fn new(id: Uuid, name: impl Into<String>, wheel: F) -> Self {
+        Self {
+            id,
+            name: name.into(),
+            wheel,
+            commands: CommandQueue::new(),
+            target: None,
+        }
+    }
+
+    pub fn wheel(&self) -> &F {
+        &self.wheel
+    }
+
+    pub fn wheel_mut(&mut self) -> &mut F {
+        &mut self.wheel
+    }
+
+    pub fn state(&self) -> FilterWheelState {
+        let filter = self.wheel.current_filter();
+        FilterWheelState {
+            slot: self.wheel.actual_slot(),
+            focus_offset: filter.as_ref().map(|f| f.focus_offset),
+            filter: filter.map(|f| f.name),
+            moving: self.wheel.is_moving(),
+            target: self.target,
+            slot_count: self.wheel.slot_count(),
+            filters: self.wheel.filters(),
+            unidirectional: self.wheel.is_unidirectional(),
+            last_error: self.commands.last_error().cloned(),
+        }
+    }
+
+    fn apply(&mut self, command: FilterWheelCommand) -> Result<(), LightspeedError> {
+        match command {
+            FilterWheelCommand::SetSlot(slot) => {
+                validate_slot(self.wheel.slot_count(), slot)?;
+                self.wheel.set_slot(slot)?;
+                self.target = Some(slot);
+            }
+            FilterWheelCommand::SetFilter(name) => {
+                let slot = self
+                    .wheel
+                    .slot_of(&name)
+                    .ok_or(PropertyErrorType::InvalidChoice)?;
+                self.wheel.set_slot(slot)?;
+                self.target = Some(slot);
+            }
+            FilterWheelCommand::SetFilters(filters) => {
+                validate_filters(self.wheel.slot_count(), &filters)?;
+                self.wheel.set_filters(filters)?;
+            }
+            FilterWheelCommand::SetUnidirectional(flag) => self.wheel.set_unidirectional(flag)?,
+        }
+        Ok(())
+    }
+}
+
+impl<F: FilterWheel + Send + 'static> LightspeedDevice for FilterWheelDevice<F> {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn dev_type(&self) -> DeviceType {
+        DeviceType::FilterWheel
+    }
+
+    fn command_topics(&self) -> &[&str] {
+        FILTER_WHEEL_TOPICS
+    }
+
+    fn state_json(&self) -> String {
+        serde_json::to_string(&self.state()).unwrap_or_default()
+    }
+
+    fn dispatcher(&self) -> Dispatcher {
+        self.commands.dispatcher(FilterWheelCommand::parse)
+    }
+
+    fn tick(&mut self, state_tx: &SyncSender<(Uuid, String)>) {
+        CommandQueue::drain(self, |d| &mut d.commands, Self::apply);
+        if self.target.is_some() && !self.wheel.is_moving() {
+            self.target = None;
+        }
+        let _ = state_tx.try_send((self.id, self.state_json()));
+    }
+
+    fn is_busy(&self) -> bool {
+        self.wheel.is_moving()
+    }
+
+    fn close(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ErrorCode;
+    use std::cell::Cell;
+    use std::sync::mpsc::sync_channel;
+
+    /// Takes `moves` polls of `is_moving` to reach its target.
+    struct FakeWheel {
+        slot: usize,
+        target: usize,
+        remaining: Cell<u32>,
+        moves: u32,
+        filters: Vec<Filter>,
+    }
+
+    impl FakeWheel {
+        fn new(moves: u32) -> Self {
+            Self {
+                slot: 0,
+                target: 0,
+                remaining: Cell::new(0),
+                moves,
+                filters: vec![
+                    Filter::new("L", 0),
+                    Filter::new("Ha", 40),
+                    Filter::new("OIII", -15),
+                ],
+            }
+        }
+    }
+
+    impl FilterWheel for FakeWheel {
+        fn slot_count(&self) -> usize {
+            self.filters.len()
+        }
+
+        fn actual_slot(&self) -> Option<usize> {
+            (!self.is_moving()).then_some(self.target)
+        }
+
+        fn set_slot(&mut self, slot: usize) -> Result<(), LightspeedError> {
+            validate_slot(self.slot_count(), slot)?;
+            self.slot = slot;
+            self.target = slot;
+            self.remaining.set(self.moves);
+            Ok(())
+        }
+
+        fn is_moving(&self) -> bool {
+            let left = self.remaining.get();
+            self.remaining.set(left.saturating_sub(1));
+            left > 0
+        }
+
+        fn filters(&self) -> Vec<Filter> {
+            self.filters.clone()
+        }
+
+        fn set_filters(&mut self, filters: Vec<Filter>) -> Result<(), LightspeedError> {
+            self.filters = filters;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trait_helpers() {
+        let mut wheel = FakeWheel::new(2);
+        assert_eq!(wheel.slot_of("ha"), Some(1));
+        assert_eq!(wheel.focus_offset_change(1, 2), Some(-55));
+        assert_eq!(wheel.focus_offset_change(0, 5), None);
+        assert!(matches!(
+            wheel.set_filter("SII"),
+            Err(LightspeedError::PropertyError(
+                PropertyErrorType::InvalidChoice
+            ))
+        ));
+        assert!(matches!(
+            wheel.set_unidirectional(true),
+            Err(LightspeedError::NotSupported)
+        ));
+
+        wheel.set_filter("OIII").unwrap();
+        wheel
+            .wait_until_stopped(Duration::from_secs(1), Duration::ZERO)
+            .unwrap();
+        assert_eq!(wheel.current_filter(), Some(Filter::new("OIII", -15)));
+
+        wheel.set_slot(0).unwrap();
+        assert!(matches!(
+            wheel.wait_until_stopped(Duration::ZERO, Duration::ZERO),
+            Err(LightspeedError::IoError(e)) if e.kind() == io::ErrorKind::TimedOut
+        ));
+        assert_eq!(default_filters(2)[1].name, "Filter 2");
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            FilterWheelCommand::parse("set_slot", b"2").unwrap(),
+            FilterWheelCommand::SetSlot(2)
+        );
+        assert_eq!(
+            FilterWheelCommand::parse("set_filters", br#"[{"name": "L"}]"#).unwrap(),
+            FilterWheelCommand::SetFilters(vec![Filter::new("L", 0)])
+        );
+        assert!(matches!(
+            FilterWheelCommand::parse("set_slot", b"-1"),
+            Err(LightspeedError::ParseError)
+        ));
+        assert!(matches!(
+            FilterWheelCommand::parse("home", b""),
+            Err(LightspeedError::UnknownCommand)
+        ));
+    }
+
+    #[test]
+    fn device_applies_commands_and_tracks_movement() {
+        let mut device = FilterWheelDevice::new(Uuid::nil(), "wheel", FakeWheel::new(3));
+        let dispatch = device.dispatcher();
+        let (state_tx, state_rx) = sync_channel(8);
+
+        dispatch("set_filter", br#""Ha""#).unwrap();
+        device.tick(&state_tx);
+        let (_, json) = state_rx.try_recv().unwrap();
+        let state: FilterWheelState = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            (state.target, state.slot, state.last_error),
+            (Some(1), None, None)
+        );
+
+        while device.is_busy() {}
+        device.tick(&state_tx);
+        let state = device.state();
+        assert_eq!(state.target, None);
+        assert_eq!(
+            (state.slot, state.filter.as_deref(), state.focus_offset),
+            (Some(1), Some("Ha"), Some(40))
+        );
+
+        dispatch("set_slot", b"7").unwrap();
+        dispatch("set_filters", br#"[{"name": "L"}]"#).unwrap();
+        device.tick(&state_tx);
+        assert_eq!(
+            device.state().last_error.unwrap().code,
+            ErrorCode::Validation
+        );
+        assert_eq!(device.wheel().slot, 1);
+    }
 }
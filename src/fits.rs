@@ -0,0 +1,1433 @@
+//! FITS reader and writer for captured frames, no C library involved.
+//!
+//! Files have a single primary HDU. [`FitsHeader::from_frame`] maps
+//! [`FrameHeader`] metadata onto the usual keywords (`DATE-OBS`, `EXPTIME`,
+//! `XBINNING`, `CCD-TEMP`, `FILTER`, `BAYERPAT`, ...); callers add their own,
+//! e.g. `INSTRUME`, before calling [`write_frame`].
+//!
+//! Unsigned 16-bit data is stored as `BITPIX = 16` with `BZERO = 32768`.
+//! Rows are written top-down (`ROWORDER = 'TOP-DOWN'`), interleaved RGB as
+//! three planes (`NAXIS3 = 3`). Long strings use the `CONTINUE` convention and
+//! keywords longer than 8 characters are written as `HIERARCH` cards.
+//!
+//! [`read_image`] parses any primary HDU (every `BITPIX`, `BZERO`/`BSCALE`
+//! applied, `CONTINUE` and `HIERARCH` cards understood); [`read_frame`] also
+//! turns it back into a [`FrameHeader`] and frame payload.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use uuid::Uuid;
+
+use crate::frame::{
+    decompress_payload, unpack_samples, BayerPattern, FrameError, FrameHeader, FrameType,
+    PixelFormat, MAX_PAYLOAD_LEN,
+};
+
+/// FITS files are written in blocks of this many bytes.
+pub const BLOCK_LEN: usize = 2880;
+/// Every header card is this many bytes.
+pub const CARD_LEN: usize = 80;
+
+/// Keywords written by [`write_image`] itself; user cards with these names
+/// are skipped.
+const STRUCTURAL: [&str; 7] = [
+    "SIMPLE", "BITPIX", "NAXIS", "EXTEND", "BZERO", "BSCALE", "END",
+];
+
+/// Headers longer than this many blocks are rejected when reading.
+const MAX_HEADER_BLOCKS: usize = 1024;
+
+/// `HIERARCH` keywords carrying lightspeed ids.
+const CORRELATION_ID_KEY: &str = "LIGHTSPEED CORRELATION_ID";
+const DRIVER_ID_KEY: &str = "LIGHTSPEED DRIVER_ID";
+
+#[derive(Debug)]
+pub enum FitsError {
+    /// The source frame could not be read.
+    Frame(FrameError),
+    /// `dims` does not multiply out to the number of samples.
+    Dimensions {
+        expected: usize,
+        actual: usize,
+    },
+    /// A card that cannot be written, e.g. a non-ASCII or NaN value.
+    InvalidCard(String),
+    /// The header is malformed or missing a mandatory keyword.
+    Parse(String),
+    /// The file ended inside the header or data.
+    Truncated,
+    /// Valid FITS that has no frame equivalent, e.g. floating-point data.
+    Unsupported(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for FitsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FitsError::Frame(e) => write!(f, "fits source: {e}"),
+            FitsError::Dimensions { expected, actual } => {
+                write!(
+                    f,
+                    "fits image has {actual} samples, dimensions need {expected}"
+                )
+            }
+            FitsError::InvalidCard(keyword) => write!(f, "invalid fits card: {keyword}"),
+            FitsError::Parse(e) => write!(f, "invalid fits header: {e}"),
+            FitsError::Truncated => f.write_str("truncated fits file"),
+            FitsError::Unsupported(e) => write!(f, "unsupported fits image: {e}"),
+            FitsError::Io(e) => write!(f, "fits i/o error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for FitsError {}
+
+impl From<FrameError> for FitsError {
+    fn from(error: FrameError) -> Self {
+        FitsError::Frame(error)
+    }
+}
+
+impl From<io::Error> for FitsError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => FitsError::Truncated,
+            _ => FitsError::Io(error),
+        }
+    }
+}
+
+/// Value of a header card.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Logical(bool),
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+impl Value {
+    /// Integers and reals as `f64`.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Integer(v) => Some(*v as f64),
+            Value::Real(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Integers, and reals with no fractional part.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Integer(v) => Some(*v),
+            Value::Real(v) if v.fract() == 0.0 => Some(*v as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Text(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Self {
+        Value::Logical(v)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Self {
+        Value::Integer(v)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(v: i32) -> Self {
+        Value::Integer(v as i64)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(v: u32) -> Self {
+        Value::Integer(v as i64)
+    }
+}
+
+impl From<u16> for Value {
+    fn from(v: u16) -> Self {
+        Value::Integer(v as i64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Value::Real(v)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Self {
+        Value::Text(v.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(v: String) -> Self {
+        Value::Text(v)
+    }
+}
+
+/// One logical header card. `COMMENT` and `HISTORY` cards have no value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Card {
+    pub keyword: String,
+    pub value: Option<Value>,
+    pub comment: Option<String>,
+}
+
+/// Ordered list of header cards, excluding the structural ones.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FitsHeader {
+    cards: Vec<Card>,
+}
+
+impl FitsHeader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Science keywords for `header`. Absent metadata produces no card.
+    pub fn from_frame(header: &FrameHeader) -> Self {
+        let mut fits = Self::new();
+        if header.timestamp_ns != 0 {
+            fits.set(
+                "DATE-OBS",
+                format_date(header.timestamp_ns),
+                "UTC start of exposure",
+            );
+        }
+        if let Some(exposure) = header.exposure_secs {
+            fits.set("EXPTIME", exposure, "[s] exposure duration");
+        }
+        if let Some(gain) = header.gain {
+            fits.set("GAIN", gain, "sensor gain");
+        }
+        if let Some(offset) = header.offset {
+            fits.set("OFFSET", offset, "sensor offset");
+        }
+        if let Some(bin) = header.bin_x {
+            fits.set("XBINNING", bin, "binning factor along x");
+        }
+        if let Some(bin) = header.bin_y {
+            fits.set("YBINNING", bin, "binning factor along y");
+        }
+        if let Some(x) = header.roi_x {
+            fits.set("XORGSUBF", x, "subframe x origin, unbinned pixels");
+        }
+        if let Some(y) = header.roi_y {
+            fits.set("YORGSUBF", y, "subframe y origin, unbinned pixels");
+        }
+        if let Some(temp) = header.sensor_temp_c {
+            fits.set("CCD-TEMP", temp, "[C] sensor temperature");
+        }
+        if let Some(frame_type) = header.frame_type {
+            fits.set("IMAGETYP", image_type(frame_type), "type of exposure");
+        }
+        if let Some(filter) = &header.filter {
+            fits.set("FILTER", filter.as_str(), "filter in the light path");
+        }
+        if let Some(size) = header.pixel_size_x_um {
+            let bin = header.bin_x.unwrap_or(1) as f64;
+            fits.set("XPIXSZ", size * bin, "[um] pixel width, binning included");
+        }
+        if let Some(size) = header.pixel_size_y_um {
+            let bin = header.bin_y.unwrap_or(1) as f64;
+            fits.set("YPIXSZ", size * bin, "[um] pixel height, binning included");
+        }
+        if let Some(pattern) = header.bayer {
+            fits.set("BAYERPAT", pattern.as_str(), "colour filter array layout");
+            fits.set("XBAYROFF", 0, "bayer x offset");
+            fits.set("YBAYROFF", 0, "bayer y offset");
+        }
+        fits.set("ROWORDER", "TOP-DOWN", "first row is the top of the image");
+        if !header.correlation_id.is_nil() {
+            fits.set(CORRELATION_ID_KEY, header.correlation_id.to_string(), "");
+        }
+        if !header.driver_id.is_nil() {
+            fits.set(DRIVER_ID_KEY, header.driver_id.to_string(), "");
+        }
+        fits
+    }
+
+    /// Set `keyword`, replacing an existing card of the same name in place.
+    /// An empty `comment` writes none.
+    pub fn set(&mut self, keyword: &str, value: impl Into<Value>, comment: &str) {
+        let card = Card {
+            keyword: keyword.to_string(),
+            value: Some(value.into()),
+            comment: (!comment.is_empty()).then(|| comment.to_string()),
+        };
+        match self.cards.iter_mut().find(|c| c.keyword == keyword) {
+            Some(existing) => *existing = card,
+            None => self.cards.push(card),
+        }
+    }
+
+    /// Append a `COMMENT` card.
+    pub fn comment(&mut self, text: &str) {
+        self.push_commentary("COMMENT", text);
+    }
+
+    /// Append a `HISTORY` card.
+    pub fn history(&mut self, text: &str) {
+        self.push_commentary("HISTORY", text);
+    }
+
+    fn push_commentary(&mut self, keyword: &str, text: &str) {
+        self.cards.push(Card {
+            keyword: keyword.to_string(),
+            value: None,
+            comment: Some(text.to_string()),
+        });
+    }
+
+    pub fn get(&self, keyword: &str) -> Option<&Value> {
+        self.cards
+            .iter()
+            .find(|c| c.keyword == keyword)
+            .and_then(|c| c.value.as_ref())
+    }
+
+    pub fn cards(&self) -> &[Card] {
+        &self.cards
+    }
+}
+
+/// `IMAGETYP` value in the MaxIm DL / N.I.N.A. convention.
+fn image_type(frame_type: FrameType) -> &'static str {
+    match frame_type {
+        FrameType::Light => "Light Frame",
+        FrameType::Dark => "Dark Frame",
+        FrameType::Flat => "Flat Field",
+        FrameType::Bias => "Bias Frame",
+    }
+}
+
+/// Image samples, fastest axis first.
+#[derive(Debug, Clone, Copy)]
+pub enum ImageData<'a> {
+    /// `BITPIX = 8`.
+    U8(&'a [u8]),
+    /// `BITPIX = 16`, `BZERO = 32768`.
+    U16(&'a [u16]),
+    /// `BITPIX = 32`.
+    I32(&'a [i32]),
+    /// `BITPIX = -32`.
+    F32(&'a [f32]),
+}
+
+impl ImageData<'_> {
+    pub fn bitpix(&self) -> i32 {
+        match self {
+            ImageData::U8(_) => 8,
+            ImageData::U16(_) => 16,
+            ImageData::I32(_) => 32,
+            ImageData::F32(_) => -32,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            ImageData::U8(d) => d.len(),
+            ImageData::U16(d) => d.len(),
+            ImageData::I32(d) => d.len(),
+            ImageData::F32(d) => d.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn to_be_bytes(self) -> Vec<u8> {
+        match self {
+            ImageData::U8(d) => d.to_vec(),
+            ImageData::U16(d) => d
+                .iter()
+                .flat_map(|&v| ((v ^ 0x8000) as i16).to_be_bytes())
+                .collect(),
+            ImageData::I32(d) => d.iter().flat_map(|v| v.to_be_bytes()).collect(),
+            ImageData::F32(d) => d.iter().flat_map(|v| v.to_be_bytes()).collect(),
+        }
+    }
+}
+
+/// True for keywords that describe the data layout (`BITPIX`, `NAXISn`, ...).
+pub(crate) fn is_structural(keyword: &str) -> bool {
+    STRUCTURAL.contains(&keyword.trim_end_matches(|ch: char| ch.is_ascii_digit()))
+}
+
+fn is_standard_keyword(keyword: &str) -> bool {
+    (1..=8).contains(&keyword.len())
+        && keyword
+            .bytes()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+}
+
+pub(crate) fn format_real(v: f64, keyword: &str) -> Result<String, FitsError> {
+    if !v.is_finite() {
+        return Err(FitsError::InvalidCard(keyword.to_string()));
+    }
+    // `{:?}` is the shortest exact representation; FITS wants an upper-case
+    // exponent and a decimal point in the mantissa.
+    let s = format!("{v:?}");
+    let (mantissa, exponent) = match s.split_once('e') {
+        Some((m, e)) => (m.to_string(), format!("E{e}")),
+        None => (s, String::new()),
+    };
+    let mantissa = if mantissa.contains('.') {
+        mantissa
+    } else {
+        format!("{mantissa}.0")
+    };
+    Ok(format!("{mantissa}{exponent}"))
+}
+
+/// Pad `card` to [`CARD_LEN`], appending ` / comment` if it fits.
+fn finish_card(mut card: String, comment: Option<&str>) -> String {
+    if let Some(comment) = comment {
+        let room = CARD_LEN.saturating_sub(card.len() + 3);
+        if room > 0 {
+            card.push_str(" / ");
+            card.extend(comment.chars().take(room));
+        }
+    }
+    format!("{card:<CARD_LEN$}")
+}
+
+/// Render one logical card as one or more 80-byte physical cards.
+fn format_card(card: &Card) -> Result<Vec<String>, FitsError> {
+    let invalid = || FitsError::InvalidCard(card.keyword.clone());
+    let ascii = |s: &str| s.bytes().all(|b| (0x20..0x7f).contains(&b));
+    if !ascii(&card.keyword) || !card.comment.as_deref().is_none_or(ascii) {
+        return Err(invalid());
+    }
+    let Some(value) = &card.value else {
+        if !is_standard_keyword(&card.keyword) {
+            return Err(invalid());
+        }
+        let text = card.comment.as_deref().unwrap_or("");
+        return Ok(vec![format!(
+            "{:<CARD_LEN$.CARD_LEN$}",
+            format!("{:<8}{text}", card.keyword)
+        )]);
+    };
+    let comment = card.comment.as_deref();
+
+    let fixed = match value {
+        Value::Logical(v) => format!("{:>20}", if *v { "T" } else { "F" }),
+        Value::Integer(v) => format!("{v:>20}"),
+        Value::Real(v) => format!("{:>20}", format_real(*v, &card.keyword)?),
+        Value::Text(text) => {
+            if !ascii(text) {
+                return Err(invalid());
+            }
+            let quoted = format!("'{:<8}'", text.replace('\'', "''"));
+            if !is_standard_keyword(&card.keyword) || quoted.len() <= CARD_LEN - 10 {
+                quoted
+            } else {
+                return Ok(format_long_string(&card.keyword, text, comment));
+            }
+        }
+    };
+
+    let line = if is_standard_keyword(&card.keyword) {
+        format!("{:<8}= {fixed}", card.keyword)
+    } else {
+        format!("HIERARCH {} = {}", card.keyword, fixed.trim_start())
+    };
+    if line.len() > CARD_LEN {
+        return Err(invalid());
+    }
+    Ok(vec![finish_card(line, comment)])
+}
+
+/// Split a long string over `CONTINUE` cards, each piece but the last ending
+/// in `&`. Doubled quotes are never split.
+fn format_long_string(keyword: &str, text: &str, comment: Option<&str>) -> Vec<String> {
+    // Room for the escaped text between the quotes, less one for the `&`.
+    const PIECE: usize = CARD_LEN - 10 - 3;
+    let mut pieces = vec![String::new()];
+    for c in text.chars() {
+        let escaped = if c == '\'' {
+            "''".to_string()
+        } else {
+            c.to_string()
+        };
+        if pieces.last().unwrap().len() + escaped.len() > PIECE {
+            pieces.push(String::new());
+        }
+        pieces.last_mut().unwrap().push_str(&escaped);
+    }
+    let last = pieces.len() - 1;
+    pieces
+        .iter()
+        .enumerate()
+        .map(|(i, piece)| {
+            let lead = if i == 0 {
+                format!("{keyword:<8}= ")
+            } else {
+                "CONTINUE  ".to_string()
+            };
+            if i == last {
+                finish_card(format!("{lead}'{piece}'"), comment)
+            } else {
+                finish_card(format!("{lead}'{piece}&'"), None)
+            }
+        })
+        .collect()
+}
+
+fn write_padded<W: Write>(w: &mut W, bytes: &[u8], pad: u8) -> io::Result<()> {
+    w.write_all(bytes)?;
+    let rem = bytes.len() % BLOCK_LEN;
+    if rem != 0 {
+        w.write_all(&vec![pad; BLOCK_LEN - rem])?;
+    }
+    Ok(())
+}
+
+/// Write a primary HDU holding `data` with axis lengths `dims` (fastest
+/// first) and the cards of `header`.
+pub fn write_image<W: Write>(
+    w: &mut W,
+    header: &FitsHeader,
+    dims: &[usize],
+    data: ImageData<'_>,
+) -> Result<(), FitsError> {
+    let expected = dims.iter().product::<usize>();
+    if expected != data.len() || dims.is_empty() {
+        return Err(FitsError::Dimensions {
+            expected,
+            actual: data.len(),
+        });
+    }
+
+    let mut structural = FitsHeader::new();
+    structural.set("SIMPLE", true, "conforms to FITS standard");
+    structural.set("BITPIX", data.bitpix(), "bits per data value");
+    structural.set("NAXIS", dims.len() as u32, "number of axes");
+    for (i, &len) in dims.iter().enumerate() {
+        structural.set(&format!("NAXIS{}", i + 1), len as i64, "");
+    }
+    structural.set("EXTEND", true, "");
+    if let ImageData::U16(_) = data {
+        structural.set("BZERO", 32768, "offset for unsigned 16-bit data");
+        structural.set("BSCALE", 1, "");
+    }
+
+    let user = header.cards.iter().filter(|c| !is_structural(&c.keyword));
+    let mut text = String::new();
+    for card in structural.cards.iter().chain(user) {
+        for line in format_card(card)? {
+            text.push_str(&line);
+        }
+    }
+    text.push_str(&format!("{:<CARD_LEN$}", "END"));
+    write_padded(w, text.as_bytes(), b' ')?;
+    write_padded(w, &data.to_be_bytes(), 0)?;
+    Ok(())
+}
+
+/// Write a captured frame as FITS: 8-bit data as `BITPIX = 8`, anything
+/// deeper as unsigned 16-bit. Compressed payloads are decompressed first.
+/// `fits` is usually [`FitsHeader::from_frame`] plus caller keywords.
+pub fn write_frame<W: Write>(
+    w: &mut W,
+    header: &FrameHeader,
+    data: &[u8],
+    fits: &FitsHeader,
+) -> Result<(), FitsError> {
+    let mut source = header.clone();
+    let decoded;
+    let data = if header.encoding.is_raw() {
+        data
+    } else {
+        decoded = decompress_payload(&mut source, data, MAX_PAYLOAD_LEN)?;
+        &decoded
+    };
+    let samples = unpack_samples(&source, data)?;
+    let (width, height) = (source.width as usize, source.height as usize);
+    let channels = source.channels() as usize;
+
+    // FITS stores colour as planes, the frame interleaves it.
+    let samples = if channels > 1 {
+        (0..channels)
+            .flat_map(|c| samples.iter().skip(c).step_by(channels).copied())
+            .collect()
+    } else {
+        samples
+    };
+    let dims: &[usize] = if channels > 1 {
+        &[width, height, channels]
+    } else {
+        &[width, height]
+    };
+
+    if source.significant_bits() <= 8 {
+        let bytes: Vec<u8> = samples.iter().map(|&s| s as u8).collect();
+        write_image(w, fits, dims, ImageData::U8(&bytes))
+    } else {
+        write_image(w, fits, dims, ImageData::U16(&samples))
+    }
+}
+
+/// Decoded primary HDU.
+#[derive(Debug, Clone)]
+pub struct FitsImage {
+    /// Every card in file order, structural ones included.
+    pub cards: FitsHeader,
+    /// `NAXISn` values, fastest axis first.
+    pub dims: Vec<usize>,
+    pub bitpix: i32,
+    /// Physical values, `BZERO` and `BSCALE` applied.
+    pub pixels: Pixels,
+}
+
+/// Physical pixel values. Integer data keeps an integer type when
+/// `BSCALE = 1` and `BZERO` is 0 or the unsigned offset (`BITPIX = 16` with
+/// `BZERO = 32768` gives `U16`, `BITPIX = 32` with `BZERO = 2^31` gives
+/// `U32`); other scalings produce `F32` for 8/16-bit data and `F64` above.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pixels {
+    U8(Vec<u8>),
+    I16(Vec<i16>),
+    U16(Vec<u16>),
+    I32(Vec<i32>),
+    U32(Vec<u32>),
+    I64(Vec<i64>),
+    F32(Vec<f32>),
+    F64(Vec<f64>),
+}
+
+impl Pixels {
+    pub fn len(&self) -> usize {
+        match self {
+            Pixels::U8(d) => d.len(),
+            Pixels::I16(d) => d.len(),
+            Pixels::U16(d) => d.len(),
+            Pixels::I32(d) => d.len(),
+            Pixels::U32(d) => d.len(),
+            Pixels::I64(d) => d.len(),
+            Pixels::F32(d) => d.len(),
+            Pixels::F64(d) => d.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn decode(bitpix: i32, raw: &[u8], bscale: f64, bzero: f64) -> Result<Pixels, FitsError> {
+        let identity = bscale == 1.0 && bzero == 0.0;
+        let scale = |v: f64| v * bscale + bzero;
+        let pixels = match bitpix {
+            8 if identity => Pixels::U8(raw.to_vec()),
+            8 => Pixels::F32(raw.iter().map(|&v| scale(v as f64) as f32).collect()),
+            16 => {
+                let values = raw
+                    .chunks_exact(2)
+                    .map(|b| i16::from_be_bytes([b[0], b[1]]));
+                if identity {
+                    Pixels::I16(values.collect())
+                } else if bscale == 1.0 && bzero == 32768.0 {
+                    Pixels::U16(values.map(|v| (v as u16) ^ 0x8000).collect())
+                } else {
+                    Pixels::F32(values.map(|v| scale(v as f64) as f32).collect())
+                }
+            }
+            32 => {
+                let values = raw
+                    .chunks_exact(4)
+                    .map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+                if identity {
+                    Pixels::I32(values.collect())
+                } else if bscale == 1.0 && bzero == 2_147_483_648.0 {
+                    Pixels::U32(values.map(|v| (v as u32) ^ 0x8000_0000).collect())
+                } else {
+                    Pixels::F64(values.map(|v| scale(v as f64)).collect())
+                }
+            }
+            64 => {
+                let values = raw
+                    .chunks_exact(8)
+                    .map(|b| i64::from_be_bytes(b.try_into().unwrap()));
+                if identity {
+                    Pixels::I64(values.collect())
+                } else {
+                    Pixels::F64(values.map(|v| scale(v as f64)).collect())
+                }
+            }
+            -32 => {
+                let values = raw
+                    .chunks_exact(4)
+                    .map(|b| f32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+                Pixels::F32(if identity {
+                    values.collect()
+                } else {
+                    values.map(|v| scale(v as f64) as f32).collect()
+                })
+            }
+            -64 => {
+                let values = raw
+                    .chunks_exact(8)
+                    .map(|b| f64::from_be_bytes(b.try_into().unwrap()));
+                Pixels::F64(if identity {
+                    values.collect()
+                } else {
+                    values.map(scale).collect()
+                })
+            }
+            _ => return Err(FitsError::Parse(format!("BITPIX = {bitpix}"))),
+        };
+        Ok(pixels)
+    }
+}
+
+impl FitsImage {
+    /// Rebuild the frame this file was written from: a [`FrameHeader`] from
+    /// the science keywords and a payload in frame layout. Only unsigned 8-
+    /// and 16-bit images with two axes, or three with `NAXIS3 = 3` (RGB),
+    /// have a frame equivalent. `ROWORDER = 'BOTTOM-UP'` images are flipped.
+    pub fn to_frame(&self) -> Result<(FrameHeader, Vec<u8>), FitsError> {
+        let (width, height, channels) = match self.dims[..] {
+            [w, h] | [w, h, 1] => (w, h, 1),
+            [w, h, 3] => (w, h, 3),
+            _ => return Err(FitsError::Unsupported(format!("axes {:?}", self.dims))),
+        };
+        let bayer = self
+            .cards
+            .get("BAYERPAT")
+            .and_then(Value::as_str)
+            .and_then(BayerPattern::parse)
+            .filter(|_| channels == 1);
+        let (bit_depth, pixel_format, samples): (u8, PixelFormat, Vec<u16>) = match &self.pixels {
+            Pixels::U8(d) => {
+                let format = match (channels, bayer) {
+                    (3, _) => PixelFormat::Rgb24,
+                    (_, Some(_)) => PixelFormat::Bayer8,
+                    _ => PixelFormat::Mono8,
+                };
+                (8, format, d.iter().map(|&v| v as u16).collect())
+            }
+            Pixels::U16(d) => {
+                let format = match (channels, bayer) {
+                    (3, _) => PixelFormat::Rgb48Le,
+                    (_, Some(_)) => PixelFormat::Bayer16,
+                    _ => PixelFormat::Mono16Le,
+                };
+                (16, format, d.clone())
+            }
+            _ => {
+                return Err(FitsError::Unsupported(format!(
+                    "BITPIX = {} with this scaling",
+                    self.bitpix
+                )))
+            }
+        };
+
+        let row_len = width * channels;
+        let bottom_up = self.cards.get("ROWORDER").and_then(Value::as_str) == Some("BOTTOM-UP");
+        let mut interleaved = vec![0u16; samples.len()];
+        for c in 0..channels {
+            let plane = &samples[c * width * height..][..width * height];
+            for (y, row) in plane.chunks_exact(width.max(1)).enumerate() {
+                let y = if bottom_up { height - 1 - y } else { y };
+                for (x, &v) in row.iter().enumerate() {
+                    interleaved[y * row_len + x * channels + c] = v;
+                }
+            }
+        }
+        let payload = if bit_depth == 8 {
+            interleaved.iter().map(|&v| v as u8).collect()
+        } else {
+            interleaved.iter().flat_map(|v| v.to_le_bytes()).collect()
+        };
+
+        let cards = &self.cards;
+        let real = |k: &str| cards.get(k).and_then(Value::as_f64);
+        let int = |k: &str| cards.get(k).and_then(Value::as_i64);
+        let text = |k: &str| cards.get(k).and_then(Value::as_str);
+        let uuid = |k: &str| {
+            text(k)
+                .and_then(|v| Uuid::parse_str(v).ok())
+                .unwrap_or_default()
+        };
+        let bin_x = int("XBINNING").and_then(|v| u16::try_from(v).ok());
+        let bin_y = int("YBINNING").and_then(|v| u16::try_from(v).ok());
+        let header = FrameHeader {
+            correlation_id: uuid(CORRELATION_ID_KEY),
+            driver_id: uuid(DRIVER_ID_KEY),
+            width: width as u32,
+            height: height as u32,
+            bit_depth,
+            bayer,
+            pixel_format: Some(pixel_format),
+            timestamp_ns: text("DATE-OBS").and_then(parse_date).unwrap_or(0),
+            exposure_secs: real("EXPTIME").or_else(|| real("EXPOSURE")),
+            gain: real("GAIN"),
+            offset: real("OFFSET").map(|v| v.round() as i32),
+            bin_x,
+            bin_y,
+            roi_x: int("XORGSUBF").and_then(|v| u32::try_from(v).ok()),
+            roi_y: int("YORGSUBF").and_then(|v| u32::try_from(v).ok()),
+            sensor_temp_c: real("CCD-TEMP"),
+            frame_type: text("IMAGETYP").and_then(parse_image_type),
+            filter: text("FILTER").map(str::to_string),
+            pixel_size_x_um: real("XPIXSZ").map(|v| v / bin_x.unwrap_or(1).max(1) as f64),
+            pixel_size_y_um: real("YPIXSZ").map(|v| v / bin_y.unwrap_or(1).max(1) as f64),
+            ..Default::default()
+        };
+        Ok((header, payload))
+    }
+}
+
+/// Lenient `IMAGETYP` parsing: `"Light Frame"`, `"LIGHT"`, `"Flat Field"`...
+fn parse_image_type(value: &str) -> Option<FrameType> {
+    let value = value.to_ascii_lowercase();
+    [
+        ("light", FrameType::Light),
+        ("dark", FrameType::Dark),
+        ("flat", FrameType::Flat),
+        ("bias", FrameType::Bias),
+        ("offset", FrameType::Bias),
+    ]
+    .into_iter()
+    .find(|(name, _)| value.contains(name))
+    .map(|(_, t)| t)
+}
+
+/// Parse the value field of a card (columns 11-80, or what follows `=` in a
+/// `HIERARCH` card) into a value and an optional comment.
+fn parse_value(field: &str) -> Result<(Option<Value>, Option<String>), FitsError> {
+    let field = field.trim_start();
+    let comment_of = |rest: &str| {
+        rest.trim_start()
+            .strip_prefix('/')
+            .map(|c| c.trim().to_string())
+            .filter(|c| !c.is_empty())
+    };
+    if let Some(rest) = field.strip_prefix('\'') {
+        let mut text = String::new();
+        let mut chars = rest.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if c != '\'' {
+                text.push(c);
+            } else if chars.peek().map(|&(_, n)| n) == Some('\'') {
+                text.push('\'');
+                chars.next();
+            } else {
+                let text = text.trim_end().to_string();
+                return Ok((Some(Value::Text(text)), comment_of(&rest[i + 1..])));
+            }
+        }
+        return Err(FitsError::Parse(format!("unterminated string: {field}")));
+    }
+    let (token, comment) = match field.find('/') {
+        Some(i) => (field[..i].trim(), comment_of(&field[i..])),
+        None => (field.trim(), None),
+    };
+    let value = match token {
+        "" => None,
+        "T" => Some(Value::Logical(true)),
+        "F" => Some(Value::Logical(false)),
+        _ => match token.parse::<i64>() {
+            Ok(v) => Some(Value::Integer(v)),
+            Err(_) => Some(Value::Real(
+                token
+                    .replace(['D', 'd'], "E")
+                    .parse()
+                    .map_err(|_| FitsError::Parse(format!("bad value: {token}")))?,
+            )),
+        },
+    };
+    Ok((value, comment))
+}
+
+/// Parse one 80-byte card into `cards`, merging `CONTINUE` cards into the
+/// preceding long string.
+fn parse_card(line: &str, cards: &mut Vec<Card>) -> Result<(), FitsError> {
+    let keyword = line[..8].trim_end();
+    if keyword == "CONTINUE" {
+        let (value, comment) = parse_value(&line[8..])?;
+        let previous = cards.last_mut().and_then(|c| match &mut c.value {
+            Some(Value::Text(text)) if text.ends_with('&') => Some((text, &mut c.comment)),
+            _ => None,
+        });
+        if let (Some((text, previous_comment)), Some(Value::Text(more))) = (previous, value) {
+            text.pop();
+            text.push_str(&more);
+            if comment.is_some() {
+                *previous_comment = comment;
+            }
+        }
+        return Ok(());
+    }
+    if keyword == "HIERARCH" {
+        if let Some((key, field)) = line[8..].split_once('=') {
+            let (value, comment) = parse_value(field)?;
+            cards.push(Card {
+                keyword: key.trim().to_string(),
+                value,
+                comment,
+            });
+            return Ok(());
+        }
+    }
+    if &line[8..10] == "= " {
+        let (value, comment) = parse_value(&line[10..])?;
+        cards.push(Card {
+            keyword: keyword.to_string(),
+            value,
+            comment,
+        });
+    } else if !keyword.is_empty() || !line[8..].trim().is_empty() {
+        let text = line[8..].trim_end();
+        cards.push(Card {
+            keyword: keyword.to_string(),
+            value: None,
+            comment: (!text.is_empty()).then(|| text.to_string()),
+        });
+    }
+    Ok(())
+}
+
+/// Read the header blocks of the primary HDU, up to and including `END`.
+fn read_header<R: Read>(r: &mut R) -> Result<FitsHeader, FitsError> {
+    let mut cards = Vec::new();
+    let mut block = [0u8; BLOCK_LEN];
+    for _ in 0..MAX_HEADER_BLOCKS {
+        r.read_exact(&mut block)?;
+        if !block.iter().all(|b| (0x20..0x7f).contains(b)) {
+            return Err(FitsError::Parse("non-ASCII header".into()));
+        }
+        for line in block.chunks_exact(CARD_LEN) {
+            // Checked ASCII above.
+            let line = std::str::from_utf8(line).unwrap();
+            if line[..8].trim_end() == "END" {
+                return Ok(FitsHeader { cards });
+            }
+            parse_card(line, &mut cards)?;
+        }
+    }
+    Err(FitsError::Parse("header has no END card".into()))
+}
+
+/// Read the primary HDU of a FITS file.
+pub fn read_image<R: Read>(r: &mut R) -> Result<FitsImage, FitsError> {
+    let cards = read_header(r)?;
+    if cards.get("SIMPLE") != Some(&Value::Logical(true)) {
+        return Err(FitsError::Parse("not a FITS file (SIMPLE)".into()));
+    }
+    let required = |k: &str| {
+        cards
+            .get(k)
+            .and_then(Value::as_i64)
+            .ok_or_else(|| FitsError::Parse(format!("missing {k}")))
+    };
+    let bitpix = required("BITPIX")? as i32;
+    let naxis = required("NAXIS")?;
+    if !(0..=999).contains(&naxis) {
+        return Err(FitsError::Parse(format!("NAXIS = {naxis}")));
+    }
+    let dims = (1..=naxis)
+        .map(|i| {
+            let len = required(&format!("NAXIS{i}"))?;
+            usize::try_from(len).map_err(|_| FitsError::Parse(format!("NAXIS{i} = {len}")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let count = if dims.is_empty() {
+        0
+    } else {
+        dims.iter()
+            .try_fold(1usize, |n, &d| n.checked_mul(d))
+            .ok_or_else(|| FitsError::Parse("image too large".into()))?
+    };
+    let len = count
+        .checked_mul(bitpix.unsigned_abs() as usize / 8)
+        .ok_or_else(|| FitsError::Parse("image too large".into()))?;
+
+    let mut raw = Vec::new();
+    r.take(len as u64).read_to_end(&mut raw)?;
+    if raw.len() != len {
+        return Err(FitsError::Truncated);
+    }
+    let bscale = cards.get("BSCALE").and_then(Value::as_f64).unwrap_or(1.0);
+    let bzero = cards.get("BZERO").and_then(Value::as_f64).unwrap_or(0.0);
+    let pixels = Pixels::decode(bitpix, &raw, bscale, bzero)?;
+    Ok(FitsImage {
+        cards,
+        dims,
+        bitpix,
+        pixels,
+    })
+}
+
+/// Read a FITS file back into a frame, see [`FitsImage::to_frame`].
+pub fn read_frame<R: Read>(r: &mut R) -> Result<(FrameHeader, Vec<u8>), FitsError> {
+    read_image(r)?.to_frame()
+}
+
+/// `YYYY-MM-DDThh:mm:ss.ssssss` (UTC) for nanoseconds since the unix epoch.
+pub(crate) fn format_date(timestamp_ns: u64) -> String {
+    let secs = timestamp_ns / 1_000_000_000;
+    let micros = (timestamp_ns % 1_000_000_000) / 1_000;
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // Howard Hinnant's civil_from_days.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{micros:06}",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Nanoseconds since the unix epoch for a `DATE-OBS` value
+/// (`YYYY-MM-DD[Thh:mm:ss[.s...]]`, UTC). Dates before 1970 give `None`.
+fn parse_date(value: &str) -> Option<u64> {
+    let (date, time) = value
+        .trim()
+        .split_once('T')
+        .unwrap_or((value.trim(), "00:00:00"));
+    let mut ymd = date.splitn(3, '-').map(|v| v.parse::<i64>().ok());
+    let (year, month, day) = (ymd.next()??, ymd.next()??, ymd.next()??);
+    let (hms, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut hms = hms.splitn(3, ':').map(|v| v.parse::<u64>().ok());
+    let (hour, minute, second) = (hms.next()??, hms.next()??, hms.next()??);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+    let digits: String = fraction.chars().take(9).collect();
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let nanos = if digits.is_empty() {
+        0
+    } else {
+        format!("{digits:0<9}").parse::<u64>().ok()?
+    };
+
+    // Howard Hinnant's days_from_civil.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146_097 + doe - 719_468).ok()?;
+    Some((days * 86_400 + hour * 3600 + minute * 60 + second) * 1_000_000_000 + nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cards_of(bytes: &[u8]) -> Vec<String> {
+        bytes
+            .chunks(CARD_LEN)
+            .map(|c| String::from_utf8(c.to_vec()).unwrap())
+            .take_while(|c| !c.starts_with("END "))
+            .collect()
+    }
+
+    #[test]
+    fn formats_fixed_format_cards() {
+        let mut h = FitsHeader::new();
+        h.set("EXPTIME", 120.0, "[s] exposure");
+        h.set("FILTER", "O'III", "");
+        h.set("XBINNING", 2, "");
+        h.set("SIMPLE", true, "");
+        h.set("TINY", 1.5e-7, "");
+        let lines: Vec<String> = h
+            .cards()
+            .iter()
+            .flat_map(|c| format_card(c).unwrap())
+            .collect();
+        assert_eq!(
+            lines[0],
+            format!("{:<80}", "EXPTIME =                120.0 / [s] exposure")
+        );
+        assert_eq!(lines[1], format!("{:<80}", "FILTER  = 'O''III  '"));
+        assert_eq!(&lines[2][..30], "XBINNING=                    2");
+        assert_eq!(&lines[3][..30], "SIMPLE  =                    T");
+        assert!(lines[4].contains("1.5E-7"));
+        assert!(lines.iter().all(|l| l.len() == CARD_LEN));
+    }
+
+    #[test]
+    fn long_strings_continue_and_hierarch() {
+        let long: String = "abcdefghij'".repeat(12);
+        let mut h = FitsHeader::new();
+        h.set("OBJECT", long.as_str(), "target");
+        h.set("LIGHTSPEED CORRELATION_ID", "x", "");
+        let lines = format_card(&h.cards()[0]).unwrap();
+        assert!(lines.len() > 1);
+        assert!(lines[0].starts_with("OBJECT  = 'abcdefghij''"));
+        assert!(lines[0].trim_end().ends_with("&'"));
+        assert!(lines[1].starts_with("CONTINUE  '"));
+        assert!(lines.last().unwrap().contains(" / target"));
+        let joined: String = lines
+            .iter()
+            .map(|l| {
+                let v = &l[l.find('\'').unwrap() + 1..];
+                let v = &v[..v.rfind('\'').unwrap()];
+                v.trim_end_matches('&').to_string()
+            })
+            .collect();
+        assert_eq!(joined.replace("''", "'"), long);
+
+        let hierarch = format_card(&h.cards()[1]).unwrap();
+        assert_eq!(
+            hierarch[0].trim_end(),
+            "HIERARCH LIGHTSPEED CORRELATION_ID = 'x       '"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_cards() {
+        let mut h = FitsHeader::new();
+        h.set("EXPTIME", f64::NAN, "");
+        assert!(matches!(
+            format_card(&h.cards()[0]),
+            Err(FitsError::InvalidCard(_))
+        ));
+        h.set("OBSERVER", "Ångström", "");
+        assert!(format_card(&h.cards()[1]).is_err());
+    }
+
+    #[test]
+    fn writes_unsigned_16_bit_frame() {
+        let header = FrameHeader {
+            correlation_id: Uuid::now_v7(),
+            width: 3,
+            height: 2,
+            bit_depth: 16,
+            pixel_format: Some(PixelFormat::Mono16Le),
+            timestamp_ns: 1_700_000_000_123_456_789,
+            exposure_secs: Some(30.0),
+            bin_x: Some(2),
+            pixel_size_x_um: Some(3.76),
+            sensor_temp_c: Some(-10.0),
+            frame_type: Some(FrameType::Dark),
+            filter: Some("Ha".into()),
+            bayer: Some(BayerPattern::Rggb),
+            ..Default::default()
+        };
+        let pixels: [u16; 6] = [0, 1, 32768, 65535, 1000, 2];
+        let data: Vec<u8> = pixels.iter().flat_map(|p| p.to_le_bytes()).collect();
+        let mut fits = FitsHeader::from_frame(&header);
+        fits.set("INSTRUME", "QHY268M", "camera");
+
+        let mut out = Vec::new();
+        write_frame(&mut out, &header, &data, &fits).unwrap();
+        assert_eq!(out.len(), 2 * BLOCK_LEN);
+
+        let cards = cards_of(&out[..BLOCK_LEN]);
+        let find = |k: &str| cards.iter().find(|c| c.starts_with(k)).cloned().unwrap();
+        assert!(find("BITPIX").contains(" 16 "));
+        assert!(find("NAXIS1").contains(" 3 "));
+        assert!(find("BZERO").contains(" 32768 "));
+        assert!(find("DATE-OBS").contains("'2023-11-14T22:13:20.123456'"));
+        assert!(find("IMAGETYP").contains("'Dark Frame'"));
+        assert!(find("XPIXSZ").contains("7.52"));
+        assert!(find("BAYERPAT").contains("'RGGB    '"));
+        assert!(find("INSTRUME").contains("'QHY268M '"));
+        assert!(
+            find("HIERARCH LIGHTSPEED CORRELATION_ID").contains(&header.correlation_id.to_string())
+        );
+
+        let body = &out[BLOCK_LEN..];
+        assert_eq!(
+            &body[..8],
+            &[0x80, 0x00, 0x80, 0x01, 0x00, 0x00, 0x7f, 0xff]
+        );
+        assert!(body[12..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn rgb_frames_become_planes() {
+        let header = FrameHeader {
+            width: 2,
+            height: 1,
+            bit_depth: 8,
+            pixel_format: Some(PixelFormat::Rgb24),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_frame(&mut out, &header, &[1, 2, 3, 4, 5, 6], &FitsHeader::new()).unwrap();
+        assert!(cards_of(&out)
+            .iter()
+            .any(|c| c.starts_with("NAXIS3  =                    3")));
+        assert_eq!(&out[BLOCK_LEN..BLOCK_LEN + 6], &[1, 4, 2, 5, 3, 6]);
+    }
+
+    #[test]
+    fn user_cards_cannot_override_structure() {
+        let mut fits = FitsHeader::new();
+        fits.set("BITPIX", 64, "");
+        fits.set("NAXIS1", 99, "");
+        let mut out = Vec::new();
+        write_image(&mut out, &fits, &[2, 2], ImageData::F32(&[0.0; 4])).unwrap();
+        let cards = cards_of(&out);
+        assert_eq!(cards.iter().filter(|c| c.starts_with("BITPIX")).count(), 1);
+        assert!(cards
+            .iter()
+            .any(|c| c.starts_with("BITPIX  =                  -32")));
+        assert!(!cards.iter().any(|c| c.contains(" 99 ")));
+
+        assert!(matches!(
+            write_image(&mut out, &fits, &[3, 2], ImageData::I32(&[0; 4])),
+            Err(FitsError::Dimensions {
+                expected: 6,
+                actual: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn formats_dates() {
+        assert_eq!(format_date(0), "1970-01-01T00:00:00.000000");
+        assert_eq!(
+            format_date(951_782_400_000_001_000),
+            "2000-02-29T00:00:00.000001"
+        );
+    }
+
+    fn raw_fits(cards: &[&str], data: &[u8]) -> Vec<u8> {
+        let mut text: String = cards.iter().map(|c| format!("{c:<80}")).collect();
+        text.push_str(&format!("{:<80}", "END"));
+        let mut out = Vec::new();
+        write_padded(&mut out, text.as_bytes(), b' ').unwrap();
+        write_padded(&mut out, data, 0).unwrap();
+        out
+    }
+
+    #[test]
+    fn frame_roundtrip_keeps_metadata() {
+        let header = FrameHeader {
+            correlation_id: Uuid::now_v7(),
+            driver_id: Uuid::now_v7(),
+            width: 4,
+            height: 3,
+            bit_depth: 16,
+            pixel_format: Some(PixelFormat::Bayer16),
+            bayer: Some(BayerPattern::Gbrg),
+            timestamp_ns: 1_700_000_000_123_456_000,
+            exposure_secs: Some(0.001),
+            gain: Some(101.0),
+            offset: Some(-20),
+            bin_x: Some(2),
+            bin_y: Some(2),
+            roi_x: Some(100),
+            roi_y: Some(50),
+            sensor_temp_c: Some(-9.5),
+            frame_type: Some(FrameType::Flat),
+            filter: Some("Luminance 'L'".into()),
+            pixel_size_x_um: Some(3.76),
+            pixel_size_y_um: Some(3.76),
+            ..Default::default()
+        };
+        let data: Vec<u8> = (0..12u16).flat_map(|i| (i * 5000).to_le_bytes()).collect();
+        let mut out = Vec::new();
+        write_frame(&mut out, &header, &data, &FitsHeader::from_frame(&header)).unwrap();
+
+        let (back, payload) = read_frame(&mut out.as_slice()).unwrap();
+        assert_eq!(payload, data);
+        assert_eq!(
+            serde_json::to_value(&back).unwrap(),
+            serde_json::to_value(&header).unwrap()
+        );
+    }
+
+    #[test]
+    fn rgb_and_8_bit_roundtrip() {
+        let header = FrameHeader {
+            width: 2,
+            height: 2,
+            bit_depth: 8,
+            pixel_format: Some(PixelFormat::Rgb24),
+            ..Default::default()
+        };
+        let data: Vec<u8> = (0..12).collect();
+        let mut out = Vec::new();
+        write_frame(&mut out, &header, &data, &FitsHeader::from_frame(&header)).unwrap();
+        let image = read_image(&mut out.as_slice()).unwrap();
+        assert_eq!(image.dims, [2, 2, 3]);
+        assert_eq!(image.bitpix, 8);
+        let (back, payload) = image.to_frame().unwrap();
+        assert_eq!(back.pixel_format, Some(PixelFormat::Rgb24));
+        assert_eq!(payload, data);
+    }
+
+    #[test]
+    fn reads_back_continue_hierarch_and_commentary() {
+        let long = "a 'quoted' name that is much longer than one card can hold, ".repeat(3);
+        let mut fits = FitsHeader::new();
+        fits.set("OBJECT", long.as_str(), "target");
+        fits.set("ESO TEL FOCU VALUE", 1.25, "focus");
+        fits.comment("written by astrotools");
+        let mut out = Vec::new();
+        write_image(&mut out, &fits, &[1], ImageData::U8(&[0])).unwrap();
+
+        let cards = read_image(&mut out.as_slice()).unwrap().cards;
+        let object = cards
+            .cards()
+            .iter()
+            .find(|c| c.keyword == "OBJECT")
+            .unwrap();
+        assert_eq!(object.value, Some(Value::Text(long.trim_end().to_string())));
+        assert_eq!(object.comment.as_deref(), Some("target"));
+        assert_eq!(cards.get("ESO TEL FOCU VALUE"), Some(&Value::Real(1.25)));
+        let comment = cards
+            .cards()
+            .iter()
+            .find(|c| c.keyword == "COMMENT")
+            .unwrap();
+        assert_eq!(comment.comment.as_deref(), Some("written by astrotools"));
+    }
+
+    #[test]
+    fn applies_bscale_and_bzero() {
+        let file = raw_fits(
+            &[
+                "SIMPLE  =                    T",
+                "BITPIX  =                   16",
+                "NAXIS   =                    1",
+                "NAXIS1  =                    2",
+                "BSCALE  =                  0.5 / scale",
+                "BZERO   =               1.0D+2",
+            ],
+            &[0x00, 0x02, 0xff, 0xfe],
+        );
+        let image = read_image(&mut file.as_slice()).unwrap();
+        assert_eq!(image.pixels, Pixels::F32(vec![101.0, 99.0]));
+        assert!(matches!(image.to_frame(), Err(FitsError::Unsupported(_))));
+
+        let file = raw_fits(
+            &[
+                "SIMPLE  =                    T",
+                "BITPIX  =                   32",
+                "NAXIS   =                    1",
+                "NAXIS1  =                    1",
+                "BZERO   =           2147483648",
+            ],
+            &[0x80, 0, 0, 7],
+        );
+        assert_eq!(
+            read_image(&mut file.as_slice()).unwrap().pixels,
+            Pixels::U32(vec![7])
+        );
+
+        let mut out = Vec::new();
+        write_image(
+            &mut out,
+            &FitsHeader::new(),
+            &[2],
+            ImageData::F32(&[1.5, -2.0]),
+        )
+        .unwrap();
+        assert_eq!(
+            read_image(&mut out.as_slice()).unwrap().pixels,
+            Pixels::F32(vec![1.5, -2.0])
+        );
+    }
+
+    #[test]
+    fn bottom_up_rows_are_flipped() {
+        let file = raw_fits(
+            &[
+                "SIMPLE  =                    T",
+                "BITPIX  =                    8",
+                "NAXIS   =                    2",
+                "NAXIS1  =                    2",
+                "NAXIS2  =                    2",
+                "ROWORDER= 'BOTTOM-UP'",
+            ],
+            &[1, 2, 3, 4],
+        );
+        let (_, payload) = read_frame(&mut file.as_slice()).unwrap();
+        assert_eq!(payload, [3, 4, 1, 2]);
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        let mut out = Vec::new();
+        write_image(
+            &mut out,
+            &FitsHeader::new(),
+            &[100],
+            ImageData::U16(&[0; 100]),
+        )
+        .unwrap();
+        assert!(matches!(
+            read_image(&mut &out[..BLOCK_LEN + 10]),
+            Err(FitsError::Truncated)
+        ));
+        assert!(matches!(
+            read_image(&mut &out[..100]),
+            Err(FitsError::Truncated)
+        ));
+
+        let file = raw_fits(&["SIMPLE  =                    F"], &[]);
+        assert!(matches!(
+            read_image(&mut file.as_slice()),
+            Err(FitsError::Parse(_))
+        ));
+        let file = raw_fits(
+            &[
+                "SIMPLE  =                    T",
+                "BITPIX  =                   16",
+            ],
+            &[],
+        );
+        assert!(matches!(
+            read_image(&mut file.as_slice()),
+            Err(FitsError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn parses_dates() {
+        for ns in [0, 951_782_400_000_001_000, 1_700_000_000_123_456_000] {
+            assert_eq!(parse_date(&format_date(ns)), Some(ns));
+        }
+        assert_eq!(parse_date("2000-01-01"), Some(946_684_800_000_000_000));
+        assert_eq!(
+            parse_date("2000-01-01T00:00:00.5"),
+            Some(946_684_800_500_000_000)
+        );
+        assert_eq!(parse_date("1969-12-31T23:59:59"), None);
+        assert_eq!(parse_date("garbage"), None);
+    }
+}
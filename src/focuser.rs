@@ -0,0 +1,623 @@
+//! Focuser trait ([`DeviceType::Focuser`](crate::DeviceType)), backlash
+//! compensation, temperature compensation and a generic
+//! [`LightspeedDevice`] adapter.
+//!
+//! Positions are absolute steps in `0..=max_position`, larger values being
+//! further out. [`FocuserDevice`] adds backlash and temperature compensation
+//! on top of any [`Focuser`], so drivers only implement the raw moves.
+
+use std::collections::VecDeque;
+use std::sync::mpsc::SyncSender;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::device::{CommandQueue, Dispatcher, LightspeedDevice};
+use crate::properties::PropertyErrorType;
+use crate::protocol::ErrorEnvelope;
+use crate::{DeviceType, LightspeedError};
+
+/// Command topics handled by [`FocuserDevice`].
+pub const FOCUSER_TOPICS: &[&str] = &[
+    "move_to",
+    "move_by",
+    "halt",
+    "set_backlash",
+    "set_temp_comp",
+];
+
+pub trait Focuser {
+    /// Largest position the focuser accepts.
+    fn max_position(&self) -> u32;
+
+    fn position(&self) -> Result<u32, LightspeedError>;
+
+    /// Start moving to `position`. Returns once the move has started.
+    fn move_to(&mut self, position: u32) -> Result<(), LightspeedError>;
+
+    fn is_moving(&self) -> bool;
+
+    fn halt(&mut self) -> Result<(), LightspeedError>;
+
+    /// Focuser temperature probe in degrees Celsius.
+    fn temperature(&self) -> Option<f64> {
+        None
+    }
+
+    /// Start moving by `steps`, positive outward.
+    fn move_by(&mut self, steps: i32) -> Result<(), LightspeedError> {
+        let target = offset_position(self.position()?, steps, self.max_position())?;
+        self.move_to(target)
+    }
+}
+
+/// `position + steps`, or `ValueOutOfRange` if that leaves `0..=max`.
+pub fn offset_position(position: u32, steps: i32, max: u32) -> Result<u32, LightspeedError> {
+    position
+        .checked_add_signed(steps)
+        .filter(|p| *p <= max)
+        .ok_or_else(|| PropertyErrorType::ValueOutOfRange.into())
+}
+
+/// Check an absolute position against the focuser range.
+pub fn validate_position(max: u32, position: u32) -> Result<(), LightspeedError> {
+    if position > max {
+        return Err(PropertyErrorType::ValueOutOfRange.into());
+    }
+    Ok(())
+}
+
+/// Direction of travel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Inward,
+    #[default]
+    Outward,
+}
+
+/// Backlash compensation: every move ends travelling in `approach`. Moves
+/// the other way overshoot by `steps` and come back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Backlash {
+    pub steps: u32,
+    #[serde(default)]
+    pub approach: Direction,
+}
+
+impl Backlash {
+    /// Positions to visit, in order, to get from `from` to `to`.
+    pub fn plan(&self, from: u32, to: u32, max: u32) -> Vec<u32> {
+        let overshoot = match self.approach {
+            _ if self.steps == 0 || to == from => None,
+            Direction::Outward if to < from => Some(to.saturating_sub(self.steps)),
+            Direction::Inward if to > from => Some(to.saturating_add(self.steps).min(max)),
+            _ => None,
+        };
+        overshoot
+            .filter(|p| *p != to)
+            .into_iter()
+            .chain([to])
+            .collect()
+    }
+}
+
+/// Temperature compensation settings.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TempCompConfig {
+    pub enabled: bool,
+    /// Steps to move per degree Celsius of warming; positive moves outward.
+    pub steps_per_degree: f64,
+    /// Temperature change, in degrees, that triggers a move.
+    pub hysteresis_c: f64,
+    /// Shortest time between two compensation moves, in seconds.
+    pub min_interval_secs: f64,
+}
+
+impl Default for TempCompConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            steps_per_degree: 0.0,
+            hysteresis_c: 0.5,
+            min_interval_secs: 60.0,
+        }
+    }
+}
+
+/// Turns temperature readings into focuser corrections.
+///
+/// The first reading becomes the reference. Once the temperature is
+/// `hysteresis_c` away from it and `min_interval_secs` have passed since
+/// the last correction, [`TempCompensation::update`] returns the steps for
+/// the drift and moves the reference by the amount corrected, so rounding
+/// never accumulates.
+#[derive(Debug, Clone, Default)]
+pub struct TempCompensation {
+    config: TempCompConfig,
+    reference: Option<f64>,
+    last_move: Option<Instant>,
+}
+
+impl TempCompensation {
+    pub fn new(config: TempCompConfig) -> Self {
+        Self {
+            config,
+            reference: None,
+            last_move: None,
+        }
+    }
+
+    pub fn config(&self) -> &TempCompConfig {
+        &self.config
+    }
+
+    /// Replace the settings and start over from the next reading.
+    pub fn set_config(&mut self, config: TempCompConfig) {
+        self.config = config;
+        self.reset();
+    }
+
+    /// Temperature the current focus position corresponds to.
+    pub fn reference(&self) -> Option<f64> {
+        self.reference
+    }
+
+    /// Forget the reference, e.g. after a manual move or autofocus run.
+    pub fn reset(&mut self) {
+        self.reference = None;
+    }
+
+    /// Steps to move for `temperature` read at `now`; 0 for none.
+    pub fn update(&mut self, temperature: f64, now: Instant) -> i32 {
+        if !self.config.enabled || !temperature.is_finite() || self.config.steps_per_degree == 0.0 {
+            return 0;
+        }
+        let Some(reference) = self.reference else {
+            self.reference = Some(temperature);
+            return 0;
+        };
+        let delta = temperature - reference;
+        let min_interval = Duration::from_secs_f64(self.config.min_interval_secs.max(0.0));
+        if delta.abs() < self.config.hysteresis_c
+            || self
+                .last_move
+                .is_some_and(|t| now.saturating_duration_since(t) < min_interval)
+        {
+            return 0;
+        }
+        let steps = (delta * self.config.steps_per_degree).round() as i32;
+        if steps != 0 {
+            self.reference = Some(reference + steps as f64 / self.config.steps_per_degree);
+            self.last_move = Some(now);
+        }
+        steps
+    }
+}
+
+/// Commands accepted by [`FocuserDevice`], one per topic in
+/// [`FOCUSER_TOPICS`]. Payloads are JSON: a position for `move_to`, signed
+/// steps for `move_by`, a [`Backlash`] and a [`TempCompConfig`]; `halt`
+/// ignores its payload.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FocuserCommand {
+    MoveTo(u32),
+    MoveBy(i32),
+    Halt,
+    SetBacklash(Backlash),
+    SetTempComp(TempCompConfig),
+}
+
+impl FocuserCommand {
+    pub fn parse(action: &str, payload: &[u8]) -> Result<Self, LightspeedError> {
+        fn json<'a, T: Deserialize<'a>>(payload: &'a [u8]) -> Result<T, LightspeedError> {
+            serde_json::from_slice(payload).map_err(|_| LightspeedError::ParseError)
+        }
+        Ok(match action {
+            "move_to" => FocuserCommand::MoveTo(json(payload)?),
+            "move_by" => FocuserCommand::MoveBy(json(payload)?),
+            "halt" => FocuserCommand::Halt,
+            "set_backlash" => FocuserCommand::SetBacklash(json(payload)?),
+            "set_temp_comp" => FocuserCommand::SetTempComp(json(payload)?),
+            _ => return Err(LightspeedError::UnknownCommand),
+        })
+    }
+}
+
+/// State published by [`FocuserDevice`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FocuserState {
+    /// None if the focuser cannot be read.
+    pub position: Option<u32>,
+    /// Final position of the move in progress.
+    pub target: Option<u32>,
+    pub moving: bool,
+    pub max_position: u32,
+    pub temperature: Option<f64>,
+    pub backlash: Backlash,
+    pub temp_comp: TempCompConfig,
+    /// Temperature the current position was compensated for.
+    pub temp_comp_reference: Option<f64>,
+    /// Last failed command or compensation move.
+    pub last_error: Option<ErrorEnvelope>,
+}
+
+/// Runs any [`Focuser`] as a [`LightspeedDevice`], with backlash and
+/// temperature compensation.
+///
+/// Compensation moves only start while the focuser is idle. Manual moves
+/// reset the compensation reference, so the new position is kept for the
+/// current temperature. `halt` skips the command queue, so it is never
+/// refused with `QueueFull` and cancels the moves still waiting.
+pub struct FocuserDevice<F> {
+    id: Uuid,
+    name: String,
+    focuser: F,
+    commands: CommandQueue<FocuserCommand>,
+    backlash: Backlash,
+    temp_comp: TempCompensation,
+    /// Remaining legs of the current move.
+    legs: VecDeque<u32>,
+    target: Option<u32>,
+}
+
+impl<F: Focuser> FocuserDevice<F> {
+    pub fn new(id: Uuid, name: impl Into<String>, focuser: F) -> Self {
+        Self {
+            id,
+            name: name.into(),
+            focuser,
+            commands: CommandQueue::with_stop(FocuserCommand::Halt),
+            backlash: Backlash::default(),
+            temp_comp: TempCompensation::default(),
+            legs: VecDeque::new(),
+            target: None,
+        }
+    }
+
+    pub fn with_backlash(mut self, backlash: Backlash) -> Self {
+        self.backlash = backlash;
+        self
+    }
+
+    pub fn with_temp_comp(mut self, config: TempCompConfig) -> Self {
+        self.temp_comp.set_config(config);
+        self
+    }
+
+    pub fn focuser(&self) -> &F {
+        &self.focuser
+    }
+
+    pub fn focuser_mut(&mut self) -> &mut F {
+        &mut self.focuser
+    }
+
+    pub fn state(&self) -> FocuserState {
+        FocuserState {
+            position: self.focuser.position().ok(),
+            target: self.target,
+            moving: self.focuser.is_moving(),
+            max_position: self.focuser.max_position(),
+            temperature: self.focuser.temperature(),
+            backlash: self.backlash,
+            temp_comp: *self.temp_comp.config(),
+            temp_comp_reference: self.temp_comp.reference(),
+            last_error: self.commands.last_error().cloned(),
+        }
+    }
+
+    /// Plan a move to `target` and start its first leg.
+    fn start_move(&mut self, target: u32) -> Result<(), LightspeedError> {
+        let max = self.focuser.max_position();
+        validate_position(max, target)?;
+        let from = self.focuser.position()?;
+        self.legs = self.backlash.plan(from, target, max).into();
+        self.target = Some(target);
+        self.next_leg()
+    }
+
+    fn next_leg(&mut self) -> Result<(), LightspeedError> {
+        match self.legs.pop_front() {
+            Some(position) => self.focuser.move_to(position).inspect_err(|_| {
+                self.legs.clear();
+                self.target = None;
+            }),
+            None => {
+                self.target = None;
+                Ok(())
+            }
+        }
+    }
+
+    fn apply(&mut self, command: FocuserCommand) -> Result<(), LightspeedError> {
+        match command {
+            FocuserCommand::MoveTo(position) => {
+                self.start_move(position)?;
+                self.temp_comp.reset();
+                Ok(())
+            }
+            FocuserCommand::MoveBy(steps) => {
+                let target =
+                    offset_position(self.focuser.position()?, steps, self.focuser.max_position())?;
+                self.start_move(target)?;
+                self.temp_comp.reset();
+                Ok(())
+            }
+            FocuserCommand::Halt => {
+                self.legs.clear();
+                self.target = None;
+                self.focuser.halt()
+            }
+            FocuserCommand::SetBacklash(backlash) => {
+                self.backlash = backlash;
+                Ok(())
+            }
+            FocuserCommand::SetTempComp(config) => {
+                let non_negative = |x: f64| x.is_finite() && x >= 0.0;
+                if !config.steps_per_degree.is_finite()
+                    || !non_negative(config.hysteresis_c)
+                    || !non_negative(config.min_interval_secs)
+                {
+                    return Err(PropertyErrorType::InvalidValue.into());
+                }
+                self.temp_comp.set_config(config);
+                Ok(())
+            }
+        }
+    }
+
+    /// Start the next backlash leg, or a temperature compensation move when
+    /// idle.
+    fn advance(&mut self, now: Instant) -> Result<(), LightspeedError> {
+        if self.focuser.is_moving() {
+            return Ok(());
+        }
+        if self.target.is_some() {
+            return self.next_leg();
+        }
+        let Some(temperature) = self.focuser.temperature() else {
+            return Ok(());
+        };
+        let steps = self.temp_comp.update(temperature, now);
+        if steps != 0 {
+            let position = self.focuser.position()?;
+            let target = position
+                .saturating_add_signed(steps)
+                .min(self.focuser.max_position());
+            log::info!(
+                "{}: temperature compensation {position} -> {target} at {temperature:.2} C",
+                self.name
+            );
+            self.start_move(target)?;
+        }
+        Ok(())
+    }
+}
+
+impl<F: Focuser + Send + 'static> LightspeedDevice for FocuserDevice<F> {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn dev_type(&self) -> DeviceType {
+        DeviceType::Focuser
+    }
+
+    fn command_topics(&self) -> &[&str] {
+        FOCUSER_TOPICS
+    }
+
+    fn state_json(&self) -> String {
+        serde_json::to_string(&self.state()).unwrap_or_default()
+    }
+
+    fn dispatcher(&self) -> Dispatcher {
+        self.commands.dispatcher(FocuserCommand::parse)
+    }
+
+    fn tick(&mut self, state_tx: &SyncSender<(Uuid, String)>) {
+        CommandQueue::drain(self, |d| &mut d.commands, Self::apply);
+        if let Err(e) = self.advance(Instant::now()) {
+            self.commands.fail(&self.name, "move", &e);
+        }
+        let _ = state_tx.try_send((self.id, self.state_json()));
+    }
+
+    fn is_busy(&self) -> bool {
+        self.target.is_some() || self.focuser.is_moving()
+    }
+
+    fn abort(&mut self) {
+        self.legs.clear();
+        self.target = None;
+        if let Err(e) = self.focuser.halt() {
+            log::warn!("{}: halt failed: {e:?}", self.name);
+        }
+    }
+
+    fn close(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::sync_channel;
+
+    /// Moves instantly and records every move.
+    struct FakeFocuser {
+        position: u32,
+        temperature: Option<f64>,
+        moves: Vec<u32>,
+        halts: u32,
+    }
+
+    impl FakeFocuser {
+        fn new(position: u32) -> Self {
+            Self {
+                position,
+                temperature: None,
+                moves: Vec::new(),
+                halts: 0,
+            }
+        }
+    }
+
+    impl Focuser for FakeFocuser {
+        fn max_position(&self) -> u32 {
+            10_000
+        }
+
+        fn position(&self) -> Result<u32, LightspeedError> {
+            Ok(self.position)
+        }
+
+        fn move_to(&mut self, position: u32) -> Result<(), LightspeedError> {
+            validate_position(self.max_position(), position)?;
+            self.position = position;
+            self.moves.push(position);
+            Ok(())
+        }
+
+        fn is_moving(&self) -> bool {
+            false
+        }
+
+        fn halt(&mut self) -> Result<(), LightspeedError> {
+            self.halts += 1;
+            Ok(())
+        }
+
+        fn temperature(&self) -> Option<f64> {
+            self.temperature
+        }
+    }
+
+    #[test]
+    fn relative_moves_stay_in_range() {
+        let mut focuser = FakeFocuser::new(100);
+        focuser.move_by(-40).unwrap();
+        assert_eq!(focuser.position, 60);
+        assert!(matches!(
+            focuser.move_by(-61),
+            Err(LightspeedError::PropertyError(
+                PropertyErrorType::ValueOutOfRange
+            ))
+        ));
+        assert!(focuser.move_by(10_000).is_err());
+        assert_eq!(focuser.moves, [60]);
+    }
+
+    #[test]
+    fn backlash_overshoots_against_approach() {
+        let out = Backlash {
+            steps: 50,
+            approach: Direction::Outward,
+        };
+        assert_eq!(out.plan(1000, 900, 10_000), [850, 900]);
+        assert_eq!(out.plan(900, 1000, 10_000), [1000]);
+        assert_eq!(out.plan(100, 20, 10_000), [0, 20]);
+        assert_eq!(out.plan(100, 0, 10_000), [0]);
+
+        let inward = Backlash {
+            steps: 50,
+            approach: Direction::Inward,
+        };
+        assert_eq!(inward.plan(900, 1000, 10_000), [1050, 1000]);
+        assert_eq!(inward.plan(9990, 9995, 10_000), [10_000, 9995]);
+        assert_eq!(Backlash::default().plan(1000, 900, 10_000), [900]);
+    }
+
+    #[test]
+    fn temp_comp_honours_hysteresis_and_interval() {
+        let mut tc = TempCompensation::new(TempCompConfig {
+            enabled: true,
+            steps_per_degree: -15.0,
+            hysteresis_c: 0.5,
+            min_interval_secs: 60.0,
+        });
+        let t0 = Instant::now();
+        assert_eq!(tc.update(10.0, t0), 0);
+        assert_eq!(tc.reference(), Some(10.0));
+        assert_eq!(tc.update(9.7, t0), 0);
+        assert_eq!(tc.update(9.0, t0), 15);
+        assert_eq!(tc.reference(), Some(9.0));
+        // Within the minimum interval.
+        assert_eq!(tc.update(8.0, t0 + Duration::from_secs(30)), 0);
+        assert_eq!(tc.update(8.0, t0 + Duration::from_secs(60)), 15);
+
+        // Rounding is carried over instead of lost.
+        tc.set_config(TempCompConfig {
+            steps_per_degree: 3.0,
+            min_interval_secs: 0.0,
+            ..*tc.config()
+        });
+        assert_eq!(tc.update(0.0, t0), 0);
+        assert_eq!(tc.update(0.5, t0), 2);
+        assert_eq!(tc.update(1.0, t0), 0);
+        assert_eq!(tc.update(1.2, t0), 2);
+        assert!((tc.reference().unwrap() - 4.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn device_moves_with_backlash_and_compensates() {
+        let mut device = FocuserDevice::new(Uuid::nil(), "focuser", FakeFocuser::new(5000))
+            .with_backlash(Backlash {
+                steps: 100,
+                approach: Direction::Outward,
+            });
+        let dispatch = device.dispatcher();
+        let (state_tx, state_rx) = sync_channel(8);
+
+        dispatch("move_by", b"-500").unwrap();
+        device.tick(&state_tx);
+        assert_eq!(device.focuser().moves, [4400, 4500]);
+        assert_eq!(device.state().target, Some(4500));
+        device.tick(&state_tx);
+        assert!(!device.is_busy());
+
+        dispatch(
+            "set_temp_comp",
+            br#"{"enabled": true, "steps_per_degree": 20.0, "hysteresis_c": 0.5, "min_interval_secs": 0}"#,
+        )
+        .unwrap();
+        device.focuser_mut().temperature = Some(12.0);
+        device.tick(&state_tx);
+        device.focuser_mut().temperature = Some(13.0);
+        device.tick(&state_tx);
+        assert_eq!(device.focuser().moves.last(), Some(&4520));
+
+        dispatch("move_to", b"20000").unwrap();
+        device.tick(&state_tx);
+        let json = state_rx.try_iter().last().unwrap().1;
+        let state: FocuserState = serde_json::from_str(&json).unwrap();
+        assert_eq!(state.position, Some(4520));
+        assert_eq!(state.temp_comp_reference, Some(13.0));
+        assert_eq!(state.last_error.unwrap().message, "value out of range");
+    }
+
+    #[test]
+    fn halt_skips_a_full_queue() {
+        let mut device = FocuserDevice::new(Uuid::nil(), "focuser", FakeFocuser::new(5000));
+        let dispatch = device.dispatcher();
+        let (state_tx, _state_rx) = sync_channel(8);
+        for _ in 0..CommandQueue::<FocuserCommand>::CAPACITY {
+            dispatch("move_by", b"100").unwrap();
+        }
+        assert!(matches!(
+            dispatch("move_by", b"100"),
+            Err(LightspeedError::QueueFull)
+        ));
+
+        dispatch("halt", b"").unwrap();
+        device.tick(&state_tx);
+        assert_eq!(device.focuser().halts, 1);
+        assert!(device.focuser().moves.is_empty());
+        assert!(!device.is_busy());
+    }
+}
@@ -5,17 +5,281 @@
 //!
 //! 1. 4-byte big-endian `u32` header length `N`
 //! 2. `N` bytes of JSON-encoded [`FrameHeader`]
-//! 3. Raw image bytes (length implied by width * height * bit_depth/8 and the
-//!    sender's framing — astrotools does not prescribe transport-level framing)
+//! 3. Raw image bytes, exactly [`FrameHeader::payload_len`] of them
+//!    (width * height * bit_depth / 8, rounded up)
 //!
-//! The framing scheme itself is implemented in lightspeed-server and in
-//! camera drivers; this type only defines the header payload.
+//! [`encode_frame`] / [`decode_frame`] implement this framing on buffers,
+//! [`write_frame`] / [`read_frame`] on `std::io` streams. Headers longer than
+//! [`MAX_HEADER_LEN`] are rejected.
+//!
+//! The payload may be compressed, as declared by [`FrameHeader::encoding`];
+//! it is then [`FrameHeader::encoded_len`] bytes long instead. Use
+//! [`compress_payload`] / [`decompress_payload`] to convert. `rice` is always
+//! available, `zstd` and `lz4` need the crate features of the same name.
+
+use std::fmt;
+use std::io::{self, Read, Write};
 
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+/// Largest accepted JSON header, in bytes.
+pub const MAX_HEADER_LEN: u32 = 64 * 1024;
+
+/// Decoded payload cap the file writers pass to [`decompress_payload`]:
+/// 2 GiB, comfortably above a 100 MP RGB48 frame.
+pub const MAX_PAYLOAD_LEN: u64 = 2 * 1024 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum FrameError {
+    /// The header length prefix exceeds [`MAX_HEADER_LEN`].
+    HeaderTooLarge(u32),
+    /// The header bytes are not a valid JSON `FrameHeader`.
+    InvalidHeader,
+    /// The payload length does not match the header.
+    PayloadLength {
+        expected: u64,
+        actual: u64,
+    },
+    /// The payload would be `len` bytes, more than the caller's `max`.
+    TooLarge {
+        len: u64,
+        max: u64,
+    },
+    /// The input ended before the header or payload was complete.
+    Truncated,
+    /// The encoding is not compiled in, or does not apply to this pixel
+    /// layout (e.g. Rice on 12-bit packed data).
+    Unsupported(FrameEncoding),
+    /// The compressed payload is corrupt.
+    Codec,
+    /// The pixel layout cannot be unpacked, e.g. a `bit_depth` above 16.
+    PixelLayout,
+    Io(io::Error),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::HeaderTooLarge(len) => write!(f, "frame header too large: {len} bytes"),
+            FrameError::InvalidHeader => f.write_str("invalid frame header"),
+            FrameError::PayloadLength { expected, actual } => {
+                write!(f, "frame payload is {actual} bytes, expected {expected}")
+            }
+            FrameError::TooLarge { len, max } => write!(
+                f,
+                "frame payload of {len} bytes exceeds the {max} byte limit"
+            ),
+            FrameError::Truncated => f.write_str("truncated frame"),
+            FrameError::Unsupported(encoding) => {
+                write!(f, "unsupported frame encoding: {encoding:?}")
+            }
+            FrameError::Codec => f.write_str("corrupt compressed frame payload"),
+            FrameError::PixelLayout => f.write_str("unsupported pixel layout"),
+            FrameError::Io(e) => write!(f, "frame i/o error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<io::Error> for FrameError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => FrameError::Truncated,
+            _ => FrameError::Io(error),
+        }
+    }
+}
+
+/// Current [`FrameHeader::version`]. Headers without a `version` field
+/// predate versioning and deserialize as version 0.
+pub const FRAME_HEADER_VERSION: u16 = 1;
+
+/// Kind of science frame, as written to the FITS `IMAGETYP` keyword.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameType {
+    Light,
+    Dark,
+    Flat,
+    Bias,
+}
+
+/// Colour filter array layout, named by the top-left 2x2 block read row by
+/// row. Serialized as the upper-case name, e.g. `"RGGB"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BayerPattern {
+    #[serde(rename = "RGGB", alias = "rggb")]
+    Rggb,
+    #[serde(rename = "GRBG", alias = "grbg")]
+    Grbg,
+    #[serde(rename = "GBRG", alias = "gbrg")]
+    Gbrg,
+    #[serde(rename = "BGGR", alias = "bggr")]
+    Bggr,
+}
+
+impl BayerPattern {
+    /// Colour channel (0 = red, 1 = green, 2 = blue) of the pixel at `(x, y)`.
+    pub fn channel_at(&self, x: u32, y: u32) -> usize {
+        let block = match self {
+            BayerPattern::Rggb => [0, 1, 1, 2],
+            BayerPattern::Grbg => [1, 0, 2, 1],
+            BayerPattern::Gbrg => [1, 2, 0, 1],
+            BayerPattern::Bggr => [2, 1, 1, 0],
+        };
+        block[((y & 1) * 2 + (x & 1)) as usize]
+    }
+
+    /// Case-insensitive, whitespace-tolerant parse of a pattern name.
+    /// Anything else, e.g. `"none"`, gives `None`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_uppercase().as_str() {
+            "RGGB" => Some(BayerPattern::Rggb),
+            "GRBG" => Some(BayerPattern::Grbg),
+            "GBRG" => Some(BayerPattern::Gbrg),
+            "BGGR" => Some(BayerPattern::Bggr),
+            _ => None,
+        }
+    }
+
+    /// FITS `BAYERPAT` value, e.g. `"RGGB"`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BayerPattern::Rggb => "RGGB",
+            BayerPattern::Grbg => "GRBG",
+            BayerPattern::Gbrg => "GBRG",
+            BayerPattern::Bggr => "BGGR",
+        }
+    }
+}
+
+impl fmt::Display for BayerPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+fn lenient_bayer<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<BayerPattern>, D::Error> {
+    let value: Option<String> = Option::deserialize(deserializer)?;
+    Ok(value.as_deref().and_then(BayerPattern::parse))
+}
+
+/// In-memory layout of the pixel payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PixelFormat {
+    /// 8-bit mono.
+    Mono8,
+    /// 12-bit mono, two pixels packed into three bytes.
+    Mono12Packed,
+    /// 12-bit mono in 16-bit little-endian words, LSB-aligned (0..4095).
+    #[serde(rename = "Mono12LE")]
+    Mono12Le,
+    /// 12-bit mono in 16-bit little-endian words, MSB-aligned (low 4 bits zero).
+    #[serde(rename = "Mono12MsbLE")]
+    Mono12MsbLe,
+    /// 16-bit mono, little-endian.
+    #[serde(rename = "Mono16LE")]
+    Mono16Le,
+    /// 16-bit mono, big-endian.
+    #[serde(rename = "Mono16BE")]
+    Mono16Be,
+    /// 8-bit raw Bayer mosaic.
+    Bayer8,
+    /// 12-bit raw Bayer mosaic, two pixels packed into three bytes.
+    Bayer12Packed,
+    /// 16-bit raw Bayer mosaic, little-endian.
+    Bayer16,
+    /// 8-bit interleaved RGB.
+    #[serde(rename = "RGB24")]
+    Rgb24,
+    /// 16-bit interleaved RGB, little-endian.
+    #[serde(rename = "RGB48LE")]
+    Rgb48Le,
+}
+
+impl PixelFormat {
+    /// Storage bits per pixel, all channels included.
+    pub fn bits_per_pixel(&self) -> u32 {
+        match self {
+            PixelFormat::Mono8 | PixelFormat::Bayer8 => 8,
+            PixelFormat::Mono12Packed | PixelFormat::Bayer12Packed => 12,
+            PixelFormat::Mono12Le
+            | PixelFormat::Mono12MsbLe
+            | PixelFormat::Mono16Le
+            | PixelFormat::Mono16Be
+            | PixelFormat::Bayer16 => 16,
+            PixelFormat::Rgb24 => 24,
+            PixelFormat::Rgb48Le => 48,
+        }
+    }
+
+    /// Significant bits per channel sample.
+    pub fn significant_bits(&self) -> u32 {
+        match self {
+            PixelFormat::Mono8 | PixelFormat::Bayer8 | PixelFormat::Rgb24 => 8,
+            PixelFormat::Mono12Packed
+            | PixelFormat::Mono12Le
+            | PixelFormat::Mono12MsbLe
+            | PixelFormat::Bayer12Packed => 12,
+            PixelFormat::Mono16Le
+            | PixelFormat::Mono16Be
+            | PixelFormat::Bayer16
+            | PixelFormat::Rgb48Le => 16,
+        }
+    }
+
+    pub fn channels(&self) -> u32 {
+        match self {
+            PixelFormat::Rgb24 | PixelFormat::Rgb48Le => 3,
+            _ => 1,
+        }
+    }
+
+    pub fn is_bayer(&self) -> bool {
+        matches!(
+            self,
+            PixelFormat::Bayer8 | PixelFormat::Bayer12Packed | PixelFormat::Bayer16
+        )
+    }
+
+    /// Payload size in bytes of a `width` x `height` frame, rounded up.
+    pub fn payload_len(&self, width: u32, height: u32) -> u64 {
+        (width as u64 * height as u64)
+            .saturating_mul(self.bits_per_pixel() as u64)
+            .div_ceil(8)
+    }
+}
+
+/// Payload encoding declared in [`FrameHeader::encoding`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameEncoding {
+    /// Uncompressed pixels.
+    #[default]
+    Raw,
+    /// Zstandard, level 3. Needs the `zstd` feature.
+    Zstd,
+    /// LZ4 block format. Needs the `lz4` feature.
+    Lz4,
+    /// Lossless Rice coding of 8- or 16-bit samples, as used by fpack.
+    Rice,
+}
+
+impl FrameEncoding {
+    pub fn is_raw(&self) -> bool {
+        matches!(self, FrameEncoding::Raw)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FrameHeader {
+    /// Header schema version, see [`FRAME_HEADER_VERSION`].
+    #[serde(default)]
+    pub version: u16,
     /// The `Command.id` of the originating expose command. The server uses
     /// this to pair the frame with the device-state snapshot it took at
     /// exposure start.
@@ -24,11 +288,393 @@ pub struct FrameHeader {
     pub width: u32,
     pub height: u32,
     pub bit_depth: u8,
-    /// Bayer pattern, e.g. `"RGGB"`. None for mono sensors.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub bayer: Option<String>,
+    /// Bayer pattern, e.g. `"RGGB"`. None for mono sensors; unrecognised
+    /// values such as `"none"` also read as None.
+    #[serde(
+        default,
+        deserialize_with = "lenient_bayer",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub bayer: Option<BayerPattern>,
+    /// Payload layout. When absent, the payload is `bit_depth` bits per pixel.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pixel_format: Option<PixelFormat>,
+    /// Payload compression. Absent means `raw`.
+    #[serde(default, skip_serializing_if = "FrameEncoding::is_raw")]
+    pub encoding: FrameEncoding,
+    /// Size of the compressed payload in bytes when `encoding` is not `raw`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoded_len: Option<u64>,
     /// Shutter-open timestamp, nanoseconds since unix epoch. Driver-supplied.
     pub timestamp_ns: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exposure_secs: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gain: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bin_x: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bin_y: Option<u16>,
+    /// Sensor x of the ROI's top-left pixel, in unbinned pixels.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roi_x: Option<u32>,
+    /// Sensor y of the ROI's top-left pixel, in unbinned pixels.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roi_y: Option<u32>,
+    /// Sensor temperature at exposure start, degrees Celsius.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sensor_temp_c: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame_type: Option<FrameType>,
+    /// Name of the filter in the light path, e.g. `"Ha"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+    /// Unbinned pixel width, micrometres.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pixel_size_x_um: Option<f64>,
+    /// Unbinned pixel height, micrometres.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pixel_size_y_um: Option<f64>,
+}
+
+impl Default for FrameHeader {
+    fn default() -> Self {
+        Self {
+            version: FRAME_HEADER_VERSION,
+            correlation_id: Uuid::nil(),
+            driver_id: Uuid::nil(),
+            width: 0,
+            height: 0,
+            bit_depth: 0,
+            bayer: None,
+            pixel_format: None,
+            encoding: FrameEncoding::Raw,
+            encoded_len: None,
+            timestamp_ns: 0,
+            exposure_secs: None,
+            gain: None,
+            offset: None,
+            bin_x: None,
+            bin_y: None,
+            roi_x: None,
+            roi_y: None,
+            sensor_temp_c: None,
+            frame_type: None,
+            filter: None,
+            pixel_size_x_um: None,
+            pixel_size_y_um: None,
+        }
+    }
+}
+
+impl FrameHeader {
+    /// Expected raw payload size in bytes: from `pixel_format` if set,
+    /// otherwise width * height * bit_depth / 8, rounded up.
+    pub fn payload_len(&self) -> u64 {
+        match self.pixel_format {
+            Some(format) => format.payload_len(self.width, self.height),
+            None => (self.width as u64 * self.height as u64)
+                .saturating_mul(self.bit_depth as u64)
+                .div_ceil(8),
+        }
+    }
+
+    /// Bits carrying data in each sample: from `pixel_format` if set,
+    /// otherwise `bit_depth`.
+    pub fn significant_bits(&self) -> u32 {
+        self.pixel_format
+            .map_or(self.bit_depth as u32, |f| f.significant_bits())
+    }
+
+    /// Samples per pixel: 3 for interleaved RGB, 1 otherwise.
+    pub fn channels(&self) -> u32 {
+        self.pixel_format.map_or(1, |f| f.channels())
+    }
+
+    /// Bytes following the header on the wire: `payload_len()` for raw
+    /// frames, `encoded_len` for compressed ones.
+    pub fn wire_len(&self) -> u64 {
+        match self.encoding {
+            FrameEncoding::Raw => self.payload_len(),
+            _ => self.encoded_len.unwrap_or(0),
+        }
+    }
+
+    /// Bytes per sample for Rice coding, `None` for layouts Rice cannot
+    /// handle (packed or odd-sized samples).
+    fn rice_sample_bytes(&self) -> Option<usize> {
+        let channels = self.pixel_format.map_or(1, |f| f.channels()) as u64;
+        let samples = self.width as u64 * self.height as u64 * channels;
+        if matches!(
+            self.pixel_format,
+            Some(PixelFormat::Mono12Packed | PixelFormat::Bayer12Packed)
+        ) || samples == 0
+        {
+            return None;
+        }
+        let len = self.payload_len();
+        match len / samples {
+            1 | 2 if len.is_multiple_of(samples) => Some((len / samples) as usize),
+            _ => None,
+        }
+    }
+}
+
+/// Unpack a raw payload into one `u16` per sample (channels interleaved),
+/// right-aligned to [`FrameHeader::significant_bits`]. 12-bit packed data
+/// uses the GenICam layout: `p0[11:4]`, `p1[3:0] << 4 | p0[3:0]`, `p1[11:4]`.
+/// Without a `pixel_format`, `bit_depth` 1..=8 means bytes, 12 means packed
+/// and 9..=16 means little-endian words.
+pub fn unpack_samples(header: &FrameHeader, data: &[u8]) -> Result<Vec<u16>, FrameError> {
+    let mut samples = Vec::with_capacity(raw_sample_count(header, data)?);
+    for_each_sample(header, data, |s| samples.push(s))?;
+    Ok(samples)
+}
+
+/// Like [`unpack_samples`], but hands each sample to `f` in order instead
+/// of collecting them, so callers can reduce a frame without holding a
+/// second full-size copy.
+pub fn for_each_sample(
+    header: &FrameHeader,
+    data: &[u8],
+    mut f: impl FnMut(u16),
+) -> Result<(), FrameError> {
+    let count = raw_sample_count(header, data)?;
+    match (header.pixel_format, header.bit_depth) {
+        (Some(PixelFormat::Mono8 | PixelFormat::Bayer8 | PixelFormat::Rgb24), _)
+        | (None, 1..=8) => data.iter().for_each(|&b| f(b as u16)),
+        (Some(PixelFormat::Mono12Packed | PixelFormat::Bayer12Packed), _) | (None, 12) => {
+            for (i, b) in data.chunks(3).enumerate() {
+                let (b0, b1, b2) = (
+                    b[0] as u16,
+                    *b.get(1).unwrap_or(&0) as u16,
+                    *b.get(2).unwrap_or(&0) as u16,
+                );
+                f(b0 << 4 | (b1 & 0x0f));
+                if 2 * i + 1 < count {
+                    f(b2 << 4 | b1 >> 4);
+                }
+            }
+        }
+        (Some(PixelFormat::Mono16Be), _) => data
+            .chunks_exact(2)
+            .for_each(|b| f(u16::from_be_bytes([b[0], b[1]]))),
+        (Some(PixelFormat::Mono12MsbLe), _) => data
+            .chunks_exact(2)
+            .for_each(|b| f(u16::from_le_bytes([b[0], b[1]]) >> 4)),
+        (Some(_), _) | (None, 9..=16) => data
+            .chunks_exact(2)
+            .for_each(|b| f(u16::from_le_bytes([b[0], b[1]]))),
+        (None, _) => return Err(FrameError::PixelLayout),
+    }
+    Ok(())
+}
+
+/// Number of samples in a raw payload, once its length matches the header.
+fn raw_sample_count(header: &FrameHeader, data: &[u8]) -> Result<usize, FrameError> {
+    if !header.encoding.is_raw() {
+        return Err(FrameError::Unsupported(header.encoding));
+    }
+    let expected = header.payload_len();
+    if expected != data.len() as u64 {
+        return Err(FrameError::PayloadLength {
+            expected,
+            actual: data.len() as u64,
+        });
+    }
+    Ok(header.width as usize * header.height as usize * header.channels() as usize)
+}
+
+/// Compress a raw payload with `encoding`, updating `header.encoding` and
+/// `header.encoded_len` to match the returned bytes.
+pub fn compress_payload(
+    header: &mut FrameHeader,
+    data: &[u8],
+    encoding: FrameEncoding,
+) -> Result<Vec<u8>, FrameError> {
+    if !header.encoding.is_raw() {
+        return Err(FrameError::Unsupported(header.encoding));
+    }
+    let expected = header.payload_len();
+    if expected != data.len() as u64 {
+        return Err(FrameError::PayloadLength {
+            expected,
+            actual: data.len() as u64,
+        });
+    }
+    let encoded = match encoding {
+        FrameEncoding::Raw => return Ok(data.to_vec()),
+        #[cfg(feature = "zstd")]
+        FrameEncoding::Zstd => zstd::bulk::compress(data, 3)?,
+        #[cfg(feature = "lz4")]
+        FrameEncoding::Lz4 => lz4_flex::block::compress(data),
+        FrameEncoding::Rice => {
+            let big_endian = header.pixel_format == Some(PixelFormat::Mono16Be);
+            match header.rice_sample_bytes() {
+                Some(1) => crate::rice::encode_u8(data),
+                Some(2) => crate::rice::encode_u16(data, big_endian),
+                _ => return Err(FrameError::Unsupported(encoding)),
+            }
+        }
+        #[allow(unreachable_patterns)]
+        _ => return Err(FrameError::Unsupported(encoding)),
+    };
+    header.encoding = encoding;
+    header.encoded_len = Some(encoded.len() as u64);
+    Ok(encoded)
+}
+
+/// Decompress a payload according to `header.encoding`, resetting the header
+/// to `raw`. Raw payloads are returned as-is. Frames whose decoded payload
+/// would exceed `max_payload_len` bytes are rejected before anything is
+/// allocated.
+pub fn decompress_payload(
+    header: &mut FrameHeader,
+    data: &[u8],
+    max_payload_len: u64,
+) -> Result<Vec<u8>, FrameError> {
+    let expected = header.payload_len();
+    if expected > max_payload_len {
+        return Err(FrameError::TooLarge {
+            len: expected,
+            max: max_payload_len,
+        });
+    }
+    let encoding = header.encoding;
+    let decoded = match encoding {
+        FrameEncoding::Raw if expected != data.len() as u64 => {
+            return Err(FrameError::PayloadLength {
+                expected,
+                actual: data.len() as u64,
+            })
+        }
+        FrameEncoding::Raw => data.to_vec(),
+        #[cfg(feature = "zstd")]
+        FrameEncoding::Zstd => {
+            zstd::bulk::decompress(data, expected as usize).map_err(|_| FrameError::Codec)?
+        }
+        #[cfg(feature = "lz4")]
+        FrameEncoding::Lz4 => {
+            lz4_flex::block::decompress(data, expected as usize).map_err(|_| FrameError::Codec)?
+        }
+        FrameEncoding::Rice => {
+            let big_endian = header.pixel_format == Some(PixelFormat::Mono16Be);
+            let decoded = match header.rice_sample_bytes() {
+                Some(1) => crate::rice::decode_u8(data, expected as usize),
+                Some(2) => crate::rice::decode_u16(data, expected as usize / 2, big_endian),
+                _ => return Err(FrameError::Unsupported(encoding)),
+            };
+            decoded.ok_or(FrameError::Codec)?
+        }
+        #[allow(unreachable_patterns)]
+        _ => return Err(FrameError::Unsupported(encoding)),
+    };
+    if decoded.len() as u64 != expected {
+        return Err(FrameError::PayloadLength {
+            expected,
+            actual: decoded.len() as u64,
+        });
+    }
+    header.encoding = FrameEncoding::Raw;
+    header.encoded_len = None;
+    Ok(decoded)
+}
+
+fn header_bytes(header: &FrameHeader, data_len: usize) -> Result<Vec<u8>, FrameError> {
+    let expected = header.wire_len();
+    if expected != data_len as u64 {
+        return Err(FrameError::PayloadLength {
+            expected,
+            actual: data_len as u64,
+        });
+    }
+    let json = serde_json::to_vec(header).map_err(|_| FrameError::InvalidHeader)?;
+    if json.len() > MAX_HEADER_LEN as usize {
+        return Err(FrameError::HeaderTooLarge(json.len() as u32));
+    }
+    Ok(json)
+}
+
+fn parse_header(json: &[u8]) -> Result<FrameHeader, FrameError> {
+    serde_json::from_slice(json).map_err(|_| FrameError::InvalidHeader)
+}
+
+fn check_header_len(len: u32) -> Result<usize, FrameError> {
+    if len > MAX_HEADER_LEN {
+        return Err(FrameError::HeaderTooLarge(len));
+    }
+    Ok(len as usize)
+}
+
+/// Encode `header` and `data` into a single length-prefixed buffer.
+pub fn encode_frame(header: &FrameHeader, data: &[u8]) -> Result<Vec<u8>, FrameError> {
+    let json = header_bytes(header, data.len())?;
+    let mut out = Vec::with_capacity(4 + json.len() + data.len());
+    out.extend_from_slice(&(json.len() as u32).to_be_bytes());
+    out.extend_from_slice(&json);
+    out.extend_from_slice(data);
+    Ok(out)
+}
+
+/// Decode a buffer produced by [`encode_frame`]. The buffer must hold
+/// exactly one frame.
+pub fn decode_frame(buf: &[u8]) -> Result<(FrameHeader, &[u8]), FrameError> {
+    let prefix: [u8; 4] = buf
+        .get(..4)
+        .ok_or(FrameError::Truncated)?
+        .try_into()
+        .unwrap();
+    let header_len = check_header_len(u32::from_be_bytes(prefix))?;
+    let json = buf.get(4..4 + header_len).ok_or(FrameError::Truncated)?;
+    let header = parse_header(json)?;
+    let data = &buf[4 + header_len..];
+    let expected = header.wire_len();
+    if expected != data.len() as u64 {
+        return Err(FrameError::PayloadLength {
+            expected,
+            actual: data.len() as u64,
+        });
+    }
+    Ok((header, data))
+}
+
+/// Write one frame to `writer`.
+pub fn write_frame<W: Write>(
+    writer: &mut W,
+    header: &FrameHeader,
+    data: &[u8],
+) -> Result<(), FrameError> {
+    let json = header_bytes(header, data.len())?;
+    writer.write_all(&(json.len() as u32).to_be_bytes())?;
+    writer.write_all(&json)?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+/// Read one frame from `reader`. Frames whose payload would exceed
+/// `max_payload_len` bytes are rejected before anything is allocated.
+pub fn read_frame<R: Read>(
+    reader: &mut R,
+    max_payload_len: u64,
+) -> Result<(FrameHeader, Vec<u8>), FrameError> {
+    let mut prefix = [0u8; 4];
+    reader.read_exact(&mut prefix)?;
+    let header_len = check_header_len(u32::from_be_bytes(prefix))?;
+    let mut json = vec![0u8; header_len];
+    reader.read_exact(&mut json)?;
+    let header = parse_header(&json)?;
+    let expected = header.wire_len();
+    if expected > max_payload_len {
+        return Err(FrameError::TooLarge {
+            len: expected,
+            max: max_payload_len,
+        });
+    }
+    let mut data = vec![0u8; expected as usize];
+    reader.read_exact(&mut data)?;
+    Ok((header, data))
 }
 
 #[cfg(test)]
@@ -43,13 +689,15 @@ mod tests {
             width: 6248,
             height: 4176,
             bit_depth: 16,
-            bayer: Some("RGGB".into()),
+            bayer: Some(BayerPattern::Rggb),
             timestamp_ns: 1_700_000_000_000_000_000,
+            ..Default::default()
         };
         let json = serde_json::to_string(&h).unwrap();
         let back: FrameHeader = serde_json::from_str(&json).unwrap();
         assert_eq!(back.width, 6248);
-        assert_eq!(back.bayer.as_deref(), Some("RGGB"));
+        assert!(json.contains(r#""bayer":"RGGB""#));
+        assert_eq!(back.bayer, Some(BayerPattern::Rggb));
     }
 
     #[test]
@@ -62,8 +710,394 @@ mod tests {
             bit_depth: 16,
             bayer: None,
             timestamp_ns: 0,
+            ..Default::default()
         };
         let json = serde_json::to_string(&h).unwrap();
         assert!(!json.contains("bayer"));
+        assert!(!json.contains("gain"));
+    }
+
+    #[test]
+    fn science_metadata_roundtrip() {
+        let h = FrameHeader {
+            width: 6248,
+            height: 4176,
+            bit_depth: 16,
+            exposure_secs: Some(300.0),
+            gain: Some(56.0),
+            offset: Some(30),
+            bin_x: Some(2),
+            bin_y: Some(2),
+            roi_x: Some(100),
+            roi_y: Some(200),
+            sensor_temp_c: Some(-10.0),
+            frame_type: Some(FrameType::Dark),
+            filter: Some("Ha".into()),
+            pixel_size_x_um: Some(3.76),
+            pixel_size_y_um: Some(3.76),
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&h).unwrap();
+        assert!(json.contains(r#""frame_type":"dark""#));
+        assert!(json.contains(r#""version":1"#));
+        let back: FrameHeader = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.version, FRAME_HEADER_VERSION);
+        assert_eq!(back.exposure_secs, Some(300.0));
+        assert_eq!(back.frame_type, Some(FrameType::Dark));
+        assert_eq!(back.filter.as_deref(), Some("Ha"));
+        assert_eq!(back.roi_y, Some(200));
+    }
+
+    #[test]
+    fn legacy_header_without_metadata_deserializes() {
+        let json = format!(
+            r#"{{"correlation_id":"{id}","driver_id":"{id}","width":1280,"height":960,"bit_depth":16,"bayer":"RGGB","timestamp_ns":5}}"#,
+            id = Uuid::nil()
+        );
+        let h: FrameHeader = serde_json::from_str(&json).unwrap();
+        assert_eq!(h.version, 0);
+        assert_eq!(h.width, 1280);
+        assert_eq!(h.bayer, Some(BayerPattern::Rggb));
+        assert!(h.pixel_format.is_none());
+        assert!(h.exposure_secs.is_none());
+        assert!(h.frame_type.is_none());
+        assert!(h.filter.is_none());
+    }
+
+    fn header(width: u32, height: u32, bit_depth: u8) -> FrameHeader {
+        FrameHeader {
+            correlation_id: Uuid::now_v7(),
+            driver_id: Uuid::now_v7(),
+            width,
+            height,
+            bit_depth,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn payload_len_rounds_up() {
+        assert_eq!(header(6248, 4176, 16).payload_len(), 6248 * 4176 * 2);
+        assert_eq!(header(3, 1, 12).payload_len(), 5);
+        assert_eq!(header(4, 4, 8).payload_len(), 16);
+    }
+
+    #[test]
+    fn bayer_pattern_wire_names() {
+        for (pattern, name) in [
+            (BayerPattern::Rggb, "RGGB"),
+            (BayerPattern::Grbg, "GRBG"),
+            (BayerPattern::Gbrg, "GBRG"),
+            (BayerPattern::Bggr, "BGGR"),
+        ] {
+            assert_eq!(
+                serde_json::to_string(&pattern).unwrap(),
+                format!("\"{name}\"")
+            );
+            assert_eq!(pattern.to_string(), name);
+        }
+        let p: BayerPattern = serde_json::from_str("\"bggr\"").unwrap();
+        assert_eq!(p, BayerPattern::Bggr);
+    }
+
+    #[test]
+    fn header_bayer_is_lenient() {
+        let id = Uuid::nil();
+        let parse = |bayer: &str| {
+            let json = format!(
+                r#"{{"correlation_id":"{id}","driver_id":"{id}","width":1,"height":1,"bit_depth":16,"bayer":{bayer},"timestamp_ns":5}}"#
+            );
+            serde_json::from_str::<FrameHeader>(&json).unwrap().bayer
+        };
+        assert_eq!(parse(r#""GRBG ""#), Some(BayerPattern::Grbg));
+        assert_eq!(parse(r#""gbrg""#), Some(BayerPattern::Gbrg));
+        assert_eq!(parse(r#""none""#), None);
+        assert_eq!(parse(r#"" ""#), None);
+        assert_eq!(parse("null"), None);
+    }
+
+    #[test]
+    fn bayer_channel_lookup() {
+        let p = BayerPattern::Rggb;
+        assert_eq!(
+            [
+                p.channel_at(0, 0),
+                p.channel_at(1, 0),
+                p.channel_at(0, 1),
+                p.channel_at(1, 1)
+            ],
+            [0, 1, 1, 2]
+        );
+        let p = BayerPattern::Gbrg;
+        assert_eq!(
+            [
+                p.channel_at(2, 2),
+                p.channel_at(3, 2),
+                p.channel_at(2, 3),
+                p.channel_at(3, 3)
+            ],
+            [1, 2, 0, 1]
+        );
+    }
+
+    #[test]
+    fn pixel_format_sizes() {
+        assert_eq!(PixelFormat::Mono8.payload_len(10, 10), 100);
+        assert_eq!(PixelFormat::Mono12Packed.payload_len(3, 1), 5);
+        assert_eq!(PixelFormat::Mono12MsbLe.payload_len(10, 10), 200);
+        assert_eq!(
+            PixelFormat::Bayer16.payload_len(6248, 4176),
+            6248 * 4176 * 2
+        );
+        assert_eq!(PixelFormat::Rgb24.payload_len(2, 2), 12);
+        assert_eq!(PixelFormat::Rgb48Le.payload_len(2, 2), 24);
+        assert_eq!(PixelFormat::Mono12Le.significant_bits(), 12);
+        assert!(PixelFormat::Bayer12Packed.is_bayer());
+        assert_eq!(PixelFormat::Rgb24.channels(), 3);
+        assert_eq!(
+            serde_json::to_string(&PixelFormat::Mono16Le).unwrap(),
+            r#""Mono16LE""#
+        );
+        assert_eq!(
+            serde_json::to_string(&PixelFormat::Rgb24).unwrap(),
+            r#""RGB24""#
+        );
+    }
+
+    #[test]
+    fn payload_len_follows_pixel_format() {
+        let mut h = header(3, 1, 12);
+        h.pixel_format = Some(PixelFormat::Mono12Le);
+        assert_eq!(h.payload_len(), 6);
+        h.pixel_format = Some(PixelFormat::Mono12Packed);
+        assert_eq!(h.payload_len(), 5);
+    }
+
+    #[test]
+    fn encode_decode_roundtrip() {
+        let h = header(4, 3, 16);
+        let data: Vec<u8> = (0..24).collect();
+        let buf = encode_frame(&h, &data).unwrap();
+        let header_len = u32::from_be_bytes(buf[..4].try_into().unwrap()) as usize;
+        assert_eq!(buf.len(), 4 + header_len + 24);
+        let (back, payload) = decode_frame(&buf).unwrap();
+        assert_eq!(back.correlation_id, h.correlation_id);
+        assert_eq!(payload, &data[..]);
+    }
+
+    #[test]
+    fn encode_rejects_wrong_payload_length() {
+        let h = header(4, 3, 16);
+        assert!(matches!(
+            encode_frame(&h, &[0; 23]),
+            Err(FrameError::PayloadLength {
+                expected: 24,
+                actual: 23
+            })
+        ));
+    }
+
+    #[test]
+    fn decode_rejects_bad_input() {
+        let h = header(2, 2, 8);
+        let buf = encode_frame(&h, &[1, 2, 3, 4]).unwrap();
+        assert!(matches!(
+            decode_frame(&buf[..2]),
+            Err(FrameError::Truncated)
+        ));
+        assert!(matches!(
+            decode_frame(&buf[..10]),
+            Err(FrameError::Truncated)
+        ));
+        assert!(matches!(
+            decode_frame(&buf[..buf.len() - 1]),
+            Err(FrameError::PayloadLength {
+                expected: 4,
+                actual: 3
+            })
+        ));
+        let mut extra = buf.clone();
+        extra.push(0);
+        assert!(matches!(
+            decode_frame(&extra),
+            Err(FrameError::PayloadLength { .. })
+        ));
+
+        let mut huge = (MAX_HEADER_LEN + 1).to_be_bytes().to_vec();
+        huge.extend_from_slice(&buf[4..]);
+        assert!(matches!(
+            decode_frame(&huge),
+            Err(FrameError::HeaderTooLarge(_))
+        ));
+
+        let mut garbage = 3u32.to_be_bytes().to_vec();
+        garbage.extend_from_slice(b"{x}");
+        assert!(matches!(
+            decode_frame(&garbage),
+            Err(FrameError::InvalidHeader)
+        ));
+    }
+
+    fn gradient_u16(width: u32, height: u32) -> (FrameHeader, Vec<u8>) {
+        let mut h = header(width, height, 16);
+        h.pixel_format = Some(PixelFormat::Mono16Le);
+        let data = (0..width * height)
+            .flat_map(|i| (1000 + (i % width) as u16 + ((i * 7919) % 13) as u16).to_le_bytes())
+            .collect();
+        (h, data)
+    }
+
+    fn compression_roundtrip(encoding: FrameEncoding) {
+        let (mut h, data) = gradient_u16(64, 48);
+        let packed = compress_payload(&mut h, &data, encoding).unwrap();
+        assert_eq!(h.encoding, encoding);
+        assert_eq!(h.encoded_len, Some(packed.len() as u64));
+        assert!(packed.len() < data.len());
+
+        let wire = encode_frame(&h, &packed).unwrap();
+        let (mut back, payload) = decode_frame(&wire).unwrap();
+        let raw = decompress_payload(&mut back, payload, MAX_PAYLOAD_LEN).unwrap();
+        assert_eq!(raw, data);
+        assert!(back.encoding.is_raw());
+        assert!(back.encoded_len.is_none());
+    }
+
+    #[test]
+    fn rice_compression_roundtrip() {
+        compression_roundtrip(FrameEncoding::Rice);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_compression_roundtrip() {
+        compression_roundtrip(FrameEncoding::Zstd);
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn lz4_compression_roundtrip() {
+        compression_roundtrip(FrameEncoding::Lz4);
+    }
+
+    #[test]
+    fn rice_handles_8_bit_and_big_endian() {
+        let mut h = header(10, 10, 8);
+        let data: Vec<u8> = (0..100).map(|i| (i / 3) as u8).collect();
+        let packed = compress_payload(&mut h, &data, FrameEncoding::Rice).unwrap();
+        assert_eq!(
+            decompress_payload(&mut h, &packed, MAX_PAYLOAD_LEN).unwrap(),
+            data
+        );
+
+        let mut h = header(10, 10, 16);
+        h.pixel_format = Some(PixelFormat::Mono16Be);
+        let data: Vec<u8> = (0..100u16).flat_map(|i| (i * 300).to_be_bytes()).collect();
+        let packed = compress_payload(&mut h, &data, FrameEncoding::Rice).unwrap();
+        assert_eq!(
+            decompress_payload(&mut h, &packed, MAX_PAYLOAD_LEN).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn rice_rejects_packed_pixels() {
+        let mut h = header(4, 2, 12);
+        h.pixel_format = Some(PixelFormat::Mono12Packed);
+        assert!(matches!(
+            compress_payload(&mut h, &[0; 12], FrameEncoding::Rice),
+            Err(FrameError::Unsupported(FrameEncoding::Rice))
+        ));
+        assert!(h.encoding.is_raw());
+    }
+
+    #[test]
+    fn corrupt_compressed_payload_fails() {
+        let (mut h, data) = gradient_u16(16, 16);
+        let packed = compress_payload(&mut h, &data, FrameEncoding::Rice).unwrap();
+        assert!(decompress_payload(&mut h, &packed[..packed.len() / 2], MAX_PAYLOAD_LEN).is_err());
+    }
+
+    #[test]
+    fn decompress_rejects_oversized_frames() {
+        let (mut h, data) = gradient_u16(16, 16);
+        let packed = compress_payload(&mut h, &data, FrameEncoding::Rice).unwrap();
+        assert!(matches!(
+            decompress_payload(&mut h, &packed, 511),
+            Err(FrameError::TooLarge { len: 512, max: 511 })
+        ));
+
+        // A tiny payload claiming a huge frame fails without allocating it.
+        let mut h = header(u32::MAX, u32::MAX, 16);
+        h.encoding = FrameEncoding::Rice;
+        assert!(decompress_payload(&mut h, &packed, u64::MAX).is_err());
+        assert!(!h.encoding.is_raw());
+    }
+
+    #[test]
+    fn raw_headers_omit_encoding() {
+        let json = serde_json::to_string(&header(1, 1, 8)).unwrap();
+        assert!(!json.contains("encoding"));
+        assert!(!json.contains("encoded_len"));
+    }
+
+    #[test]
+    fn unpacks_samples() {
+        let mut h = header(3, 1, 12);
+        h.pixel_format = Some(PixelFormat::Mono12Packed);
+        let packed = [0xab, 0x4c, 0xde, 0x12, 0x03];
+        assert_eq!(unpack_samples(&h, &packed).unwrap(), [0xabc, 0xde4, 0x123]);
+
+        h.pixel_format = Some(PixelFormat::Mono12MsbLe);
+        assert_eq!(
+            unpack_samples(&h, &[0x10, 0xff, 0x00, 0x80, 0xf0, 0x00]).unwrap(),
+            [0xff1, 0x800, 0x00f]
+        );
+
+        h.pixel_format = Some(PixelFormat::Mono16Be);
+        assert_eq!(
+            unpack_samples(&h, &[1, 2, 3, 4, 5, 6]).unwrap(),
+            [0x0102, 0x0304, 0x0506]
+        );
+
+        let h = header(1, 1, 24);
+        assert!(matches!(
+            unpack_samples(&h, &[0; 3]),
+            Err(FrameError::PixelLayout)
+        ));
+    }
+
+    #[test]
+    fn stream_roundtrip() {
+        let a = header(2, 2, 16);
+        let b = header(3, 1, 8);
+        let mut stream = Vec::new();
+        write_frame(&mut stream, &a, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        write_frame(&mut stream, &b, &[9, 10, 11]).unwrap();
+
+        let mut reader = &stream[..];
+        let (ha, da) = read_frame(&mut reader, 1024).unwrap();
+        let (hb, db) = read_frame(&mut reader, 1024).unwrap();
+        assert_eq!(ha.correlation_id, a.correlation_id);
+        assert_eq!(da, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(hb.correlation_id, b.correlation_id);
+        assert_eq!(db, vec![9, 10, 11]);
+        assert!(matches!(
+            read_frame(&mut reader, 1024),
+            Err(FrameError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn read_rejects_payload_over_limit() {
+        let h = header(100, 100, 16);
+        let mut stream = Vec::new();
+        write_frame(&mut stream, &h, &vec![0; 20_000]).unwrap();
+        assert!(matches!(
+            read_frame(&mut &stream[..], 10_000),
+            Err(FrameError::TooLarge {
+                len: 20_000,
+                max: 10_000
+            })
+        ));
     }
 }
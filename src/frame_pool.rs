@@ -0,0 +1,259 @@
+//! Bounded pool of reusable frame buffers.
+//!
+//! Readout threads borrow a [`FrameBuffer`] from a [`FramePool`], fill it
+//! and send it down the `ReadingOut` channel; dropping the buffer hands it
+//! back. With frames of 50+ MB this avoids an allocation per frame and caps
+//! frame memory at `buffers * buffer_len`.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::time::Duration;
+
+/// Pool usage counters, see [`FramePool::metrics`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolMetrics {
+    /// Buffers owned by the pool.
+    pub capacity: usize,
+    /// Buffers ready to be borrowed.
+    pub available: usize,
+    /// Buffers currently borrowed.
+    pub in_use: usize,
+    /// Most buffers borrowed at once.
+    pub peak_in_use: usize,
+    /// Successful borrows.
+    pub acquired: u64,
+    /// Borrows that found the pool empty (and failed or had to wait).
+    pub exhausted: u64,
+}
+
+struct State {
+    free: Vec<Vec<u8>>,
+    metrics: PoolMetrics,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    returned: Condvar,
+}
+
+impl Inner {
+    fn give_back(&self, mut buf: Vec<u8>) {
+        buf.clear();
+        let mut state = self.state.lock().unwrap();
+        state.free.push(buf);
+        state.metrics.available += 1;
+        state.metrics.in_use -= 1;
+        self.returned.notify_one();
+    }
+}
+
+/// Cloning shares the pool.
+#[derive(Clone)]
+pub struct FramePool {
+    inner: Arc<Inner>,
+}
+
+impl FramePool {
+    /// A pool of `buffers` buffers of `buffer_len` bytes capacity each.
+    pub fn new(buffers: usize, buffer_len: usize) -> Self {
+        let free = (0..buffers)
+            .map(|_| Vec::with_capacity(buffer_len))
+            .collect();
+        Self {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State {
+                    free,
+                    metrics: PoolMetrics {
+                        capacity: buffers,
+                        available: buffers,
+                        ..Default::default()
+                    },
+                }),
+                returned: Condvar::new(),
+            }),
+        }
+    }
+
+    fn take(&self, state: &mut State) -> Option<FrameBuffer> {
+        let data = state.free.pop()?;
+        let m = &mut state.metrics;
+        m.available -= 1;
+        m.in_use += 1;
+        m.peak_in_use = m.peak_in_use.max(m.in_use);
+        m.acquired += 1;
+        Some(FrameBuffer {
+            data,
+            pool: Arc::downgrade(&self.inner),
+        })
+    }
+
+    /// Borrow an empty buffer, or `None` if all are in use.
+    pub fn try_get(&self) -> Option<FrameBuffer> {
+        let mut state = self.inner.state.lock().unwrap();
+        let buf = self.take(&mut state);
+        if buf.is_none() {
+            state.metrics.exhausted += 1;
+        }
+        buf
+    }
+
+    /// Borrow an empty buffer, waiting up to `timeout` for one to be
+    /// returned.
+    pub fn get_timeout(&self, timeout: Duration) -> Option<FrameBuffer> {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.free.is_empty() {
+            state.metrics.exhausted += 1;
+            state = self
+                .inner
+                .returned
+                .wait_timeout_while(state, timeout, |s| s.free.is_empty())
+                .unwrap()
+                .0;
+        }
+        self.take(&mut state)
+    }
+
+    pub fn metrics(&self) -> PoolMetrics {
+        self.inner.state.lock().unwrap().metrics
+    }
+}
+
+impl fmt::Debug for FramePool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("FramePool").field(&self.metrics()).finish()
+    }
+}
+
+/// Frame bytes, returned to their pool on drop. Derefs to `Vec<u8>`, so it
+/// can be resized and filled like one. Buffers made with `From<Vec<u8>>`
+/// belong to no pool.
+pub struct FrameBuffer {
+    data: Vec<u8>,
+    pool: Weak<Inner>,
+}
+
+impl FrameBuffer {
+    /// Detach from the pool and keep the bytes. The pool shrinks by one.
+    pub fn into_vec(mut self) -> Vec<u8> {
+        if let Some(pool) = self.pool.upgrade() {
+            let mut state = pool.state.lock().unwrap();
+            state.metrics.in_use -= 1;
+            state.metrics.capacity -= 1;
+        }
+        self.pool = Weak::new();
+        std::mem::take(&mut self.data)
+    }
+
+    pub fn is_pooled(&self) -> bool {
+        self.pool.strong_count() > 0
+    }
+}
+
+impl From<Vec<u8>> for FrameBuffer {
+    fn from(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            pool: Weak::new(),
+        }
+    }
+}
+
+impl Deref for FrameBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.data
+    }
+}
+
+impl DerefMut for FrameBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+}
+
+impl PartialEq for FrameBuffer {
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data
+    }
+}
+
+impl fmt::Debug for FrameBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FrameBuffer({} bytes)", self.data.len())
+    }
+}
+
+impl Drop for FrameBuffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.upgrade() {
+            pool.give_back(std::mem::take(&mut self.data));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn buffers_are_reused() {
+        let pool = FramePool::new(2, 1024);
+        let mut a = pool.try_get().unwrap();
+        a.extend_from_slice(&[1, 2, 3]);
+        let ptr = a.as_ptr();
+        drop(a);
+
+        let b = pool.try_get().unwrap();
+        assert!(b.is_empty());
+        assert!(b.capacity() >= 1024);
+        assert_eq!(b.as_ptr(), ptr);
+        assert_eq!(
+            pool.metrics(),
+            PoolMetrics {
+                capacity: 2,
+                available: 1,
+                in_use: 1,
+                peak_in_use: 1,
+                acquired: 2,
+                exhausted: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn exhaustion_is_counted() {
+        let pool = FramePool::new(1, 16);
+        let held = pool.try_get().unwrap();
+        assert!(pool.try_get().is_none());
+        assert!(pool.get_timeout(Duration::from_millis(5)).is_none());
+        assert_eq!(pool.metrics().exhausted, 2);
+
+        let waiter = {
+            let pool = pool.clone();
+            thread::spawn(move || pool.get_timeout(Duration::from_secs(5)).is_some())
+        };
+        thread::sleep(Duration::from_millis(10));
+        drop(held);
+        assert!(waiter.join().unwrap());
+        assert_eq!(pool.metrics().in_use, 0);
+    }
+
+    #[test]
+    fn detached_and_orphaned_buffers() {
+        let pool = FramePool::new(2, 16);
+        let kept = pool.try_get().unwrap().into_vec();
+        assert!(kept.capacity() >= 16);
+        let m = pool.metrics();
+        assert_eq!((m.capacity, m.available, m.in_use), (1, 1, 0));
+
+        let orphan = pool.try_get().unwrap();
+        drop(pool);
+        assert!(!orphan.is_pooled());
+        drop(orphan);
+
+        assert!(!FrameBuffer::from(vec![1]).is_pooled());
+    }
+}
@@ -0,0 +1,585 @@
+//! Chunked frame transport over MQTT on `devices/{uuid}/frame`.
+//!
+//! A full-resolution frame (e.g. 6248x4176x16 bit, ~52 MB) does not fit in a
+//! single MQTT packet, so [`FrameChunker`] splits a [`FrameHeader`] plus the
+//! pixel buffer into numbered chunks and [`FrameAssembler`] puts them back
+//! together on the receiving side.
+//!
+//! Every chunk payload starts with a fixed 48-byte big-endian header:
+//!
+//! ```text
+//! offset  size  field
+//!      0     4  magic "LSFC"
+//!      4     1  version (1)
+//!      5     3  reserved, zero
+//!      8    16  transfer id (uuid, fresh per frame)
+//!     24     4  chunk index; 0 is the JSON FrameHeader, 1.. are pixel data
+//!     28     4  chunk count, header chunk included
+//!     32     8  total pixel bytes
+//!     40     4  pixel bytes per data chunk (the last one may be shorter)
+//!     44     4  CRC-32 (IEEE) of the chunk data
+//! ```
+//!
+//! followed by the chunk data.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::frame::{FrameHeader, MAX_HEADER_LEN};
+
+const MAGIC: &[u8; 4] = b"LSFC";
+const VERSION: u8 = 1;
+
+/// Size of the fixed header at the start of every chunk payload.
+pub const CHUNK_HEADER_LEN: usize = 48;
+
+/// Maximum MQTT packet size the runner accepts and sends: 10 MiB.
+pub const MAX_PACKET_SIZE: usize = 10 * 1024 * 1024;
+
+/// Largest pixel byte count per chunk that keeps a chunk payload within
+/// [`MAX_PACKET_SIZE`].
+pub const MAX_CHUNK_SIZE: usize = MAX_PACKET_SIZE - CHUNK_HEADER_LEN;
+
+/// Default pixel bytes per chunk: 1 MiB, well below [`MAX_PACKET_SIZE`].
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameTransportError {
+    /// Payload is not a valid chunk: bad magic, version, or inconsistent
+    /// fields.
+    Malformed,
+    /// Chunk data does not match its CRC-32.
+    Checksum { transfer_id: Uuid, index: u32 },
+    /// Frame exceeds `AssemblerConfig::max_frame_bytes`.
+    TooLarge { transfer_id: Uuid, total_len: u64 },
+    /// Accepting the frame would exceed `AssemblerConfig::max_buffered_bytes`.
+    MemoryLimit { transfer_id: Uuid },
+    /// Chunk 0 does not hold a valid `FrameHeader`.
+    InvalidHeader { transfer_id: Uuid },
+    /// No chunk arrived within `AssemblerConfig::timeout`; `missing` lists
+    /// the chunk indices never received.
+    Timeout {
+        transfer_id: Uuid,
+        missing: Vec<u32>,
+    },
+}
+
+/// Fixed header of a single chunk, see the module docs for the layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkHeader {
+    pub transfer_id: Uuid,
+    pub index: u32,
+    pub count: u32,
+    pub total_len: u64,
+    pub chunk_size: u32,
+    pub crc32: u32,
+}
+
+impl ChunkHeader {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&[VERSION, 0, 0, 0]);
+        out.extend_from_slice(self.transfer_id.as_bytes());
+        out.extend_from_slice(&self.index.to_be_bytes());
+        out.extend_from_slice(&self.count.to_be_bytes());
+        out.extend_from_slice(&self.total_len.to_be_bytes());
+        out.extend_from_slice(&self.chunk_size.to_be_bytes());
+        out.extend_from_slice(&self.crc32.to_be_bytes());
+    }
+
+    /// Parse a chunk payload into its header and data, verifying the CRC.
+    pub fn parse(payload: &[u8]) -> Result<(ChunkHeader, &[u8]), FrameTransportError> {
+        if payload.len() < CHUNK_HEADER_LEN || &payload[0..4] != MAGIC || payload[4] != VERSION {
+            return Err(FrameTransportError::Malformed);
+        }
+        let u32_at = |at: usize| u32::from_be_bytes(payload[at..at + 4].try_into().unwrap());
+        let header = ChunkHeader {
+            transfer_id: Uuid::from_slice(&payload[8..24])
+                .map_err(|_| FrameTransportError::Malformed)?,
+            index: u32_at(24),
+            count: u32_at(28),
+            total_len: u64::from_be_bytes(payload[32..40].try_into().unwrap()),
+            chunk_size: u32_at(40),
+            crc32: u32_at(44),
+        };
+        if header.index >= header.count || header.chunk_size == 0 {
+            return Err(FrameTransportError::Malformed);
+        }
+        let data = &payload[CHUNK_HEADER_LEN..];
+        if crc32(data) != header.crc32 {
+            return Err(FrameTransportError::Checksum {
+                transfer_id: header.transfer_id,
+                index: header.index,
+            });
+        }
+        Ok((header, data))
+    }
+}
+
+/// Splits frames into chunk payloads ready to publish on the frame topic.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameChunker {
+    chunk_size: usize,
+}
+
+impl Default for FrameChunker {
+    fn default() -> Self {
+        Self::new(DEFAULT_CHUNK_SIZE)
+    }
+}
+
+impl FrameChunker {
+    /// `chunk_size` is the number of pixel bytes per chunk; clamped to at
+    /// least 1 and at most [`MAX_CHUNK_SIZE`].
+    pub fn new(chunk_size: usize) -> Self {
+        Self {
+            chunk_size: chunk_size.clamp(1, MAX_CHUNK_SIZE),
+        }
+    }
+
+    /// Pixel bytes per chunk after clamping.
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Number of chunk payloads `data_len` pixel bytes are split into,
+    /// header chunk included.
+    pub fn chunk_count(&self, data_len: usize) -> u32 {
+        1 + data_len.div_ceil(self.chunk_size) as u32
+    }
+
+    /// Lazily produce the chunk payloads for one frame. Chunk 0 carries the
+    /// JSON-encoded `header`; the rest carry `data` in order.
+    pub fn chunks<'a>(
+        &self,
+        header: &FrameHeader,
+        data: &'a [u8],
+    ) -> Result<impl Iterator<Item = Vec<u8>> + 'a, FrameTransportError> {
+        let header_json = serde_json::to_vec(header).map_err(|_| FrameTransportError::Malformed)?;
+        if header_json.len() > MAX_HEADER_LEN as usize {
+            return Err(FrameTransportError::Malformed);
+        }
+        let transfer_id = Uuid::now_v7();
+        let count = self.chunk_count(data.len());
+        let chunk_size = self.chunk_size;
+        let build = move |index: u32, body: &[u8]| {
+            let mut out = Vec::with_capacity(CHUNK_HEADER_LEN + body.len());
+            ChunkHeader {
+                transfer_id,
+                index,
+                count,
+                total_len: data.len() as u64,
+                chunk_size: chunk_size as u32,
+                crc32: crc32(body),
+            }
+            .write(&mut out);
+            out.extend_from_slice(body);
+            out
+        };
+        let first = build(0, &header_json);
+        let rest = data
+            .chunks(chunk_size)
+            .enumerate()
+            .map(move |(i, body)| build(i as u32 + 1, body));
+        Ok(std::iter::once(first).chain(rest))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AssemblerConfig {
+    /// A partial frame is dropped if no chunk arrives for this long.
+    pub timeout: Duration,
+    /// Largest pixel payload accepted for a single frame.
+    pub max_frame_bytes: usize,
+    /// Upper bound for the pixel bytes of all partial frames held at once.
+    pub max_buffered_bytes: usize,
+}
+
+impl Default for AssemblerConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            max_frame_bytes: 256 * 1024 * 1024,
+            max_buffered_bytes: 512 * 1024 * 1024,
+        }
+    }
+}
+
+struct PartialFrame {
+    count: u32,
+    total_len: u64,
+    chunk_size: u32,
+    header: Option<FrameHeader>,
+    data: Vec<u8>,
+    received: Vec<bool>,
+    remaining: u32,
+    last_seen: Instant,
+}
+
+impl PartialFrame {
+    fn missing(&self) -> Vec<u32> {
+        (0..self.count)
+            .filter(|i| !self.received[*i as usize])
+            .collect()
+    }
+}
+
+/// Reassembles frames from chunk payloads. Several transfers can be in
+/// flight at once; chunks may arrive out of order or duplicated.
+#[derive(Default)]
+pub struct FrameAssembler {
+    config: AssemblerConfig,
+    pending: HashMap<Uuid, PartialFrame>,
+    buffered_bytes: usize,
+}
+
+impl FrameAssembler {
+    pub fn new(config: AssemblerConfig) -> Self {
+        Self {
+            config,
+            pending: HashMap::new(),
+            buffered_bytes: 0,
+        }
+    }
+
+    /// Pixel bytes currently held by partial frames.
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes
+    }
+
+    /// Number of frames currently being assembled.
+    pub fn in_flight(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Chunk indices not yet received for `transfer_id`.
+    pub fn missing(&self, transfer_id: Uuid) -> Option<Vec<u32>> {
+        self.pending.get(&transfer_id).map(PartialFrame::missing)
+    }
+
+    /// Feed one chunk payload received at `now`. Returns the frame once its
+    /// last chunk has arrived.
+    pub fn ingest(
+        &mut self,
+        payload: &[u8],
+        now: Instant,
+    ) -> Result<Option<(FrameHeader, Vec<u8>)>, FrameTransportError> {
+        let (chunk, body) = ChunkHeader::parse(payload)?;
+        let transfer_id = chunk.transfer_id;
+
+        if !self.pending.contains_key(&transfer_id) {
+            self.start(&chunk, now)?;
+        }
+        let frame = self.pending.get_mut(&transfer_id).unwrap();
+        if frame.count != chunk.count
+            || frame.total_len != chunk.total_len
+            || frame.chunk_size != chunk.chunk_size
+        {
+            return Err(FrameTransportError::Malformed);
+        }
+        frame.last_seen = now;
+        if frame.received[chunk.index as usize] {
+            return Ok(None);
+        }
+
+        if chunk.index == 0 {
+            let header = serde_json::from_slice(body)
+                .map_err(|_| FrameTransportError::InvalidHeader { transfer_id })?;
+            frame.header = Some(header);
+        } else {
+            let offset = (chunk.index as usize - 1) * frame.chunk_size as usize;
+            let end = offset + body.len();
+            let expected = (frame.total_len as usize - offset).min(frame.chunk_size as usize);
+            if body.len() != expected || end > frame.data.len() {
+                return Err(FrameTransportError::Malformed);
+            }
+            frame.data[offset..end].copy_from_slice(body);
+        }
+        frame.received[chunk.index as usize] = true;
+        frame.remaining -= 1;
+
+        if frame.remaining > 0 {
+            return Ok(None);
+        }
+        let frame = self.pending.remove(&transfer_id).unwrap();
+        self.buffered_bytes -= frame.data.len();
+        let header = frame.header.unwrap();
+        // The chunks must carry exactly the bytes the header describes.
+        if frame.data.len() as u64 != header.wire_len() {
+            return Err(FrameTransportError::Malformed);
+        }
+        Ok(Some((header, frame.data)))
+    }
+
+    /// Drop partial frames that have not seen a chunk within the timeout.
+    pub fn expire(&mut self, now: Instant) -> Vec<FrameTransportError> {
+        let timeout = self.config.timeout;
+        let expired: Vec<Uuid> = self
+            .pending
+            .iter()
+            .filter(|(_, f)| now.saturating_duration_since(f.last_seen) >= timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        expired
+            .into_iter()
+            .map(|transfer_id| {
+                let frame = self.pending.remove(&transfer_id).unwrap();
+                self.buffered_bytes -= frame.data.len();
+                FrameTransportError::Timeout {
+                    transfer_id,
+                    missing: frame.missing(),
+                }
+            })
+            .collect()
+    }
+
+    fn start(&mut self, chunk: &ChunkHeader, now: Instant) -> Result<(), FrameTransportError> {
+        let transfer_id = chunk.transfer_id;
+        if chunk.total_len > self.config.max_frame_bytes as u64 {
+            return Err(FrameTransportError::TooLarge {
+                transfer_id,
+                total_len: chunk.total_len,
+            });
+        }
+        let total_len = chunk.total_len as usize;
+        let expected_count = 1 + total_len.div_ceil(chunk.chunk_size as usize) as u64;
+        if expected_count != chunk.count as u64 {
+            return Err(FrameTransportError::Malformed);
+        }
+        if self.buffered_bytes + total_len > self.config.max_buffered_bytes {
+            return Err(FrameTransportError::MemoryLimit { transfer_id });
+        }
+        self.buffered_bytes += total_len;
+        self.pending.insert(
+            transfer_id,
+            PartialFrame {
+                count: chunk.count,
+                total_len: chunk.total_len,
+                chunk_size: chunk.chunk_size,
+                header: None,
+                data: vec![0; total_len],
+                received: vec![false; chunk.count as usize],
+                remaining: chunk.count,
+                last_seen: now,
+            },
+        );
+        Ok(())
+    }
+}
+
+/// CRC-32 (IEEE 802.3, reflected, polynomial 0xEDB88320).
+pub fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    !data.iter().fold(!0u32, |crc, b| {
+        TABLE[((crc ^ *b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(width: u32, height: u32) -> FrameHeader {
+        FrameHeader {
+            correlation_id: Uuid::now_v7(),
+            driver_id: Uuid::now_v7(),
+            width,
+            height,
+            bit_depth: 16,
+            ..Default::default()
+        }
+    }
+
+    fn pixels(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn crc32_known_vector() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn roundtrip_in_order() {
+        let h = header(100, 50);
+        let data = pixels(100 * 50 * 2);
+        let chunker = FrameChunker::new(1000);
+        let chunks: Vec<_> = chunker.chunks(&h, &data).unwrap().collect();
+        assert_eq!(chunks.len() as u32, chunker.chunk_count(data.len()));
+        assert_eq!(chunks.len(), 1 + 10);
+
+        let mut asm = FrameAssembler::default();
+        let now = Instant::now();
+        let (last, rest) = chunks.split_last().unwrap();
+        for c in rest {
+            assert!(asm.ingest(c, now).unwrap().is_none());
+        }
+        let (back_header, back_data) = asm.ingest(last, now).unwrap().unwrap();
+        assert_eq!(back_header.correlation_id, h.correlation_id);
+        assert_eq!(back_data, data);
+        assert_eq!(asm.in_flight(), 0);
+        assert_eq!(asm.buffered_bytes(), 0);
+    }
+
+    #[test]
+    fn chunk_size_is_clamped_to_packet_size() {
+        assert_eq!(FrameChunker::new(0).chunk_size(), 1);
+        assert_eq!(FrameChunker::new(usize::MAX).chunk_size(), MAX_CHUNK_SIZE);
+
+        let data = vec![0u8; MAX_CHUNK_SIZE + 1];
+        let chunks: Vec<_> = FrameChunker::new(usize::MAX)
+            .chunks(&header(1, 1), &data)
+            .unwrap()
+            .collect();
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|c| c.len() <= MAX_PACKET_SIZE));
+    }
+
+    #[test]
+    fn roundtrip_out_of_order_with_duplicates() {
+        let h = header(64, 64);
+        let data = pixels(64 * 64 * 2);
+        let mut chunks: Vec<_> = FrameChunker::new(777).chunks(&h, &data).unwrap().collect();
+        chunks.reverse();
+        chunks.insert(3, chunks[1].clone());
+
+        let mut asm = FrameAssembler::default();
+        let now = Instant::now();
+        let mut done = None;
+        for c in &chunks {
+            if let Some(frame) = asm.ingest(c, now).unwrap() {
+                done = Some(frame);
+            }
+        }
+        assert_eq!(done.unwrap().1, data);
+    }
+
+    #[test]
+    fn rejects_data_that_disagrees_with_the_header() {
+        let data = pixels(200);
+        let chunks: Vec<_> = FrameChunker::new(64)
+            .chunks(&header(10, 11), &data)
+            .unwrap()
+            .collect();
+        let mut asm = FrameAssembler::default();
+        let now = Instant::now();
+        let (last, rest) = chunks.split_last().unwrap();
+        for c in rest {
+            assert!(asm.ingest(c, now).unwrap().is_none());
+        }
+        assert!(matches!(
+            asm.ingest(last, now),
+            Err(FrameTransportError::Malformed)
+        ));
+        assert_eq!(asm.in_flight(), 0);
+        assert_eq!(asm.buffered_bytes(), 0);
+    }
+
+    #[test]
+    fn empty_frame_is_header_only() {
+        let h = header(0, 0);
+        let chunks: Vec<_> = FrameChunker::default().chunks(&h, &[]).unwrap().collect();
+        assert_eq!(chunks.len(), 1);
+        let mut asm = FrameAssembler::default();
+        let (_, data) = asm.ingest(&chunks[0], Instant::now()).unwrap().unwrap();
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn detects_corrupted_chunk() {
+        let h = header(10, 10);
+        let data = pixels(200);
+        let mut chunks: Vec<_> = FrameChunker::new(64).chunks(&h, &data).unwrap().collect();
+        let last = chunks[2].len() - 1;
+        chunks[2][last] ^= 0xFF;
+        let mut asm = FrameAssembler::default();
+        assert!(matches!(
+            asm.ingest(&chunks[2], Instant::now()),
+            Err(FrameTransportError::Checksum { index: 2, .. })
+        ));
+        assert!(matches!(
+            asm.ingest(b"garbage", Instant::now()),
+            Err(FrameTransportError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn times_out_with_missing_chunks() {
+        let h = header(10, 10);
+        let data = pixels(200);
+        let chunks: Vec<_> = FrameChunker::new(64).chunks(&h, &data).unwrap().collect();
+        let mut asm = FrameAssembler::new(AssemblerConfig {
+            timeout: Duration::from_secs(5),
+            ..Default::default()
+        });
+        let t0 = Instant::now();
+        asm.ingest(&chunks[0], t0).unwrap();
+        asm.ingest(&chunks[2], t0).unwrap();
+        let (chunk, _) = ChunkHeader::parse(&chunks[0]).unwrap();
+        assert_eq!(asm.missing(chunk.transfer_id), Some(vec![1, 3, 4]));
+
+        assert!(asm.expire(t0 + Duration::from_secs(1)).is_empty());
+        let expired = asm.expire(t0 + Duration::from_secs(5));
+        assert_eq!(
+            expired,
+            vec![FrameTransportError::Timeout {
+                transfer_id: chunk.transfer_id,
+                missing: vec![1, 3, 4],
+            }]
+        );
+        assert_eq!(asm.in_flight(), 0);
+        assert_eq!(asm.buffered_bytes(), 0);
+    }
+
+    #[test]
+    fn enforces_memory_limits() {
+        let h = header(10, 10);
+        let data = pixels(1000);
+        let chunker = FrameChunker::new(100);
+        let mut asm = FrameAssembler::new(AssemblerConfig {
+            max_frame_bytes: 1000,
+            max_buffered_bytes: 1500,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        let a: Vec<_> = chunker.chunks(&h, &data).unwrap().collect();
+        let b: Vec<_> = chunker.chunks(&h, &data).unwrap().collect();
+        asm.ingest(&a[0], now).unwrap();
+        assert!(matches!(
+            asm.ingest(&b[0], now),
+            Err(FrameTransportError::MemoryLimit { .. })
+        ));
+
+        let big = pixels(1001);
+        let c: Vec<_> = chunker.chunks(&h, &big).unwrap().collect();
+        assert!(matches!(
+            asm.ingest(&c[0], now),
+            Err(FrameTransportError::TooLarge {
+                total_len: 1001,
+                ..
+            })
+        ));
+        assert_eq!(asm.buffered_bytes(), 1000);
+    }
+}
@@ -1,29 +1,126 @@
-use std::sync::mpsc::Receiver;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc, Condvar, Mutex, MutexGuard,
 };
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::frame_pool::{FrameBuffer, FramePool};
+use crate::LightspeedError;
+
+/// Slack past the nominal exposure end before an exposure whose hardware
+/// never signals completion is failed. Also bounds the readout.
+pub const DEFAULT_EXPOSURE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long an abort may take before the exposure is failed instead.
+pub const ABORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A downloaded frame: `(frame_bytes, pixel_count)`. The bytes usually come
+/// from a [`FramePool`](crate::frame_pool::FramePool) and go back to it when
+/// dropped; `Vec<u8>::into()` makes an unpooled one.
+pub type RawFrame = (FrameBuffer, usize);
+
+/// Frames a [`VideoStream`] holds for its consumer by default.
+pub const DEFAULT_RING_CAPACITY: usize = 8;
+/// How long the stream thread waits for a free buffer before checking for
+/// [`VideoStream::stop`] again.
+const STREAM_BUFFER_WAIT: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExposureError {
+    /// `action` is not allowed while in state `from`.
+    InvalidTransition {
+        from: &'static str,
+        action: &'static str,
+    },
+}
+
+impl fmt::Display for ExposureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExposureError::InvalidTransition { from, action } => {
+                write!(f, "cannot {action} while {from}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExposureError {}
+
+/// What [`ExposureState::poll`] observed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExposureEvent {
+    /// Nothing changed.
+    Pending,
+    /// The hardware signalled the end of the exposure. Start the download
+    /// and hand its channel to [`ExposureState::begin_readout`].
+    ExposureDone,
+    /// The frame arrived; the state is now `Complete`.
+    Completed,
+    /// The abort finished; the state is now `Idle`.
+    Aborted,
+    /// The exposure timed out or the readout was lost; the state is now
+    /// `Failed`.
+    Failed,
+}
 
 /// Exposure state machine for imaging devices (CCDs, CMOSes).
 ///
 /// Embed this in device structs. In `tick()`, skip `sync_state()` while
 /// `ReadingOut` to avoid USB contention errors during frame transfer.
+///
+/// ```text
+/// Idle / Complete / Failed --start--> Exposing --done--> ReadingOut --frame--> Complete
+///                                        |                  |
+///                                        +------abort-------+--> Aborting --> Idle
+/// ```
+///
+/// Exposing and ReadingOut fail on timeout, Aborting fails after
+/// [`ABORT_TIMEOUT`]. Methods taking `now` let callers and tests control the
+/// clock.
+#[derive(Default)]
 pub enum ExposureState {
+    #[default]
     Idle,
     /// Exposure in progress. `done` is set to `true` (Release ordering) by
     /// the monitor thread when the hardware signals completion.
     Exposing {
         done: Arc<AtomicBool>,
+        started: Instant,
+        duration: Duration,
+        /// Slack past `started + duration`, then again for the readout.
+        timeout: Duration,
     },
     /// USB readout in progress. `rx` receives `(frame_bytes, pixel_count)`.
     ReadingOut {
-        rx: Receiver<(Vec<u8>, usize)>,
+        rx: Receiver<RawFrame>,
+        deadline: Instant,
+    },
+    /// Abort requested, waiting for the hardware. `done` is the exposure's
+    /// completion flag, `rx` an interrupted readout; either firing (or the
+    /// readout hanging up) ends the abort.
+    Aborting {
+        done: Option<Arc<AtomicBool>>,
+        rx: Option<Receiver<RawFrame>>,
+        deadline: Instant,
     },
+    Failed(String),
+    /// The downloaded frame, until [`ExposureState::take_frame`].
+    Complete(RawFrame),
 }
 
-impl Default for ExposureState {
-    fn default() -> Self {
-        ExposureState::Idle
+impl fmt::Debug for ExposureState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExposureState::Failed(reason) => write!(f, "Failed({reason:?})"),
+            ExposureState::Complete((bytes, pixels)) => {
+                write!(f, "Complete({} bytes, {pixels} pixels)", bytes.len())
+            }
+            other => f.write_str(other.name()),
+        }
     }
 }
 
@@ -40,12 +137,976 @@ impl ExposureState {
         matches!(self, ExposureState::ReadingOut { .. })
     }
 
+    pub fn is_aborting(&self) -> bool {
+        matches!(self, ExposureState::Aborting { .. })
+    }
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self, ExposureState::Failed(_))
+    }
+
+    pub fn is_complete(&self) -> bool {
+        matches!(self, ExposureState::Complete(_))
+    }
+
+    /// Exposing, reading out or aborting: a new exposure cannot start.
+    pub fn is_busy(&self) -> bool {
+        self.is_exposing() || self.is_reading_out() || self.is_aborting()
+    }
+
+    /// State name, e.g. for `state_json()`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExposureState::Idle => "Idle",
+            ExposureState::Exposing { .. } => "Exposing",
+            ExposureState::ReadingOut { .. } => "ReadingOut",
+            ExposureState::Aborting { .. } => "Aborting",
+            ExposureState::Failed(_) => "Failed",
+            ExposureState::Complete(_) => "Complete",
+        }
+    }
+
     /// Returns `true` if the exposure monitor thread has signalled completion.
     /// Only meaningful when in the `Exposing` state.
     pub fn exposure_done(&self) -> bool {
         match self {
-            ExposureState::Exposing { done } => done.load(Ordering::Acquire),
+            ExposureState::Exposing { done, .. } => done.load(Ordering::Acquire),
             _ => false,
         }
     }
+
+    fn invalid(&self, action: &'static str) -> ExposureError {
+        ExposureError::InvalidTransition {
+            from: self.name(),
+            action,
+        }
+    }
+
+    /// Start an exposure with [`DEFAULT_EXPOSURE_TIMEOUT`]. See
+    /// [`ExposureState::start_with_timeout`].
+    pub fn start(
+        &mut self,
+        duration: Duration,
+        now: Instant,
+    ) -> Result<Arc<AtomicBool>, ExposureError> {
+        self.start_with_timeout(duration, DEFAULT_EXPOSURE_TIMEOUT, now)
+    }
+
+    /// Start an exposure from `Idle`, `Complete` (discarding the frame) or
+    /// `Failed`. Returns the completion flag for the monitor thread.
+    pub fn start_with_timeout(
+        &mut self,
+        duration: Duration,
+        timeout: Duration,
+        now: Instant,
+    ) -> Result<Arc<AtomicBool>, ExposureError> {
+        if self.is_busy() {
+            return Err(self.invalid("start"));
+        }
+        let done = Arc::new(AtomicBool::new(false));
+        *self = ExposureState::Exposing {
+            done: done.clone(),
+            started: now,
+            duration,
+            timeout,
+        };
+        Ok(done)
+    }
+
+    /// Exposing -> ReadingOut, once the exposure is done and the download
+    /// has started.
+    pub fn begin_readout(
+        &mut self,
+        rx: Receiver<RawFrame>,
+        now: Instant,
+    ) -> Result<(), ExposureError> {
+        match self {
+            ExposureState::Exposing { timeout, .. } => {
+                *self = ExposureState::ReadingOut {
+                    rx,
+                    deadline: now + *timeout,
+                };
+                Ok(())
+            }
+            _ => Err(self.invalid("begin readout")),
+        }
+    }
+
+    /// Exposing or ReadingOut -> Aborting. The driver stops the hardware;
+    /// [`ExposureState::poll`] returns [`ExposureEvent::Aborted`] once it
+    /// has.
+    pub fn abort(&mut self, now: Instant) -> Result<(), ExposureError> {
+        let deadline = now + ABORT_TIMEOUT;
+        match std::mem::take(self) {
+            ExposureState::Exposing { done, .. } => {
+                *self = ExposureState::Aborting {
+                    done: Some(done),
+                    rx: None,
+                    deadline,
+                };
+                Ok(())
+            }
+            ExposureState::ReadingOut { rx, .. } => {
+                *self = ExposureState::Aborting {
+                    done: None,
+                    rx: Some(rx),
+                    deadline,
+                };
+                Ok(())
+            }
+            other => {
+                *self = other;
+                Err(self.invalid("abort"))
+            }
+        }
+    }
+
+    /// Aborting -> Idle, for drivers whose abort call is synchronous.
+    pub fn finish_abort(&mut self) -> Result<(), ExposureError> {
+        if !self.is_aborting() {
+            return Err(self.invalid("finish abort"));
+        }
+        *self = ExposureState::Idle;
+        Ok(())
+    }
+
+    /// Any state -> Failed.
+    pub fn fail(&mut self, reason: impl Into<String>) {
+        *self = ExposureState::Failed(reason.into());
+    }
+
+    /// Complete -> Idle, returning the frame.
+    pub fn take_frame(&mut self) -> Option<RawFrame> {
+        match std::mem::take(self) {
+            ExposureState::Complete(frame) => Some(frame),
+            other => {
+                *self = other;
+                None
+            }
+        }
+    }
+
+    /// Advance on hardware signals and timeouts. Call from `tick()`.
+    pub fn poll(&mut self, now: Instant) -> ExposureEvent {
+        match self {
+            ExposureState::Exposing {
+                done,
+                started,
+                duration,
+                timeout,
+            } => {
+                if done.load(Ordering::Acquire) {
+                    ExposureEvent::ExposureDone
+                } else if now > *started + *duration + *timeout {
+                    self.fail("exposure timed out");
+                    ExposureEvent::Failed
+                } else {
+                    ExposureEvent::Pending
+                }
+            }
+            ExposureState::ReadingOut { rx, deadline } => match rx.try_recv() {
+                Ok(frame) => {
+                    *self = ExposureState::Complete(frame);
+                    ExposureEvent::Completed
+                }
+                Err(TryRecvError::Disconnected) => {
+                    self.fail("readout ended without a frame");
+                    ExposureEvent::Failed
+                }
+                Err(TryRecvError::Empty) if now > *deadline => {
+                    self.fail("readout timed out");
+                    ExposureEvent::Failed
+                }
+                Err(TryRecvError::Empty) => ExposureEvent::Pending,
+            },
+            ExposureState::Aborting { done, rx, deadline } => {
+                let stopped = done.as_ref().is_some_and(|d| d.load(Ordering::Acquire))
+                    || rx
+                        .as_ref()
+                        .is_some_and(|rx| !matches!(rx.try_recv(), Err(TryRecvError::Empty)))
+                    || (done.is_none() && rx.is_none());
+                if stopped {
+                    *self = ExposureState::Idle;
+                    ExposureEvent::Aborted
+                } else if now > *deadline {
+                    self.fail("abort timed out");
+                    ExposureEvent::Failed
+                } else {
+                    ExposureEvent::Pending
+                }
+            }
+            _ => ExposureEvent::Pending,
+        }
+    }
+
+    /// Exposure time left, `None` unless exposing.
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        match self {
+            ExposureState::Exposing {
+                started, duration, ..
+            } => Some(duration.saturating_sub(now.saturating_duration_since(*started))),
+            _ => None,
+        }
+    }
+
+    /// Exposure progress, 0..=100: elapsed share of the duration while
+    /// exposing, 100 once reading out or complete, `None` otherwise.
+    pub fn percent_complete(&self, now: Instant) -> Option<f64> {
+        match self {
+            ExposureState::Exposing {
+                started, duration, ..
+            } => {
+                if duration.is_zero() {
+                    return Some(100.0);
+                }
+                let elapsed = now.saturating_duration_since(*started).as_secs_f64();
+                Some((elapsed / duration.as_secs_f64() * 100.0).min(100.0))
+            }
+            ExposureState::ReadingOut { .. } | ExposureState::Complete(_) => Some(100.0),
+            _ => None,
+        }
+    }
+}
+
+/// Handed to the readout closure of an [`ExposureMonitor`].
+#[derive(Clone)]
+pub struct ReadoutContext {
+    cancel: Arc<AtomicBool>,
+    read: Arc<AtomicUsize>,
+    total: Arc<AtomicUsize>,
+}
+
+impl ReadoutContext {
+    /// True once [`ExposureMonitor::cancel`] was called. Long downloads
+    /// should check this between chunks and bail out.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Acquire)
+    }
+
+    /// Record that `read` of `total` bytes have been downloaded.
+    pub fn report(&self, read: usize, total: usize) {
+        self.total.store(total, Ordering::Relaxed);
+        self.read.store(read, Ordering::Release);
+    }
+}
+
+/// Runs the monitor and readout threads of one exposure.
+///
+/// The monitor thread calls `is_complete` every `poll_interval` until it
+/// returns `true`, sets the exposure's `done` flag and spawns the readout
+/// thread, which calls `read_frame` and sends the result down the channel
+/// [`ExposureMonitor::drive`] hands to [`ExposureState::begin_readout`].
+/// Errors from `is_complete` are logged and polling continues, leaving hung
+/// hardware to the exposure timeout; a failed readout closes the channel,
+/// which fails the exposure.
+///
+/// [`ExposureMonitor::cancel`] stops both threads. The monitor then sets
+/// `done` and the readout drops its frame, so an `Aborting` state settles.
+pub struct ExposureMonitor {
+    context: ReadoutContext,
+    rx: Option<Receiver<RawFrame>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ExposureMonitor {
+    pub fn spawn<C, R>(
+        done: Arc<AtomicBool>,
+        poll_interval: Duration,
+        mut is_complete: C,
+        read_frame: R,
+    ) -> io::Result<Self>
+    where
+        C: FnMut() -> Result<bool, LightspeedError> + Send + 'static,
+        R: FnOnce(&ReadoutContext) -> Result<RawFrame, LightspeedError> + Send + 'static,
+    {
+        let context = ReadoutContext {
+            cancel: Arc::new(AtomicBool::new(false)),
+            read: Arc::new(AtomicUsize::new(0)),
+            total: Arc::new(AtomicUsize::new(0)),
+        };
+        let (tx, rx) = channel();
+        let ctx = context.clone();
+        let handle = thread::Builder::new()
+            .name("exposure-monitor".into())
+            .spawn(move || {
+                loop {
+                    if ctx.is_cancelled() {
+                        done.store(true, Ordering::Release);
+                        return;
+                    }
+                    match is_complete() {
+                        Ok(true) => break,
+                        Ok(false) => {}
+                        Err(e) => log::warn!("exposure status check failed: {e:?}"),
+                    }
+                    thread::sleep(poll_interval);
+                }
+                done.store(true, Ordering::Release);
+
+                let readout_ctx = ctx.clone();
+                let readout = thread::Builder::new()
+                    .name("exposure-readout".into())
+                    .spawn(move || match read_frame(&readout_ctx) {
+                        Ok(frame) if !readout_ctx.is_cancelled() => {
+                            let _ = tx.send(frame);
+                        }
+                        Ok(_) => {}
+                        Err(e) => log::error!("frame readout failed: {e:?}"),
+                    });
+                match readout {
+                    Ok(readout) => {
+                        let _ = readout.join();
+                    }
+                    Err(e) => log::error!("cannot spawn readout thread: {e}"),
+                }
+            })?;
+        Ok(Self {
+            context,
+            rx: Some(rx),
+            handle: Some(handle),
+        })
+    }
+
+    /// Poll `state` and start its readout once the exposure is done. Call
+    /// from `tick()` instead of [`ExposureState::poll`]; take the frame with
+    /// [`ExposureState::take_frame`] after [`ExposureEvent::Completed`].
+    pub fn drive(&mut self, state: &mut ExposureState, now: Instant) -> ExposureEvent {
+        let event = state.poll(now);
+        if event == ExposureEvent::ExposureDone {
+            if let Some(rx) = self.rx.take() {
+                if state.begin_readout(rx, now).is_err() {
+                    return ExposureEvent::Pending;
+                }
+            }
+        }
+        event
+    }
+
+    /// Readout progress, 0..=100. None until the readout reports.
+    pub fn readout_progress(&self) -> Option<f64> {
+        let read = self.context.read.load(Ordering::Acquire);
+        let total = self.context.total.load(Ordering::Relaxed);
+        (total > 0).then(|| (read as f64 / total as f64 * 100.0).min(100.0))
+    }
+
+    pub fn cancel(&self) {
+        self.context.cancel.store(true, Ordering::Release);
+    }
+
+    /// True once both threads have exited.
+    pub fn is_finished(&self) -> bool {
+        self.handle.as_ref().is_none_or(|h| h.is_finished())
+    }
+
+    /// Wait for both threads to exit.
+    pub fn join(mut self) {
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for ExposureMonitor {
+    /// Cancels without waiting; the threads exit at their next check.
+    fn drop(&mut self) {
+        if self.handle.is_some() {
+            self.cancel();
+        }
+    }
+}
+
+/// One frame of a [`VideoStream`].
+#[derive(Debug, PartialEq)]
+pub struct StreamFrame {
+    /// Position in the stream, starting at 0. Gaps mean dropped frames.
+    pub seq: u64,
+    /// Capture time, nanoseconds since unix epoch, like
+    /// [`FrameHeader::timestamp_ns`](crate::frame::FrameHeader::timestamp_ns).
+    pub timestamp_ns: u64,
+    pub data: FrameBuffer,
+}
+
+/// Counters of a [`VideoStream`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    /// Frames grabbed from the camera.
+    pub captured: u64,
+    /// Frames taken by the consumer.
+    pub delivered: u64,
+    /// Frames discarded unread because the consumer fell behind.
+    pub dropped: u64,
+    /// Times the stream thread found every buffer in the consumer's hands.
+    pub starved: u64,
+}
+
+/// Fixed-size FIFO of stream frames. Pushing onto a full ring evicts the
+/// oldest frame.
+#[derive(Debug)]
+pub struct FrameRing {
+    frames: VecDeque<StreamFrame>,
+    capacity: usize,
+}
+
+impl FrameRing {
+    /// A ring holding up to `capacity` frames (at least one).
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Append `frame`, returning the evicted frame if the ring was full.
+    pub fn push(&mut self, frame: StreamFrame) -> Option<StreamFrame> {
+        let evicted = if self.frames.len() == self.capacity {
+            self.frames.pop_front()
+        } else {
+            None
+        };
+        self.frames.push_back(frame);
+        evicted
+    }
+
+    /// Remove the oldest frame.
+    pub fn pop(&mut self) -> Option<StreamFrame> {
+        self.frames.pop_front()
+    }
+
+    /// Remove every frame, returning the newest and how many older ones
+    /// were discarded.
+    pub fn pop_latest(&mut self) -> Option<(StreamFrame, usize)> {
+        let latest = self.frames.pop_back()?;
+        let skipped = self.frames.len();
+        self.frames.clear();
+        Some((latest, skipped))
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+struct StreamState {
+    ring: FrameRing,
+    stats: StreamStats,
+    error: Option<String>,
+    running: bool,
+}
+
+struct StreamShared {
+    state: Mutex<StreamState>,
+    ready: Condvar,
+    stop: AtomicBool,
+}
+
+impl StreamShared {
+    fn lock(&self) -> MutexGuard<'_, StreamState> {
+        self.state.lock().unwrap()
+    }
+}
+
+/// Continuous capture for planetary and guiding cameras.
+///
+/// A capture thread borrows buffers from a [`FramePool`], fills them with
+/// `grab` and pushes the frames into a [`FrameRing`] the consumer drains
+/// with [`VideoStream::try_next`], [`VideoStream::next_timeout`] or
+/// [`VideoStream::latest`]. `grab` should block until a frame arrives or a
+/// short timeout passes, returning `Ok(false)` in the latter case. When the
+/// consumer falls behind, the oldest unread frame is dropped so capture
+/// never stalls; an error from `grab` ends the stream.
+///
+/// Size the pool one or two buffers above the ring capacity, so the stream
+/// keeps running while the consumer holds a frame.
+pub struct VideoStream {
+    shared: Arc<StreamShared>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl VideoStream {
+    pub fn start<G>(pool: FramePool, ring_capacity: usize, mut grab: G) -> io::Result<Self>
+    where
+        G: FnMut(&mut Vec<u8>) -> Result<bool, LightspeedError> + Send + 'static,
+    {
+        let shared = Arc::new(StreamShared {
+            state: Mutex::new(StreamState {
+                ring: FrameRing::new(ring_capacity),
+                stats: StreamStats::default(),
+                error: None,
+                running: true,
+            }),
+            ready: Condvar::new(),
+            stop: AtomicBool::new(false),
+        });
+        let thread_shared = shared.clone();
+        let handle = thread::Builder::new()
+            .name("video-stream".into())
+            .spawn(move || {
+                let shared = thread_shared;
+                let mut seq = 0;
+                while !shared.stop.load(Ordering::Acquire) {
+                    let mut buf = match pool.try_get() {
+                        Some(buf) => buf,
+                        None => {
+                            // Recycle the oldest unread frame, or wait for the
+                            // consumer to return one.
+                            let oldest = {
+                                let mut state = shared.lock();
+                                let oldest = state.ring.pop();
+                                match oldest {
+                                    Some(_) => state.stats.dropped += 1,
+                                    None => state.stats.starved += 1,
+                                }
+                                oldest
+                            };
+                            if oldest.is_none() {
+                                let _ = pool.get_timeout(STREAM_BUFFER_WAIT);
+                            }
+                            continue;
+                        }
+                    };
+                    match grab(&mut buf) {
+                        Ok(true) => {
+                            let frame = StreamFrame {
+                                seq,
+                                timestamp_ns: unix_nanos(),
+                                data: buf,
+                            };
+                            seq += 1;
+                            let evicted = {
+                                let mut state = shared.lock();
+                                state.stats.captured += 1;
+                                let evicted = state.ring.push(frame);
+                                if evicted.is_some() {
+                                    state.stats.dropped += 1;
+                                }
+                                evicted
+                            };
+                            drop(evicted);
+                            shared.ready.notify_all();
+                        }
+                        Ok(false) => {}
+                        Err(e) => {
+                            log::error!("stream capture failed: {e:?}");
+                            shared.lock().error = Some(format!("{e:?}"));
+                            break;
+                        }
+                    }
+                }
+                shared.lock().running = false;
+                shared.ready.notify_all();
+            })?;
+        Ok(Self {
+            shared,
+            handle: Some(handle),
+        })
+    }
+
+    /// Oldest unread frame, if any.
+    pub fn try_next(&self) -> Option<StreamFrame> {
+        let mut state = self.shared.lock();
+        let frame = state.ring.pop();
+        if frame.is_some() {
+            state.stats.delivered += 1;
+        }
+        frame
+    }
+
+    /// Oldest unread frame, waiting up to `timeout` for one. Returns early
+    /// with `None` once the stream has stopped and the ring is empty.
+    pub fn next_timeout(&self, timeout: Duration) -> Option<StreamFrame> {
+        let state = self.shared.lock();
+        let (mut state, _) = self
+            .shared
+            .ready
+            .wait_timeout_while(state, timeout, |s| s.ring.is_empty() && s.running)
+            .unwrap();
+        let frame = state.ring.pop();
+        if frame.is_some() {
+            state.stats.delivered += 1;
+        }
+        frame
+    }
+
+    /// Newest frame, discarding older unread ones. Suits guiding and
+    /// previews, which only care about the current view.
+    pub fn latest(&self) -> Option<StreamFrame> {
+        let mut state = self.shared.lock();
+        let (frame, skipped) = state.ring.pop_latest()?;
+        state.stats.delivered += 1;
+        state.stats.dropped += skipped as u64;
+        Some(frame)
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        self.shared.lock().stats
+    }
+
+    /// Unread frames in the ring.
+    pub fn buffered(&self) -> usize {
+        self.shared.lock().ring.len()
+    }
+
+    /// Why capture ended, if `grab` failed.
+    pub fn error(&self) -> Option<String> {
+        self.shared.lock().error.clone()
+    }
+
+    /// False once the capture thread has exited. Unread frames stay
+    /// available.
+    pub fn is_running(&self) -> bool {
+        self.shared.lock().running
+    }
+
+    /// Ask the capture thread to exit after the current `grab`.
+    pub fn stop(&self) {
+        self.shared.stop.store(true, Ordering::Release);
+    }
+
+    /// Stop and wait for the capture thread to exit.
+    pub fn join(mut self) {
+        self.stop();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for VideoStream {
+    /// Stops without waiting; the thread exits after its current `grab`.
+    fn drop(&mut self) {
+        if self.handle.is_some() {
+            self.stop();
+        }
+    }
+}
+
+fn unix_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame_pool::FramePool;
+
+    #[test]
+    fn full_exposure_cycle() {
+        let t0 = Instant::now();
+        let mut state = ExposureState::default();
+        let done = state.start(Duration::from_secs(10), t0).unwrap();
+        assert!(state.is_exposing());
+        assert_eq!(
+            state.remaining(t0 + Duration::from_secs(4)),
+            Some(Duration::from_secs(6))
+        );
+        assert_eq!(
+            state.percent_complete(t0 + Duration::from_secs(5)),
+            Some(50.0)
+        );
+        assert_eq!(
+            state.poll(t0 + Duration::from_secs(5)),
+            ExposureEvent::Pending
+        );
+
+        done.store(true, Ordering::Release);
+        assert_eq!(
+            state.poll(t0 + Duration::from_secs(10)),
+            ExposureEvent::ExposureDone
+        );
+        let (tx, rx) = channel();
+        state
+            .begin_readout(rx, t0 + Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(
+            state.poll(t0 + Duration::from_secs(11)),
+            ExposureEvent::Pending
+        );
+
+        tx.send((vec![1, 2].into(), 1)).unwrap();
+        assert_eq!(
+            state.poll(t0 + Duration::from_secs(12)),
+            ExposureEvent::Completed
+        );
+        assert_eq!(state.percent_complete(t0), Some(100.0));
+        assert_eq!(state.take_frame(), Some((vec![1, 2].into(), 1)));
+        assert!(state.is_idle());
+        assert_eq!(state.take_frame(), None);
+    }
+
+    #[test]
+    fn rejects_invalid_transitions() {
+        let t0 = Instant::now();
+        let mut state = ExposureState::default();
+        assert_eq!(
+            state.abort(t0),
+            Err(ExposureError::InvalidTransition {
+                from: "Idle",
+                action: "abort"
+            })
+        );
+        assert!(state.begin_readout(channel().1, t0).is_err());
+        state.start(Duration::from_secs(1), t0).unwrap();
+        assert!(state.start(Duration::from_secs(1), t0).is_err());
+        assert!(state.finish_abort().is_err());
+        assert!(state.is_exposing());
+    }
+
+    #[test]
+    fn exposure_and_readout_time_out() {
+        let t0 = Instant::now();
+        let mut state = ExposureState::default();
+        state
+            .start_with_timeout(Duration::from_secs(10), Duration::from_secs(5), t0)
+            .unwrap();
+        assert_eq!(
+            state.poll(t0 + Duration::from_secs(15)),
+            ExposureEvent::Pending
+        );
+        assert_eq!(
+            state.poll(t0 + Duration::from_secs(16)),
+            ExposureEvent::Failed
+        );
+        assert!(matches!(&state, ExposureState::Failed(r) if r == "exposure timed out"));
+
+        // A failed exposure can be retried.
+        state.start(Duration::from_secs(1), t0).unwrap();
+        let (tx, rx) = channel();
+        state.begin_readout(rx, t0).unwrap();
+        assert_eq!(
+            state.poll(t0 + Duration::from_secs(31)),
+            ExposureEvent::Failed
+        );
+        drop(tx);
+
+        state.start(Duration::from_secs(1), t0).unwrap();
+        let (tx, rx) = channel::<RawFrame>();
+        state.begin_readout(rx, t0).unwrap();
+        drop(tx);
+        assert_eq!(state.poll(t0), ExposureEvent::Failed);
+    }
+
+    #[test]
+    fn abort_waits_for_hardware() {
+        let t0 = Instant::now();
+        let mut state = ExposureState::default();
+        let done = state.start(Duration::from_secs(60), t0).unwrap();
+        state.abort(t0).unwrap();
+        assert!(state.is_busy());
+        assert_eq!(state.poll(t0), ExposureEvent::Pending);
+        done.store(true, Ordering::Release);
+        assert_eq!(state.poll(t0), ExposureEvent::Aborted);
+        assert!(state.is_idle());
+
+        state.start(Duration::from_secs(1), t0).unwrap();
+        let (_tx, rx) = channel();
+        state.begin_readout(rx, t0).unwrap();
+        state.abort(t0).unwrap();
+        assert_eq!(
+            state.poll(t0 + ABORT_TIMEOUT + Duration::from_secs(1)),
+            ExposureEvent::Failed
+        );
+
+        state.start(Duration::from_secs(1), t0).unwrap();
+        state.abort(t0).unwrap();
+        state.finish_abort().unwrap();
+        assert!(state.is_idle());
+    }
+
+    /// Finishes exposing after `polls` status checks and downloads `len`
+    /// bytes in four chunks into a buffer from `pool`.
+    struct FakeSensor {
+        polls: usize,
+        len: usize,
+        fail_readout: bool,
+    }
+
+    impl FakeSensor {
+        fn monitor(self, done: Arc<AtomicBool>, pool: FramePool) -> ExposureMonitor {
+            let mut remaining = self.polls;
+            let (len, fail) = (self.len, self.fail_readout);
+            ExposureMonitor::spawn(
+                done,
+                Duration::from_millis(1),
+                move || {
+                    remaining = remaining.saturating_sub(1);
+                    Ok(remaining == 0)
+                },
+                move |ctx| {
+                    if fail {
+                        return Err(LightspeedError::DeviceConnectionError);
+                    }
+                    let mut frame = pool.try_get().ok_or(LightspeedError::QueueFull)?;
+                    for chunk in 0..4 {
+                        if ctx.is_cancelled() {
+                            return Err(LightspeedError::DeviceConnectionError);
+                        }
+                        frame.resize(len * (chunk + 1) / 4, chunk as u8);
+                        ctx.report(frame.len(), len);
+                    }
+                    Ok((frame, len / 2))
+                },
+            )
+            .unwrap()
+        }
+    }
+
+    /// Drive until something other than `Pending`/`ExposureDone` happens.
+    fn settle(monitor: &mut ExposureMonitor, state: &mut ExposureState) -> ExposureEvent {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            match monitor.drive(state, Instant::now()) {
+                ExposureEvent::Pending | ExposureEvent::ExposureDone => {
+                    thread::sleep(Duration::from_millis(1))
+                }
+                event => return event,
+            }
+        }
+        panic!("exposure did not settle: {state:?}");
+    }
+
+    #[test]
+    fn monitor_delivers_frame_from_fake_sensor() {
+        let pool = FramePool::new(1, 1000);
+        let mut state = ExposureState::default();
+        let done = state
+            .start(Duration::from_millis(5), Instant::now())
+            .unwrap();
+        let mut monitor = FakeSensor {
+            polls: 5,
+            len: 1000,
+            fail_readout: false,
+        }
+        .monitor(done, pool.clone());
+
+        assert_eq!(settle(&mut monitor, &mut state), ExposureEvent::Completed);
+        assert_eq!(monitor.readout_progress(), Some(100.0));
+        let (frame, pixels) = state.take_frame().unwrap();
+        assert_eq!((frame.len(), pixels), (1000, 500));
+        assert_eq!(frame[999], 3);
+        assert_eq!(pool.metrics().in_use, 1);
+        drop(frame);
+        assert_eq!(pool.metrics().available, 1);
+        monitor.join();
+    }
+
+    #[test]
+    fn monitor_cancel_settles_abort() {
+        let mut state = ExposureState::default();
+        let done = state
+            .start(Duration::from_secs(60), Instant::now())
+            .unwrap();
+        let mut monitor = FakeSensor {
+            polls: usize::MAX,
+            len: 10,
+            fail_readout: false,
+        }
+        .monitor(done, FramePool::new(1, 16));
+
+        state.abort(Instant::now()).unwrap();
+        monitor.cancel();
+        assert_eq!(settle(&mut monitor, &mut state), ExposureEvent::Aborted);
+        assert_eq!(monitor.readout_progress(), None);
+        monitor.join();
+    }
+
+    #[test]
+    fn monitor_readout_failure_fails_exposure() {
+        let mut state = ExposureState::default();
+        let done = state.start(Duration::ZERO, Instant::now()).unwrap();
+        let mut monitor = FakeSensor {
+            polls: 1,
+            len: 10,
+            fail_readout: true,
+        }
+        .monitor(done, FramePool::new(1, 16));
+
+        assert_eq!(settle(&mut monitor, &mut state), ExposureEvent::Failed);
+        assert!(matches!(&state, ExposureState::Failed(r) if r == "readout ended without a frame"));
+        monitor.join();
+    }
+
+    fn ring_frame(seq: u64) -> StreamFrame {
+        StreamFrame {
+            seq,
+            timestamp_ns: 0,
+            data: vec![seq as u8].into(),
+        }
+    }
+
+    #[test]
+    fn ring_evicts_oldest() {
+        let mut ring = FrameRing::new(2);
+        assert!(ring.push(ring_frame(0)).is_none());
+        assert!(ring.push(ring_frame(1)).is_none());
+        assert_eq!(ring.push(ring_frame(2)).map(|f| f.seq), Some(0));
+        assert_eq!(ring.pop().map(|f| f.seq), Some(1));
+
+        ring.push(ring_frame(3));
+        let (latest, skipped) = ring.pop_latest().unwrap();
+        assert_eq!((latest.seq, skipped), (3, 1));
+        assert!(ring.is_empty());
+    }
+
+    /// Grabs `frames` frames whose first byte is the frame number, then
+    /// fails.
+    fn counting_stream(pool: FramePool, ring: usize, frames: u8) -> VideoStream {
+        let mut n = 0u8;
+        VideoStream::start(pool, ring, move |buf| {
+            if n == frames {
+                return Err(LightspeedError::QueueFull);
+            }
+            buf.clear();
+            buf.extend_from_slice(&[n; 4]);
+            n += 1;
+            Ok(true)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn stream_delivers_frames_in_order() {
+        let pool = FramePool::new(4, 4);
+        let stream = counting_stream(pool.clone(), 8, 3);
+        let mut seen = Vec::new();
+        while let Some(frame) = stream.next_timeout(Duration::from_secs(5)) {
+            assert_eq!(frame.data[0] as u64, frame.seq);
+            assert!(frame.timestamp_ns > 0);
+            seen.push(frame.seq);
+        }
+        assert_eq!(seen, [0, 1, 2]);
+        assert!(!stream.is_running());
+        assert_eq!(stream.error().as_deref(), Some("QueueFull"));
+        let stats = stream.stats();
+        assert_eq!((stats.captured, stats.delivered, stats.dropped), (3, 3, 0));
+        stream.join();
+        assert_eq!(pool.metrics().in_use, 0);
+    }
+
+    #[test]
+    fn stream_drops_oldest_when_consumer_lags() {
+        let stream = counting_stream(FramePool::new(3, 4), 2, 10);
+        while stream.is_running() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let stats = stream.stats();
+        assert_eq!((stats.captured, stats.dropped), (10, 8));
+        assert_eq!(stream.buffered(), 2);
+
+        let latest = stream.latest().unwrap();
+        assert_eq!(latest.seq, 9);
+        assert!(stream.try_next().is_none());
+        assert_eq!(stream.stats().dropped, 9);
+    }
 }
@@ -8,22 +8,46 @@
 pub mod properties;
 
 #[cfg(feature = "wire")]
-pub mod protocol;
+pub mod fits;
+#[cfg(feature = "wire")]
+pub mod frame;
+#[cfg(feature = "wire")]
+pub mod frame_transport;
 #[cfg(feature = "wire")]
 pub mod presence;
+#[cfg(feature = "preview")]
+pub mod preview;
 #[cfg(feature = "wire")]
-pub mod frame;
+pub mod protocol;
+#[cfg(feature = "wire")]
+mod rice;
+#[cfg(feature = "wire")]
+pub mod ser;
 #[cfg(feature = "wire")]
 pub mod topics;
+#[cfg(feature = "xisf")]
+pub mod xisf;
+
+// Server-only modules
+#[cfg(feature = "server")]
+pub mod presence_tracker;
 
 // Driver-only modules
 #[cfg(feature = "driver")]
+pub mod camera;
+#[cfg(feature = "driver")]
 pub mod device;
 #[cfg(feature = "driver")]
 pub mod filter_wheel;
 #[cfg(feature = "driver")]
+pub mod focuser;
+#[cfg(feature = "driver")]
+pub mod frame_pool;
+#[cfg(feature = "driver")]
 pub mod imaging;
 #[cfg(feature = "driver")]
+pub mod mount;
+#[cfg(feature = "driver")]
 pub mod runner;
 #[cfg(feature = "driver")]
 mod serial;
@@ -31,7 +55,7 @@ mod serial;
 #[cfg(feature = "driver")]
 pub use crate::serial::find_serial_devices;
 
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Serialize, Serializer};
 
 fn io_serialize<S>(err: &std::io::Error, serializer: S) -> Result<S::Ok, S::Error>
 where
@@ -41,6 +65,22 @@ where
     serializer.serialize_str(&kind.to_string())
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceType {
+    Ccd,
+    Mount,
+    Focuser,
+    FilterWheel,
+    PowerBox,
+    Dome,    // NEW in 0.12
+    Weather, // NEW in 0.12
+    AuxBox,  // NEW in 0.12 — generic catch-all
+    /// A type introduced by a newer peer.
+    #[serde(other)]
+    Unknown,
+}
+
 #[derive(Debug, Serialize)]
 pub enum LightspeedError {
     PropertyError(properties::PropertyErrorType),
@@ -50,6 +90,8 @@ pub enum LightspeedError {
     UnknownCommand,
     QueueFull,
     ParseError,
+    /// The device lacks the requested capability.
+    NotSupported,
 }
 
 impl From<properties::PropertyErrorType> for LightspeedError {
@@ -76,12 +118,22 @@ pub trait Lightspeed {
 
 #[cfg(test)]
 mod tests {
-    use crate::LightspeedError;
+    use crate::{DeviceType, LightspeedError};
     use std::io::{Error, ErrorKind};
 
+    #[test]
+    fn test_serialize_device_type() {
+        assert_eq!(
+            "\"filter_wheel\"",
+            serde_json::to_string(&DeviceType::FilterWheel).unwrap()
+        );
+        let t: DeviceType = serde_json::from_str("\"ccd\"").unwrap();
+        assert_eq!(t, DeviceType::Ccd);
+    }
+
     #[test]
     fn test_serialize_lightspeed_error() {
-        let custom_error_1 = Error::new(ErrorKind::Other, "oh no!");
+        let custom_error_1 = Error::other("oh no!");
         let custom_error_2 = Error::from(ErrorKind::NotConnected);
         let e1 = LightspeedError::IoError(custom_error_1);
         let e2 = LightspeedError::IoError(custom_error_2);
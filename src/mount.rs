@@ -0,0 +1,745 @@
+//! Mount trait for equatorial mounts ([`DeviceType::Mount`](crate::DeviceType))
+//! and a generic [`LightspeedDevice`] adapter.
+//!
+//! Coordinates are right ascension in hours and declination in degrees,
+//! tagged with their [`Equinox`]. A mount works in one equinox
+//! ([`Mount::equinox`]); [`EquatorialCoords::to_equinox`] converts between
+//! J2000 and JNow. The conversion applies precession only, so it is off by
+//! nutation and aberration (well under an arcminute), which plate solving
+//! and sync absorb.
+
+use std::sync::mpsc::SyncSender;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::device::{CommandQueue, Dispatcher, LightspeedDevice};
+use crate::properties::{
+    Permission, PropValue, Property, PropertyErrorType, UpdatePropertyRequest,
+};
+use crate::protocol::ErrorEnvelope;
+use crate::{DeviceType, LightspeedError};
+
+/// Command topics handled by [`MountDevice`].
+pub const MOUNT_TOPICS: &[&str] = &[
+    "slew",
+    "sync",
+    "abort",
+    "park",
+    "unpark",
+    "pulse_guide",
+    "set_tracking",
+    "set_tracking_rate",
+    "set_site",
+    "set_time",
+    "set",
+];
+
+/// Sidereal tracking rate in arcseconds of RA per second of time.
+pub const SIDEREAL_RATE: f64 = 15.041067;
+pub const LUNAR_RATE: f64 = 14.685;
+pub const SOLAR_RATE: f64 = 15.0;
+
+/// Julian date of J2000.0.
+const J2000_JD: f64 = 2_451_545.0;
+/// Julian date of the unix epoch.
+const UNIX_EPOCH_JD: f64 = 2_440_587.5;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Equinox {
+    #[default]
+    J2000,
+    /// Equinox of date.
+    JNow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EquatorialCoords {
+    /// 0..24 hours.
+    pub ra_hours: f64,
+    /// -90..=90 degrees.
+    pub dec_degrees: f64,
+    #[serde(default)]
+    pub equinox: Equinox,
+}
+
+impl EquatorialCoords {
+    pub fn new(ra_hours: f64, dec_degrees: f64, equinox: Equinox) -> Self {
+        Self {
+            ra_hours,
+            dec_degrees,
+            equinox,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), LightspeedError> {
+        if !(0.0..24.0).contains(&self.ra_hours) || !(-90.0..=90.0).contains(&self.dec_degrees) {
+            return Err(PropertyErrorType::ValueOutOfRange.into());
+        }
+        Ok(())
+    }
+
+    /// The same position in `equinox`, with JNow taken at `now`.
+    pub fn to_equinox(&self, equinox: Equinox, now: SystemTime) -> Self {
+        if self.equinox == equinox {
+            return *self;
+        }
+        let (zeta, z, theta) = precession_angles(julian_date(now));
+        let ra = (self.ra_hours * 15.0).to_radians();
+        let dec = self.dec_degrees.to_radians();
+        // Meeus, Astronomical Algorithms, eq. 21.4 and its inverse.
+        let (ra, dec) = match equinox {
+            Equinox::JNow => {
+                let a = dec.cos() * (ra + zeta).sin();
+                let b = theta.cos() * dec.cos() * (ra + zeta).cos() - theta.sin() * dec.sin();
+                let c = theta.sin() * dec.cos() * (ra + zeta).cos() + theta.cos() * dec.sin();
+                (a.atan2(b) + z, c.clamp(-1.0, 1.0).asin())
+            }
+            Equinox::J2000 => {
+                let a = dec.cos() * (ra - z).sin();
+                let b = theta.cos() * dec.cos() * (ra - z).cos() + theta.sin() * dec.sin();
+                let c = -theta.sin() * dec.cos() * (ra - z).cos() + theta.cos() * dec.sin();
+                (a.atan2(b) - zeta, c.clamp(-1.0, 1.0).asin())
+            }
+        };
+        Self {
+            ra_hours: (ra.to_degrees() / 15.0).rem_euclid(24.0),
+            dec_degrees: dec.to_degrees(),
+            equinox,
+        }
+    }
+}
+
+fn julian_date(time: SystemTime) -> f64 {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs_f64(),
+        Err(e) => -e.duration().as_secs_f64(),
+    };
+    UNIX_EPOCH_JD + secs / 86_400.0
+}
+
+/// IAU 1976 precession angles `(zeta, z, theta)` in radians from J2000 to
+/// the Julian date `jd`.
+fn precession_angles(jd: f64) -> (f64, f64, f64) {
+    let t = (jd - J2000_JD) / 36_525.0;
+    let arcsec = |x: f64| (x / 3600.0).to_radians();
+    (
+        arcsec(2306.2181 * t + 0.30188 * t * t + 0.017998 * t * t * t),
+        arcsec(2306.2181 * t + 1.09468 * t * t + 0.018203 * t * t * t),
+        arcsec(2004.3109 * t - 0.42665 * t * t - 0.041833 * t * t * t),
+    )
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackingRate {
+    #[default]
+    Sidereal,
+    Lunar,
+    Solar,
+    /// Rates in arcseconds per second; `ra` is the tracking rate itself, not
+    /// an offset from sidereal.
+    Custom {
+        ra: f64,
+        dec: f64,
+    },
+}
+
+impl TrackingRate {
+    /// `(ra, dec)` in arcseconds per second.
+    pub fn rates(&self) -> (f64, f64) {
+        match *self {
+            TrackingRate::Sidereal => (SIDEREAL_RATE, 0.0),
+            TrackingRate::Lunar => (LUNAR_RATE, 0.0),
+            TrackingRate::Solar => (SOLAR_RATE, 0.0),
+            TrackingRate::Custom { ra, dec } => (ra, dec),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GuideDirection {
+    North,
+    South,
+    East,
+    West,
+}
+
+/// Side of the pier the telescope tube is on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PierSide {
+    East,
+    West,
+    #[default]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MeridianFlipState {
+    /// The target has not crossed the meridian.
+    NotNeeded,
+    /// Past the meridian, waiting for the flip.
+    Pending,
+    Flipping,
+    Done,
+    #[default]
+    Unknown,
+}
+
+/// Observing site. Longitude is positive east.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SiteLocation {
+    pub latitude_deg: f64,
+    pub longitude_deg: f64,
+    #[serde(default)]
+    pub elevation_m: f64,
+}
+
+impl SiteLocation {
+    pub fn validate(&self) -> Result<(), LightspeedError> {
+        if !(-90.0..=90.0).contains(&self.latitude_deg)
+            || !(-180.0..=180.0).contains(&self.longitude_deg)
+            || !self.elevation_m.is_finite()
+        {
+            return Err(PropertyErrorType::ValueOutOfRange.into());
+        }
+        Ok(())
+    }
+}
+
+pub trait Mount {
+    /// Equinox of the coordinates the mount reports and accepts.
+    fn equinox(&self) -> Equinox {
+        Equinox::JNow
+    }
+
+    /// Where the mount points, in [`Mount::equinox`].
+    fn coordinates(&self) -> Result<EquatorialCoords, LightspeedError>;
+
+    /// Start slewing to `target`, given in [`Mount::equinox`].
+    fn slew_to(&mut self, target: EquatorialCoords) -> Result<(), LightspeedError>;
+
+    fn is_slewing(&self) -> bool;
+
+    /// Stop a slew (and pulse guiding). Tracking state is left alone.
+    fn abort_slew(&mut self) -> Result<(), LightspeedError>;
+
+    /// Tell the mount it points at `coords`, given in [`Mount::equinox`].
+    fn sync_to(&mut self, _coords: EquatorialCoords) -> Result<(), LightspeedError> {
+        Err(LightspeedError::NotSupported)
+    }
+
+    fn is_parked(&self) -> bool {
+        false
+    }
+
+    fn park(&mut self) -> Result<(), LightspeedError> {
+        Err(LightspeedError::NotSupported)
+    }
+
+    fn unpark(&mut self) -> Result<(), LightspeedError> {
+        Err(LightspeedError::NotSupported)
+    }
+
+    fn is_tracking(&self) -> bool;
+    fn set_tracking(&mut self, on: bool) -> Result<(), LightspeedError>;
+
+    fn tracking_rate(&self) -> TrackingRate {
+        TrackingRate::Sidereal
+    }
+
+    fn set_tracking_rate(&mut self, _rate: TrackingRate) -> Result<(), LightspeedError> {
+        Err(LightspeedError::NotSupported)
+    }
+
+    /// Move at the guide rate in `direction` for `duration`. Returns once the
+    /// pulse has started.
+    fn pulse_guide(
+        &mut self,
+        _direction: GuideDirection,
+        _duration: Duration,
+    ) -> Result<(), LightspeedError> {
+        Err(LightspeedError::NotSupported)
+    }
+
+    fn is_pulse_guiding(&self) -> bool {
+        false
+    }
+
+    fn pier_side(&self) -> PierSide {
+        PierSide::Unknown
+    }
+
+    fn meridian_flip_state(&self) -> MeridianFlipState {
+        MeridianFlipState::Unknown
+    }
+
+    fn site(&self) -> Option<SiteLocation> {
+        None
+    }
+
+    fn set_site(&mut self, _site: SiteLocation) -> Result<(), LightspeedError> {
+        Err(LightspeedError::NotSupported)
+    }
+
+    /// The mount's clock, UTC.
+    fn time(&self) -> Option<SystemTime> {
+        None
+    }
+
+    fn set_time(&mut self, _time: SystemTime) -> Result<(), LightspeedError> {
+        Err(LightspeedError::NotSupported)
+    }
+}
+
+/// Commands accepted by [`MountDevice`], one per topic in [`MOUNT_TOPICS`].
+///
+/// Payloads are JSON: `slew` and `sync` take an [`EquatorialCoords`] object
+/// (equinox defaults to J2000), `pulse_guide` a [`PulseGuide`],
+/// `set_tracking` a bool, `set_tracking_rate` a [`TrackingRate`],
+/// `set_site` a [`SiteLocation`], `set_time` milliseconds since unix epoch
+/// and `set` an [`UpdatePropertyRequest`] for `tracking` or `tracking_rate`.
+/// `abort`, `park` and `unpark` ignore their payload.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MountCommand {
+    Slew(EquatorialCoords),
+    Sync(EquatorialCoords),
+    Abort,
+    Park,
+    Unpark,
+    PulseGuide(PulseGuide),
+    SetTracking(bool),
+    SetTrackingRate(TrackingRate),
+    SetSite(SiteLocation),
+    SetTime(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PulseGuide {
+    pub direction: GuideDirection,
+    pub duration_ms: u32,
+}
+
+/// Properties settable through the `set` topic.
+const WRITABLE_PROPS: [&str; 2] = ["tracking", "tracking_rate"];
+const READ_ONLY_PROPS: [&str; 9] = [
+    "coordinates",
+    "slewing",
+    "parked",
+    "pulse_guiding",
+    "pier_side",
+    "meridian_flip",
+    "site",
+    "time_unix_ms",
+    "equinox",
+];
+
+impl MountCommand {
+    pub fn parse(action: &str, payload: &[u8]) -> Result<Self, LightspeedError> {
+        fn json<'a, T: Deserialize<'a>>(payload: &'a [u8]) -> Result<T, LightspeedError> {
+            serde_json::from_slice(payload).map_err(|_| LightspeedError::ParseError)
+        }
+        Ok(match action {
+            "slew" => MountCommand::Slew(json(payload)?),
+            "sync" => MountCommand::Sync(json(payload)?),
+            "abort" => MountCommand::Abort,
+            "park" => MountCommand::Park,
+            "unpark" => MountCommand::Unpark,
+            "pulse_guide" => MountCommand::PulseGuide(json(payload)?),
+            "set_tracking" => MountCommand::SetTracking(json(payload)?),
+            "set_tracking_rate" => MountCommand::SetTrackingRate(json(payload)?),
+            "set_site" => MountCommand::SetSite(json(payload)?),
+            "set_time" => MountCommand::SetTime(json(payload)?),
+            "set" => {
+                let request: UpdatePropertyRequest = json(payload)?;
+                match (request.prop_name.as_str(), request.value) {
+                    ("tracking", PropValue::Bool(on)) => MountCommand::SetTracking(on),
+                    ("tracking_rate", PropValue::Str(rate)) => MountCommand::SetTrackingRate(
+                        serde_json::from_value(serde_json::Value::String(rate))
+                            .map_err(|_| PropertyErrorType::InvalidChoice)?,
+                    ),
+                    (name, _) if WRITABLE_PROPS.contains(&name) => {
+                        return Err(PropertyErrorType::InvalidValue.into())
+                    }
+                    (name, _) if READ_ONLY_PROPS.contains(&name) => {
+                        return Err(PropertyErrorType::CannotUpdateReadOnlyProp.into())
+                    }
+                    _ => return Err(PropertyErrorType::InvalidChoice.into()),
+                }
+            }
+            _ => return Err(LightspeedError::UnknownCommand),
+        })
+    }
+}
+
+/// State published by [`MountDevice`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MountState {
+    /// None if the mount cannot be read.
+    pub coordinates: Property<Option<EquatorialCoords>>,
+    pub slewing: Property<bool>,
+    pub parked: Property<bool>,
+    pub tracking: Property<bool>,
+    pub tracking_rate: Property<TrackingRate>,
+    pub pulse_guiding: Property<bool>,
+    pub pier_side: Property<PierSide>,
+    pub meridian_flip: Property<MeridianFlipState>,
+    pub site: Property<Option<SiteLocation>>,
+    pub time_unix_ms: Property<Option<u64>>,
+    pub last_error: Option<ErrorEnvelope>,
+}
+
+/// [`LightspeedDevice`] adapter for a [`Mount`].
+///
+/// J2000 and JNow targets are converted to the mount's equinox before they
+/// are handed over. `abort` skips the command queue: it is never refused
+/// with `QueueFull` and cancels the commands still waiting.
+pub struct MountDevice<M> {
+    id: Uuid,
+    name: String,
+    mount: M,
+    commands: CommandQueue<MountCommand>,
+}
+
+impl<M: Mount> MountDevice<M> {
+    pub fn new(id: Uuid, name: impl Into<String>, mount: M) -> Self {
+        Self {
+            id,
+            name: name.into(),
+            mount,
+            commands: CommandQueue::with_stop(MountCommand::Abort),
+        }
+    }
+
+    pub fn mount(&self) -> &M {
+        &self.mount
+    }
+
+    pub fn mount_mut(&mut self) -> &mut M {
+        &mut self.mount
+    }
+
+    pub fn state(&self) -> MountState {
+        let ro = |v| Property::new(v, Permission::ReadOnly);
+        MountState {
+            coordinates: Property::new(self.mount.coordinates().ok(), Permission::ReadOnly),
+            slewing: ro(self.mount.is_slewing()),
+            parked: ro(self.mount.is_parked()),
+            tracking: Property::new(self.mount.is_tracking(), Permission::ReadWrite),
+            tracking_rate: Property::new(self.mount.tracking_rate(), Permission::ReadWrite),
+            pulse_guiding: ro(self.mount.is_pulse_guiding()),
+            pier_side: Property::new(self.mount.pier_side(), Permission::ReadOnly),
+            meridian_flip: Property::new(self.mount.meridian_flip_state(), Permission::ReadOnly),
+            site: Property::new(self.mount.site(), Permission::ReadOnly),
+            time_unix_ms: Property::new(
+                self.mount
+                    .time()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_millis() as u64),
+                Permission::ReadOnly,
+            ),
+            last_error: self.commands.last_error().cloned(),
+        }
+    }
+
+    fn apply(&mut self, command: MountCommand) -> Result<(), LightspeedError> {
+        match command {
+            MountCommand::Slew(target) => {
+                target.validate()?;
+                let target = target.to_equinox(self.mount.equinox(), SystemTime::now());
+                self.mount.slew_to(target)
+            }
+            MountCommand::Sync(coords) => {
+                coords.validate()?;
+                let coords = coords.to_equinox(self.mount.equinox(), SystemTime::now());
+                self.mount.sync_to(coords)
+            }
+            MountCommand::Abort => self.mount.abort_slew(),
+            MountCommand::Park => self.mount.park(),
+            MountCommand::Unpark => self.mount.unpark(),
+            MountCommand::PulseGuide(pulse) => {
+                if pulse.duration_ms == 0 {
+                    return Err(PropertyErrorType::ValueOutOfRange.into());
+                }
+                self.mount.pulse_guide(
+                    pulse.direction,
+                    Duration::from_millis(pulse.duration_ms as u64),
+                )
+            }
+            MountCommand::SetTracking(on) => self.mount.set_tracking(on),
+            MountCommand::SetTrackingRate(rate) => {
+                let (ra, dec) = rate.rates();
+                if !ra.is_finite() || !dec.is_finite() {
+                    return Err(PropertyErrorType::InvalidValue.into());
+                }
+                self.mount.set_tracking_rate(rate)
+            }
+            MountCommand::SetSite(site) => {
+                site.validate()?;
+                self.mount.set_site(site)
+            }
+            MountCommand::SetTime(unix_ms) => self
+                .mount
+                .set_time(UNIX_EPOCH + Duration::from_millis(unix_ms)),
+        }
+    }
+}
+
+impl<M: Mount + Send + 'static> LightspeedDevice for MountDevice<M> {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn dev_type(&self) -> DeviceType {
+        DeviceType::Mount
+    }
+
+    fn command_topics(&self) -> &[&str] {
+        MOUNT_TOPICS
+    }
+
+    fn state_json(&self) -> String {
+        serde_json::to_string(&self.state()).unwrap_or_default()
+    }
+
+    fn dispatcher(&self) -> Dispatcher {
+        self.commands.dispatcher(MountCommand::parse)
+    }
+
+    fn tick(&mut self, state_tx: &SyncSender<(Uuid, String)>) {
+        CommandQueue::drain(self, |d| &mut d.commands, Self::apply);
+        let _ = state_tx.try_send((self.id, self.state_json()));
+    }
+
+    fn is_busy(&self) -> bool {
+        self.mount.is_slewing() || self.mount.is_pulse_guiding()
+    }
+
+    fn abort(&mut self) {
+        if let Err(e) = self.mount.abort_slew() {
+            log::warn!("{}: abort failed: {e:?}", self.name);
+        }
+    }
+
+    fn close(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::CommandQueue;
+    use crate::properties::Prop;
+    use crate::protocol::ErrorCode;
+    use std::sync::mpsc::sync_channel;
+
+    /// Slews instantly, works in JNow.
+    #[derive(Default)]
+    struct FakeMount {
+        position: Option<EquatorialCoords>,
+        tracking: bool,
+        rate: TrackingRate,
+        parked: bool,
+        pulses: Vec<(GuideDirection, Duration)>,
+        aborts: u32,
+    }
+
+    impl Mount for FakeMount {
+        fn coordinates(&self) -> Result<EquatorialCoords, LightspeedError> {
+            self.position.ok_or(LightspeedError::DeviceConnectionError)
+        }
+
+        fn slew_to(&mut self, target: EquatorialCoords) -> Result<(), LightspeedError> {
+            self.position = Some(target);
+            Ok(())
+        }
+
+        fn is_slewing(&self) -> bool {
+            false
+        }
+
+        fn abort_slew(&mut self) -> Result<(), LightspeedError> {
+            self.aborts += 1;
+            Ok(())
+        }
+
+        fn is_parked(&self) -> bool {
+            self.parked
+        }
+
+        fn park(&mut self) -> Result<(), LightspeedError> {
+            self.parked = true;
+            self.tracking = false;
+            Ok(())
+        }
+
+        fn is_tracking(&self) -> bool {
+            self.tracking
+        }
+
+        fn set_tracking(&mut self, on: bool) -> Result<(), LightspeedError> {
+            self.tracking = on;
+            Ok(())
+        }
+
+        fn tracking_rate(&self) -> TrackingRate {
+            self.rate
+        }
+
+        fn set_tracking_rate(&mut self, rate: TrackingRate) -> Result<(), LightspeedError> {
+            self.rate = rate;
+            Ok(())
+        }
+
+        fn pulse_guide(
+            &mut self,
+            direction: GuideDirection,
+            duration: Duration,
+        ) -> Result<(), LightspeedError> {
+            self.pulses.push((direction, duration));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn precesses_between_j2000_and_jnow() {
+        // Meeus example 21.b: theta Persei to 2028 Nov 13.19, without its
+        // proper motion (about 4 arcseconds over the interval).
+        let epoch = UNIX_EPOCH + Duration::from_secs_f64((2_462_088.69 - UNIX_EPOCH_JD) * 86_400.0);
+        let j2000 = EquatorialCoords::new(41.054063 / 15.0, 49.227750, Equinox::J2000);
+        let now = j2000.to_equinox(Equinox::JNow, epoch);
+        assert_eq!(now.equinox, Equinox::JNow);
+        assert!((now.ra_hours * 15.0 - 41.547214).abs() < 0.005, "{now:?}");
+        assert!((now.dec_degrees - 49.348483).abs() < 0.005, "{now:?}");
+
+        let back = now.to_equinox(Equinox::J2000, epoch);
+        assert!((back.ra_hours - j2000.ra_hours).abs() < 1e-9);
+        assert!((back.dec_degrees - j2000.dec_degrees).abs() < 1e-9);
+        assert_eq!(j2000.to_equinox(Equinox::J2000, epoch), j2000);
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            MountCommand::parse("slew", br#"{"ra_hours": 5.5, "dec_degrees": -5.4}"#).unwrap(),
+            MountCommand::Slew(EquatorialCoords::new(5.5, -5.4, Equinox::J2000))
+        );
+        assert_eq!(
+            MountCommand::parse(
+                "pulse_guide",
+                br#"{"direction": "east", "duration_ms": 250}"#
+            )
+            .unwrap(),
+            MountCommand::PulseGuide(PulseGuide {
+                direction: GuideDirection::East,
+                duration_ms: 250
+            })
+        );
+        assert_eq!(
+            MountCommand::parse(
+                "set_tracking_rate",
+                br#"{"custom": {"ra": 15.0, "dec": 0.5}}"#
+            )
+            .unwrap(),
+            MountCommand::SetTrackingRate(TrackingRate::Custom { ra: 15.0, dec: 0.5 })
+        );
+        assert_eq!(
+            MountCommand::parse(
+                "set",
+                br#"{"prop_name": "tracking_rate", "value": "lunar"}"#
+            )
+            .unwrap(),
+            MountCommand::SetTrackingRate(TrackingRate::Lunar)
+        );
+        assert!(matches!(
+            MountCommand::parse("set", br#"{"prop_name": "pier_side", "value": "east"}"#),
+            Err(LightspeedError::PropertyError(
+                PropertyErrorType::CannotUpdateReadOnlyProp
+            ))
+        ));
+        assert!(matches!(
+            MountCommand::parse("goto", b"{}"),
+            Err(LightspeedError::UnknownCommand)
+        ));
+    }
+
+    #[test]
+    fn device_drives_mount() {
+        let mut device = MountDevice::new(Uuid::nil(), "mount", FakeMount::default());
+        let dispatch = device.dispatcher();
+        let (state_tx, state_rx) = sync_channel(8);
+
+        dispatch(
+            "slew",
+            br#"{"ra_hours": 5.5, "dec_degrees": -5.4, "equinox": "jnow"}"#,
+        )
+        .unwrap();
+        dispatch("set", br#"{"prop_name": "tracking", "value": true}"#).unwrap();
+        dispatch(
+            "pulse_guide",
+            br#"{"direction": "north", "duration_ms": 100}"#,
+        )
+        .unwrap();
+        device.tick(&state_tx);
+
+        let (_, json) = state_rx.try_recv().unwrap();
+        let state: MountState = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            state.coordinates.value(),
+            &Some(EquatorialCoords::new(5.5, -5.4, Equinox::JNow))
+        );
+        assert!(state.tracking.value());
+        assert_eq!(state.last_error, None);
+        assert_eq!(
+            device.mount().pulses,
+            [(GuideDirection::North, Duration::from_millis(100))]
+        );
+
+        dispatch("slew", br#"{"ra_hours": 25.0, "dec_degrees": 0.0}"#).unwrap();
+        device.tick(&state_tx);
+        assert_eq!(
+            device.state().last_error.unwrap().message,
+            "value out of range"
+        );
+
+        dispatch("park", b"").unwrap();
+        dispatch(
+            "set_site",
+            br#"{"latitude_deg": 45.0, "longitude_deg": 9.0}"#,
+        )
+        .unwrap();
+        device.tick(&state_tx);
+        assert!(device.state().parked.value());
+        assert_eq!(
+            device.state().last_error.unwrap().code,
+            ErrorCode::Validation
+        );
+    }
+
+    #[test]
+    fn abort_skips_a_full_queue() {
+        let mut device = MountDevice::new(Uuid::nil(), "mount", FakeMount::default());
+        let dispatch = device.dispatcher();
+        let (state_tx, _state_rx) = sync_channel(8);
+        for _ in 0..CommandQueue::<MountCommand>::CAPACITY {
+            dispatch("slew", br#"{"ra_hours": 5.5, "dec_degrees": -5.4}"#).unwrap();
+        }
+        assert!(matches!(
+            dispatch("park", b""),
+            Err(LightspeedError::QueueFull)
+        ));
+
+        dispatch("abort", b"").unwrap();
+        device.tick(&state_tx);
+        assert_eq!(device.mount().aborts, 1);
+        assert_eq!(device.mount().position, None);
+    }
+}
@@ -12,21 +12,109 @@
 //! `DeviceStatus` is Online AND its owning runner's `RunnerStatus` is Online.
 //! Stale per-device retained Online statuses are overridden by a runner
 //! Offline LWT.
+//!
+//! Besides `online` and `offline`, a device can report `starting`,
+//! `degraded`, `busy` or `maintenance`. On the wire `state` stays the coarse
+//! `online`/`offline` so older peers keep parsing it; the detailed state goes
+//! in the optional `detail` field. Use [`DeviceStatus::presence_state`] to
+//! read it back and [`PresenceState::is_online`] for the coarse view the
+//! reconciliation rule above works with.
 
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::protocol::ErrorEnvelope;
+use crate::DeviceType;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum PresenceState {
     Online,
     Offline,
+    /// Process is up, hardware is still being initialized.
+    Starting,
+    /// Connected but erroring, e.g. the cooler stopped responding.
+    Degraded,
+    /// Connected and running an exclusive operation.
+    Busy,
+    /// Connected but put aside by an operator.
+    Maintenance,
+    /// A state introduced by a newer peer. Treated as not online.
+    #[serde(other)]
+    Unknown,
+}
+
+impl PresenceState {
+    /// `true` if the device process is up and its hardware is connected.
+    /// `Starting`, `Offline` and `Unknown` map to `false`.
+    pub fn is_online(&self) -> bool {
+        matches!(
+            self,
+            PresenceState::Online
+                | PresenceState::Degraded
+                | PresenceState::Busy
+                | PresenceState::Maintenance
+        )
+    }
+
+    /// `Online` or `Offline`, as published in the `state` field.
+    pub fn coarse(self) -> PresenceState {
+        if self.is_online() {
+            PresenceState::Online
+        } else {
+            PresenceState::Offline
+        }
+    }
+}
+
+/// Presence as reported by a device: the state plus an optional explanation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DevicePresence {
+    pub state: PresenceState,
+    pub reason: Option<String>,
+    pub error: Option<ErrorEnvelope>,
+}
+
+impl DevicePresence {
+    pub fn new(state: PresenceState) -> Self {
+        Self {
+            state,
+            reason: None,
+            error: None,
+        }
+    }
+
+    pub fn with_reason(state: PresenceState, reason: impl Into<String>) -> Self {
+        Self {
+            state,
+            reason: Some(reason.into()),
+            error: None,
+        }
+    }
+
+    pub fn with_error(state: PresenceState, error: ErrorEnvelope) -> Self {
+        Self {
+            state,
+            reason: Some(error.message.clone()),
+            error: Some(error),
+        }
+    }
+}
+
+impl Default for DevicePresence {
+    fn default() -> Self {
+        Self::new(PresenceState::Online)
+    }
 }
 
 /// Per-device presence status. Published retained to `devices/{uuid}/status`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeviceStatus {
+    /// Coarse state, always `Online` or `Offline`.
     pub state: PresenceState,
+    /// Detailed state when it differs from `state`, e.g. `Degraded`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<PresenceState>,
     /// The runner process that owns this device. Server uses this to look up
     /// `RunnerStatus` for reconciliation.
     pub runner_id: Uuid,
@@ -35,6 +123,45 @@ pub struct DeviceStatus {
     /// Driver crate version, e.g. `"0.4.1"`.
     pub driver_version: String,
     pub pid: u32,
+    /// Human-readable explanation for a non-Online state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Structured error behind a `Degraded` state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorEnvelope>,
+    #[serde(flatten)]
+    pub inventory: DeviceInventory,
+}
+
+impl DeviceStatus {
+    /// Detailed state, falling back to the coarse one for payloads without
+    /// a `detail`.
+    pub fn presence_state(&self) -> PresenceState {
+        self.detail.unwrap_or(self.state)
+    }
+}
+
+/// What a device is and where it is attached. Every field is optional;
+/// absent fields are omitted on the wire.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceInventory {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_type: Option<DeviceType>,
+    /// Hardware model, e.g. `"QHY268M"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firmware_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial_number: Option<String>,
+    /// Host name of the machine running the driver.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Serial or USB port, e.g. `"/dev/ttyUSB0"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<String>,
 }
 
 /// Per-runner presence status. Published retained to `runners/{runner_id}/status`.
@@ -53,20 +180,163 @@ pub struct RunnerStatus {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::ErrorCode;
 
     #[test]
     fn device_status_roundtrip() {
         let s = DeviceStatus {
             state: PresenceState::Online,
+            detail: None,
             runner_id: Uuid::now_v7(),
             started_at: 1_700_000_000,
             driver_version: "0.4.1".into(),
             pid: 1234,
+            reason: None,
+            error: None,
+            inventory: DeviceInventory::default(),
         };
         let json = serde_json::to_string(&s).unwrap();
         let back: DeviceStatus = serde_json::from_str(&json).unwrap();
         assert_eq!(back.state, PresenceState::Online);
         assert_eq!(back.driver_version, "0.4.1");
+        assert!(!json.contains("reason"));
+        assert!(!json.contains("model"));
+    }
+
+    #[test]
+    fn device_status_inventory_is_flat() {
+        let s = DeviceStatus {
+            state: PresenceState::Online,
+            detail: None,
+            runner_id: Uuid::now_v7(),
+            started_at: 1_700_000_000,
+            driver_version: "0.4.1".into(),
+            pid: 1234,
+            reason: None,
+            error: None,
+            inventory: DeviceInventory {
+                name: Some("Main camera".into()),
+                device_type: Some(DeviceType::Ccd),
+                model: Some("QHY268M".into()),
+                port: Some("/dev/ttyUSB0".into()),
+                ..Default::default()
+            },
+        };
+        let json = serde_json::to_string(&s).unwrap();
+        assert!(json.contains(r#""device_type":"ccd""#));
+        assert!(json.contains(r#""model":"QHY268M""#));
+        let back: DeviceStatus = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.inventory, s.inventory);
+    }
+
+    #[test]
+    fn unknown_device_type_still_parses() {
+        let json = format!(
+            r#"{{"state":"online","runner_id":"{}","started_at":1,"driver_version":"0.4.1","pid":1,"device_type":"rotator","model":"X"}}"#,
+            Uuid::nil()
+        );
+        let s: DeviceStatus = serde_json::from_str(&json).unwrap();
+        assert_eq!(s.inventory.device_type, Some(DeviceType::Unknown));
+        assert_eq!(s.inventory.model.as_deref(), Some("X"));
+    }
+
+    #[test]
+    fn degraded_status_carries_error() {
+        let error = ErrorEnvelope {
+            code: ErrorCode::DriverUnavailable,
+            message: "cooler not responding".into(),
+            field: None,
+        };
+        let s = DeviceStatus {
+            state: PresenceState::Online,
+            detail: Some(PresenceState::Degraded),
+            runner_id: Uuid::now_v7(),
+            started_at: 1_700_000_000,
+            driver_version: "0.4.1".into(),
+            pid: 1234,
+            reason: Some(error.message.clone()),
+            error: Some(error),
+            inventory: DeviceInventory::default(),
+        };
+        let json = serde_json::to_string(&s).unwrap();
+        assert!(json.contains(r#""state":"online""#));
+        assert!(json.contains(r#""detail":"degraded""#));
+        assert!(json.contains(r#""code":"driver_unavailable""#));
+        let back: DeviceStatus = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.presence_state(), PresenceState::Degraded);
+    }
+
+    #[test]
+    fn old_peer_parses_detailed_status() {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "lowercase")]
+        enum OldState {
+            Online,
+            Offline,
+        }
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct OldDeviceStatus {
+            state: OldState,
+            runner_id: Uuid,
+            started_at: u64,
+            driver_version: String,
+            pid: u32,
+        }
+
+        let s = DeviceStatus {
+            state: PresenceState::Starting.coarse(),
+            detail: Some(PresenceState::Starting),
+            runner_id: Uuid::now_v7(),
+            started_at: 1_700_000_000,
+            driver_version: "0.4.1".into(),
+            pid: 1234,
+            reason: Some("warming up".into()),
+            error: None,
+            inventory: DeviceInventory::default(),
+        };
+        let json = serde_json::to_string(&s).unwrap();
+        let old: OldDeviceStatus = serde_json::from_str(&json).unwrap();
+        assert!(matches!(old.state, OldState::Offline));
+        let s = DeviceStatus {
+            state: PresenceState::Busy.coarse(),
+            detail: Some(PresenceState::Busy),
+            ..s
+        };
+        let old: OldDeviceStatus =
+            serde_json::from_str(&serde_json::to_string(&s).unwrap()).unwrap();
+        assert!(matches!(old.state, OldState::Online));
+    }
+
+    #[test]
+    fn old_device_status_still_parses() {
+        let json = format!(
+            r#"{{"state":"online","runner_id":"{}","started_at":1,"driver_version":"0.4.1","pid":1}}"#,
+            Uuid::nil()
+        );
+        let s: DeviceStatus = serde_json::from_str(&json).unwrap();
+        assert_eq!(s.presence_state(), PresenceState::Online);
+        assert!(s.reason.is_none());
+        assert!(s.error.is_none());
+    }
+
+    #[test]
+    fn unknown_state_is_not_online() {
+        let state: PresenceState = serde_json::from_str(r#""hibernating""#).unwrap();
+        assert_eq!(state, PresenceState::Unknown);
+        assert!(!state.is_online());
+    }
+
+    #[test]
+    fn coarse_online_mapping() {
+        assert!(PresenceState::Online.is_online());
+        assert!(PresenceState::Degraded.is_online());
+        assert!(PresenceState::Busy.is_online());
+        assert!(PresenceState::Maintenance.is_online());
+        assert!(!PresenceState::Starting.is_online());
+        assert!(!PresenceState::Offline.is_online());
+        assert_eq!(PresenceState::Busy.coarse(), PresenceState::Online);
+        assert_eq!(PresenceState::Starting.coarse(), PresenceState::Offline);
     }
 
     #[test]
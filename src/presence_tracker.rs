@@ -0,0 +1,582 @@
+//! Server-side presence reconciliation.
+//!
+//! [`PresenceTracker`] ingests the retained `devices/{uuid}/status` and
+//! `runners/{runner_id}/status` messages and applies the rule documented in
+//! [`crate::presence`]: a device is effectively online iff its own
+//! `DeviceStatus` is online AND its owning runner's `RunnerStatus` is online.
+//! Otherwise the device is reported `Offline`.
+//!
+//! Retained messages can arrive in any order, e.g. the LWT of a previous
+//! runner incarnation after the Online status of the current one. Updates
+//! carrying an older `started_at` than the status already held are ignored.
+//! Two device statuses with the same `started_at` are ordered by `runner_id`,
+//! so the same set of messages yields the same result in any arrival order.
+//! An empty payload clears the retained topic and forgets the entry.
+//!
+//! Topics are parsed in the tracker's [`TopicNamespace`], the root namespace
+//! unless built with [`PresenceTracker::with_namespace`].
+//!
+//! The tracker does no I/O: feed it topic/payload pairs and act on the
+//! returned [`PresenceEvent`]s.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use uuid::Uuid;
+
+use crate::presence::{DeviceStatus, PresenceState, RunnerStatus};
+use crate::topics::{Topic, TopicNamespace};
+use crate::LightspeedError;
+
+/// Change notifications emitted by [`PresenceTracker`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PresenceEvent {
+    /// The effective presence of a device changed.
+    Changed {
+        device_uuid: Uuid,
+        previous: PresenceState,
+        current: PresenceState,
+    },
+    /// A device is claimed by more than one online runner. `runners` is
+    /// empty once the conflict is resolved.
+    Conflict {
+        device_uuid: Uuid,
+        runners: Vec<Uuid>,
+    },
+}
+
+impl PresenceEvent {
+    /// The device this event is about.
+    pub fn device_uuid(&self) -> Uuid {
+        match self {
+            PresenceEvent::Changed { device_uuid, .. }
+            | PresenceEvent::Conflict { device_uuid, .. } => *device_uuid,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PresenceTracker {
+    namespace: TopicNamespace,
+    devices: BTreeMap<Uuid, DeviceStatus>,
+    runners: BTreeMap<Uuid, RunnerStatus>,
+    effective: BTreeMap<Uuid, PresenceState>,
+    conflicts: BTreeMap<Uuid, Vec<Uuid>>,
+}
+
+impl PresenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_namespace(namespace: TopicNamespace) -> Self {
+        Self {
+            namespace,
+            ..Self::default()
+        }
+    }
+
+    /// Filters to subscribe to in order to feed this tracker.
+    pub fn filters(&self) -> [String; 2] {
+        [
+            self.namespace.device_status_filter(),
+            self.namespace.runner_status_filter(),
+        ]
+    }
+
+    /// Ingest a raw MQTT message. Topics other than device or runner status
+    /// topics are ignored and yield no events.
+    pub fn ingest(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+    ) -> Result<Vec<PresenceEvent>, LightspeedError> {
+        match self.namespace.parse(topic) {
+            Ok(Topic::DeviceStatus { uuid }) => {
+                let status = parse_payload(payload)?;
+                Ok(self.apply_device_status(uuid, status))
+            }
+            Ok(Topic::RunnerStatus { runner_id }) => {
+                let status = parse_payload(payload)?;
+                Ok(self.apply_runner_status(runner_id, status))
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Apply a device status. `None` means the retained topic was cleared.
+    pub fn apply_device_status(
+        &mut self,
+        uuid: Uuid,
+        status: Option<DeviceStatus>,
+    ) -> Vec<PresenceEvent> {
+        match status {
+            Some(status) => {
+                if let Some(current) = self.devices.get(&uuid) {
+                    if (status.started_at, status.runner_id)
+                        < (current.started_at, current.runner_id)
+                    {
+                        return Vec::new();
+                    }
+                }
+                self.devices.insert(uuid, status);
+            }
+            None => {
+                self.devices.remove(&uuid);
+            }
+        }
+        self.reconcile()
+    }
+
+    /// Apply a runner status. `None` means the retained topic was cleared.
+    pub fn apply_runner_status(
+        &mut self,
+        runner_id: Uuid,
+        status: Option<RunnerStatus>,
+    ) -> Vec<PresenceEvent> {
+        match status {
+            Some(status) => {
+                if let Some(current) = self.runners.get(&runner_id) {
+                    if status.started_at < current.started_at {
+                        return Vec::new();
+                    }
+                }
+                self.runners.insert(runner_id, status);
+            }
+            None => {
+                self.runners.remove(&runner_id);
+            }
+        }
+        self.reconcile()
+    }
+
+    /// Effective presence of a device. Unknown devices are `Offline`.
+    pub fn effective(&self, uuid: Uuid) -> PresenceState {
+        self.effective
+            .get(&uuid)
+            .copied()
+            .unwrap_or(PresenceState::Offline)
+    }
+
+    /// Effective presence of every known device.
+    pub fn devices(&self) -> impl Iterator<Item = (Uuid, PresenceState)> + '_ {
+        self.effective.iter().map(|(uuid, state)| (*uuid, *state))
+    }
+
+    /// Last device status received for `uuid`, if any.
+    pub fn device_status(&self, uuid: Uuid) -> Option<&DeviceStatus> {
+        self.devices.get(&uuid)
+    }
+
+    /// Last runner status received for `runner_id`, if any.
+    pub fn runner_status(&self, runner_id: Uuid) -> Option<&RunnerStatus> {
+        self.runners.get(&runner_id)
+    }
+
+    /// Online runners currently claiming `uuid`, if more than one does.
+    pub fn conflict(&self, uuid: Uuid) -> Option<&[Uuid]> {
+        self.conflicts.get(&uuid).map(Vec::as_slice)
+    }
+
+    fn compute(&self, uuid: Uuid, status: &DeviceStatus) -> PresenceState {
+        let runner_online = self
+            .runners
+            .get(&status.runner_id)
+            .is_some_and(|r| r.state.is_online() && r.device_uuids.contains(&uuid));
+        if runner_online {
+            status.presence_state()
+        } else {
+            PresenceState::Offline
+        }
+    }
+
+    fn reconcile(&mut self) -> Vec<PresenceEvent> {
+        let mut events = Vec::new();
+
+        let effective: BTreeMap<Uuid, PresenceState> = self
+            .devices
+            .iter()
+            .map(|(uuid, status)| (*uuid, self.compute(*uuid, status)))
+            .collect();
+        for (uuid, current) in &effective {
+            let previous = self.effective(*uuid);
+            if previous != *current {
+                events.push(PresenceEvent::Changed {
+                    device_uuid: *uuid,
+                    previous,
+                    current: *current,
+                });
+            }
+        }
+        for (uuid, previous) in &self.effective {
+            if !effective.contains_key(uuid) && *previous != PresenceState::Offline {
+                events.push(PresenceEvent::Changed {
+                    device_uuid: *uuid,
+                    previous: *previous,
+                    current: PresenceState::Offline,
+                });
+            }
+        }
+        self.effective = effective;
+
+        let mut claims: BTreeMap<Uuid, BTreeSet<Uuid>> = BTreeMap::new();
+        for (runner_id, runner) in &self.runners {
+            if runner.state.is_online() {
+                for uuid in &runner.device_uuids {
+                    claims.entry(*uuid).or_default().insert(*runner_id);
+                }
+            }
+        }
+        let conflicts: BTreeMap<Uuid, Vec<Uuid>> = claims
+            .into_iter()
+            .filter(|(_, runners)| runners.len() > 1)
+            .map(|(uuid, runners)| (uuid, runners.into_iter().collect()))
+            .collect();
+        for (uuid, runners) in &conflicts {
+            if self.conflicts.get(uuid) != Some(runners) {
+                events.push(PresenceEvent::Conflict {
+                    device_uuid: *uuid,
+                    runners: runners.clone(),
+                });
+            }
+        }
+        for uuid in self.conflicts.keys() {
+            if !conflicts.contains_key(uuid) {
+                events.push(PresenceEvent::Conflict {
+                    device_uuid: *uuid,
+                    runners: Vec::new(),
+                });
+            }
+        }
+        self.conflicts = conflicts;
+
+        // Stable, so a device's Changed event stays ahead of its Conflict.
+        events.sort_by_key(PresenceEvent::device_uuid);
+        events
+    }
+}
+
+fn parse_payload<T: serde::de::DeserializeOwned>(
+    payload: &[u8],
+) -> Result<Option<T>, LightspeedError> {
+    if payload.is_empty() {
+        return Ok(None);
+    }
+    serde_json::from_slice(payload)
+        .map(Some)
+        .map_err(|_| LightspeedError::ParseError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::topics;
+
+    fn device(state: PresenceState, runner_id: Uuid, started_at: u64) -> DeviceStatus {
+        DeviceStatus {
+            state: state.coarse(),
+            detail: Some(state).filter(|s| *s != s.coarse()),
+            runner_id,
+            started_at,
+            driver_version: "0.4.1".into(),
+            pid: 1,
+            reason: None,
+            error: None,
+            inventory: Default::default(),
+        }
+    }
+
+    fn runner(state: PresenceState, device_uuids: Vec<Uuid>, started_at: u64) -> RunnerStatus {
+        RunnerStatus {
+            state,
+            device_uuids,
+            started_at,
+            runner_version: "0.4.1".into(),
+            pid: 1,
+        }
+    }
+
+    fn ingest<T: serde::Serialize>(
+        tracker: &mut PresenceTracker,
+        topic: String,
+        msg: &T,
+    ) -> Vec<PresenceEvent> {
+        tracker
+            .ingest(&topic, &serde_json::to_vec(msg).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn online_requires_device_and_runner() {
+        let mut t = PresenceTracker::new();
+        let (dev, run) = (Uuid::now_v7(), Uuid::now_v7());
+
+        let events = ingest(
+            &mut t,
+            topics::device_status(dev),
+            &device(PresenceState::Online, run, 10),
+        );
+        assert!(events.is_empty());
+        assert_eq!(t.effective(dev), PresenceState::Offline);
+
+        let events = ingest(
+            &mut t,
+            topics::runner_status(run),
+            &runner(PresenceState::Online, vec![dev], 10),
+        );
+        assert_eq!(
+            events,
+            vec![PresenceEvent::Changed {
+                device_uuid: dev,
+                previous: PresenceState::Offline,
+                current: PresenceState::Online,
+            }]
+        );
+        assert_eq!(t.effective(dev), PresenceState::Online);
+    }
+
+    #[test]
+    fn runner_lwt_overrides_stale_device_online() {
+        let mut t = PresenceTracker::new();
+        let (dev, run) = (Uuid::now_v7(), Uuid::now_v7());
+        ingest(
+            &mut t,
+            topics::runner_status(run),
+            &runner(PresenceState::Online, vec![dev], 10),
+        );
+        ingest(
+            &mut t,
+            topics::device_status(dev),
+            &device(PresenceState::Online, run, 10),
+        );
+
+        let events = ingest(
+            &mut t,
+            topics::runner_status(run),
+            &runner(PresenceState::Offline, vec![dev], 10),
+        );
+        assert_eq!(events.len(), 1);
+        assert_eq!(t.effective(dev), PresenceState::Offline);
+    }
+
+    #[test]
+    fn rich_device_state_passes_through() {
+        let mut t = PresenceTracker::new();
+        let (dev, run) = (Uuid::now_v7(), Uuid::now_v7());
+        ingest(
+            &mut t,
+            topics::runner_status(run),
+            &runner(PresenceState::Online, vec![dev], 10),
+        );
+        ingest(
+            &mut t,
+            topics::device_status(dev),
+            &device(PresenceState::Busy, run, 10),
+        );
+        assert_eq!(t.effective(dev), PresenceState::Busy);
+    }
+
+    #[test]
+    fn stale_retained_lwt_is_ignored() {
+        let mut t = PresenceTracker::new();
+        let (dev, run) = (Uuid::now_v7(), Uuid::now_v7());
+        ingest(
+            &mut t,
+            topics::device_status(dev),
+            &device(PresenceState::Online, run, 20),
+        );
+        ingest(
+            &mut t,
+            topics::runner_status(run),
+            &runner(PresenceState::Online, vec![dev], 20),
+        );
+
+        // LWT from the previous incarnation of the same runner id.
+        let events = ingest(
+            &mut t,
+            topics::runner_status(run),
+            &runner(PresenceState::Offline, vec![dev], 10),
+        );
+        assert!(events.is_empty());
+        assert_eq!(t.effective(dev), PresenceState::Online);
+    }
+
+    #[test]
+    fn device_moved_to_new_runner() {
+        let mut t = PresenceTracker::new();
+        let (dev, old, new) = (Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7());
+        ingest(
+            &mut t,
+            topics::runner_status(new),
+            &runner(PresenceState::Online, vec![dev], 20),
+        );
+        ingest(
+            &mut t,
+            topics::device_status(dev),
+            &device(PresenceState::Online, new, 20),
+        );
+
+        // Late delivery of the device status published by the old runner.
+        ingest(
+            &mut t,
+            topics::device_status(dev),
+            &device(PresenceState::Online, old, 10),
+        );
+        assert_eq!(t.device_status(dev).unwrap().runner_id, new);
+
+        // The old runner's LWT doesn't affect a device it no longer owns.
+        ingest(
+            &mut t,
+            topics::runner_status(old),
+            &runner(PresenceState::Offline, vec![dev], 10),
+        );
+        assert_eq!(t.effective(dev), PresenceState::Online);
+    }
+
+    #[test]
+    fn detects_device_claimed_by_two_runners() {
+        let mut t = PresenceTracker::new();
+        let (dev, a, b) = (Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7());
+        ingest(
+            &mut t,
+            topics::runner_status(a),
+            &runner(PresenceState::Online, vec![dev], 10),
+        );
+        let events = ingest(
+            &mut t,
+            topics::runner_status(b),
+            &runner(PresenceState::Online, vec![dev], 10),
+        );
+        let mut expected = vec![a, b];
+        expected.sort();
+        assert_eq!(
+            events,
+            vec![PresenceEvent::Conflict {
+                device_uuid: dev,
+                runners: expected.clone(),
+            }]
+        );
+        assert_eq!(t.conflict(dev), Some(expected.as_slice()));
+
+        let events = ingest(
+            &mut t,
+            topics::runner_status(b),
+            &runner(PresenceState::Offline, vec![dev], 10),
+        );
+        assert_eq!(
+            events,
+            vec![PresenceEvent::Conflict {
+                device_uuid: dev,
+                runners: vec![],
+            }]
+        );
+        assert!(t.conflict(dev).is_none());
+    }
+
+    #[test]
+    fn equal_started_at_breaks_tie_on_runner_id() {
+        let (dev, a, b) = (Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7());
+        let winner = a.max(b);
+        for order in [[a, b], [b, a]] {
+            let mut t = PresenceTracker::new();
+            for run in order {
+                ingest(
+                    &mut t,
+                    topics::device_status(dev),
+                    &device(PresenceState::Online, run, 10),
+                );
+            }
+            assert_eq!(t.device_status(dev).unwrap().runner_id, winner);
+        }
+    }
+
+    #[test]
+    fn events_are_ordered_by_device() {
+        let mut t = PresenceTracker::new();
+        let run = Uuid::now_v7();
+        let mut devs: Vec<Uuid> = (0..16).map(|_| Uuid::now_v7()).collect();
+        devs.reverse();
+        for dev in &devs {
+            ingest(
+                &mut t,
+                topics::device_status(*dev),
+                &device(PresenceState::Online, run, 10),
+            );
+        }
+        let events = ingest(
+            &mut t,
+            topics::runner_status(run),
+            &runner(PresenceState::Online, devs.clone(), 10),
+        );
+        devs.sort();
+        assert_eq!(
+            events
+                .iter()
+                .map(PresenceEvent::device_uuid)
+                .collect::<Vec<_>>(),
+            devs
+        );
+    }
+
+    #[test]
+    fn empty_payload_clears_entry() {
+        let mut t = PresenceTracker::new();
+        let (dev, run) = (Uuid::now_v7(), Uuid::now_v7());
+        ingest(
+            &mut t,
+            topics::runner_status(run),
+            &runner(PresenceState::Online, vec![dev], 10),
+        );
+        ingest(
+            &mut t,
+            topics::device_status(dev),
+            &device(PresenceState::Online, run, 10),
+        );
+
+        let events = t.ingest(&topics::runner_status(run), &[]).unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(t.runner_status(run).is_none());
+        assert_eq!(t.effective(dev), PresenceState::Offline);
+    }
+
+    #[test]
+    fn namespaced_tracker_ignores_other_namespaces() {
+        let ns = TopicNamespace::new("obs/siteA").unwrap();
+        let mut t = PresenceTracker::with_namespace(ns.clone());
+        let (dev, run) = (Uuid::now_v7(), Uuid::now_v7());
+        ingest(
+            &mut t,
+            topics::runner_status(run),
+            &runner(PresenceState::Online, vec![dev], 10),
+        );
+        ingest(
+            &mut t,
+            topics::device_status(dev),
+            &device(PresenceState::Online, run, 10),
+        );
+        assert!(t.device_status(dev).is_none());
+
+        assert_eq!(t.filters()[0], "obs/siteA/devices/+/status");
+        ingest(
+            &mut t,
+            ns.runner_status(run),
+            &runner(PresenceState::Online, vec![dev], 10),
+        );
+        ingest(
+            &mut t,
+            ns.device_status(dev),
+            &device(PresenceState::Online, run, 10),
+        );
+        assert_eq!(t.effective(dev), PresenceState::Online);
+    }
+
+    #[test]
+    fn ignores_other_topics_and_rejects_garbage() {
+        let mut t = PresenceTracker::new();
+        let dev = Uuid::now_v7();
+        assert!(t
+            .ingest(&topics::device_state(dev), b"{}")
+            .unwrap()
+            .is_empty());
+        assert!(t.ingest(&topics::device_status(dev), b"not json").is_err());
+    }
+}
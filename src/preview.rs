@@ -0,0 +1,677 @@
+//! Stretched, downsampled previews of raw frames for the
+//! `devices/{uuid}/preview` topic.
+//!
+//! [`generate_preview`] bins a frame down to fit [`PreviewOptions::max_width`]
+//! x [`PreviewOptions::max_height`], optionally debayers it (2x2 superpixel),
+//! applies a per-channel auto-stretch and encodes it as JPEG or PNG.
+//!
+//! The auto-stretch is PixInsight's STF: shadows are clipped at
+//! `median + shadows_clip * MAD` and a midtones transfer function maps the
+//! median to `target_background`.
+//!
+//! Wire format on the preview topic mirrors [`crate::frame`]: a 4-byte
+//! big-endian header length, the JSON [`PreviewHeader`], then the image bytes.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::frame::{
+    decompress_payload, for_each_sample, BayerPattern, FrameError, FrameHeader, MAX_HEADER_LEN,
+    MAX_PAYLOAD_LEN,
+};
+
+/// Statistics are computed on at most this many samples per channel.
+const MAX_STAT_SAMPLES: usize = 1 << 20;
+/// Scales the median absolute deviation to a normal-distribution sigma.
+const MAD_TO_SIGMA: f32 = 1.4826;
+
+#[derive(Debug)]
+pub enum PreviewError {
+    /// The source frame could not be read.
+    Frame(FrameError),
+    /// The JPEG or PNG encoder failed.
+    Encode(String),
+    /// The source frame has no pixels.
+    EmptyFrame,
+}
+
+impl fmt::Display for PreviewError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreviewError::Frame(e) => write!(f, "preview source: {e}"),
+            PreviewError::Encode(e) => write!(f, "preview encoding failed: {e}"),
+            PreviewError::EmptyFrame => f.write_str("preview source has no pixels"),
+        }
+    }
+}
+
+impl std::error::Error for PreviewError {}
+
+impl From<FrameError> for PreviewError {
+    fn from(error: FrameError) -> Self {
+        PreviewError::Frame(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewFormat {
+    Jpeg,
+    Png,
+}
+
+#[derive(Debug, Clone)]
+pub struct PreviewOptions {
+    /// Largest preview width in pixels.
+    pub max_width: u32,
+    /// Largest preview height in pixels.
+    pub max_height: u32,
+    pub format: PreviewFormat,
+    /// JPEG quality, 1..=100. Ignored for PNG.
+    pub quality: u8,
+    /// Debayer colour sensors. When false, Bayer frames are previewed as
+    /// mono with an even bin factor so the mosaic averages out.
+    pub debayer: bool,
+    /// Where the stretched median lands, 0..1.
+    pub target_background: f32,
+    /// Shadows clipping point in MADs from the median, usually negative.
+    pub shadows_clip: f32,
+}
+
+impl Default for PreviewOptions {
+    fn default() -> Self {
+        Self {
+            max_width: 1024,
+            max_height: 1024,
+            format: PreviewFormat::Jpeg,
+            quality: 85,
+            debayer: true,
+            target_background: 0.25,
+            shadows_clip: -2.8,
+        }
+    }
+}
+
+/// Screen transfer function of one channel, on samples normalised to 0..1.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Stretch {
+    pub shadows: f32,
+    pub midtones: f32,
+    pub highlights: f32,
+}
+
+impl Default for Stretch {
+    fn default() -> Self {
+        Self {
+            shadows: 0.0,
+            midtones: 0.5,
+            highlights: 1.0,
+        }
+    }
+}
+
+impl Stretch {
+    /// Auto-stretch parameters from the median and MAD of `samples`.
+    pub fn auto(samples: &[f32], target_background: f32, shadows_clip: f32) -> Self {
+        let step = samples.len().div_ceil(MAX_STAT_SAMPLES).max(1);
+        let mut values: Vec<f32> = samples.iter().step_by(step).copied().collect();
+        if values.is_empty() {
+            return Self::default();
+        }
+        let median = median_of(&mut values);
+        values.iter_mut().for_each(|v| *v = (*v - median).abs());
+        let sigma = median_of(&mut values) * MAD_TO_SIGMA;
+
+        let shadows = if sigma > 0.0 {
+            (median + shadows_clip * sigma).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let x = (median - shadows) / (1.0 - shadows);
+        let t = target_background;
+        let midtones = if x > 0.0 && x < 1.0 {
+            x * (1.0 - t) / (x + t - 2.0 * x * t)
+        } else {
+            0.5
+        };
+        Self {
+            shadows,
+            midtones,
+            highlights: 1.0,
+        }
+    }
+
+    /// Stretch one normalised sample.
+    pub fn apply(&self, x: f32) -> f32 {
+        let range = self.highlights - self.shadows;
+        if range <= 0.0 {
+            return if x >= self.highlights { 1.0 } else { 0.0 };
+        }
+        mtf(self.midtones, ((x - self.shadows) / range).clamp(0.0, 1.0))
+    }
+}
+
+/// Midtones transfer function: maps 0 to 0, 1 to 1 and `m` to 0.5.
+pub fn mtf(m: f32, x: f32) -> f32 {
+    if x <= 0.0 {
+        0.0
+    } else if x >= 1.0 {
+        1.0
+    } else {
+        (m - 1.0) * x / ((2.0 * m - 1.0) * x - m)
+    }
+}
+
+fn median_of(values: &mut [f32]) -> f32 {
+    let mid = values.len() / 2;
+    *values.select_nth_unstable_by(mid, f32::total_cmp).1
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreviewHeader {
+    /// Same as the source [`FrameHeader::correlation_id`].
+    pub correlation_id: Uuid,
+    pub driver_id: Uuid,
+    /// Preview size in pixels.
+    pub width: u32,
+    pub height: u32,
+    /// Source frame size in pixels.
+    pub source_width: u32,
+    pub source_height: u32,
+    /// Source pixels per preview pixel along each axis.
+    pub bin: u32,
+    pub format: PreviewFormat,
+    /// True for RGB previews, false for grayscale.
+    pub color: bool,
+    /// Source shutter-open timestamp, nanoseconds since unix epoch.
+    pub timestamp_ns: u64,
+    /// Stretch applied to each channel (one entry for mono).
+    pub stretch: Vec<Stretch>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Preview {
+    pub header: PreviewHeader,
+    /// JPEG or PNG bytes.
+    pub data: Vec<u8>,
+}
+
+impl Preview {
+    /// Length-prefixed buffer for the preview topic.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, FrameError> {
+        let json = serde_json::to_vec(&self.header).map_err(|_| FrameError::InvalidHeader)?;
+        if json.len() > MAX_HEADER_LEN as usize {
+            return Err(FrameError::HeaderTooLarge(json.len() as u32));
+        }
+        let mut out = Vec::with_capacity(4 + json.len() + self.data.len());
+        out.extend_from_slice(&(json.len() as u32).to_be_bytes());
+        out.extend_from_slice(&json);
+        out.extend_from_slice(&self.data);
+        Ok(out)
+    }
+
+    /// Parse a buffer produced by [`Preview::to_bytes`].
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, FrameError> {
+        let prefix: [u8; 4] = buf
+            .get(..4)
+            .ok_or(FrameError::Truncated)?
+            .try_into()
+            .unwrap();
+        let len = u32::from_be_bytes(prefix);
+        if len > MAX_HEADER_LEN {
+            return Err(FrameError::HeaderTooLarge(len));
+        }
+        let json = buf.get(4..4 + len as usize).ok_or(FrameError::Truncated)?;
+        let header = serde_json::from_slice(json).map_err(|_| FrameError::InvalidHeader)?;
+        Ok(Self {
+            header,
+            data: buf[4 + len as usize..].to_vec(),
+        })
+    }
+}
+
+/// Interleaved `f32` image, samples normalised to 0..1.
+struct Image {
+    width: usize,
+    height: usize,
+    channels: usize,
+    data: Vec<f32>,
+}
+
+impl Image {
+    /// Reduce a raw payload while unpacking it, so only the reduced image is
+    /// allocated. Each output pixel averages a `bin` x `bin` block of source
+    /// pixels or, with a `superpixel` pattern, of 2x2 Bayer cells debayered
+    /// to RGB with the two greens averaged. Partial blocks at the right and
+    /// bottom edges are dropped.
+    fn downsample(
+        header: &FrameHeader,
+        data: &[u8],
+        bin: usize,
+        superpixel: Option<BayerPattern>,
+    ) -> Result<Image, FrameError> {
+        let bits = header.significant_bits();
+        if !(1..=16).contains(&bits) {
+            return Err(FrameError::PixelLayout);
+        }
+        let max = ((1u32 << bits) - 1) as f32;
+        let (source_width, source_channels) = (header.width as usize, header.channels() as usize);
+        let step = if superpixel.is_some() { 2 * bin } else { bin };
+        let channels = if superpixel.is_some() {
+            3
+        } else {
+            source_channels
+        };
+        let (width, height) = (source_width / step, header.height as usize / step);
+        // Each output sample sums bin * bin source samples per cell, and a
+        // Bayer cell holds two greens.
+        let mut weight = vec![1.0 / (bin * bin) as f32; channels];
+        if superpixel.is_some() {
+            weight[1] /= 2.0;
+        }
+
+        let mut out = vec![0.0f32; width * height * channels];
+        let (mut x, mut y, mut c) = (0, 0, 0);
+        for_each_sample(header, data, |sample| {
+            let (ox, oy) = (x / step, y / step);
+            if ox < width && oy < height {
+                let channel = superpixel.map_or(c, |p| p.channel_at(x as u32, y as u32));
+                out[(oy * width + ox) * channels + channel] +=
+                    (sample as f32 / max).min(1.0) * weight[channel];
+            }
+            c += 1;
+            if c == source_channels {
+                c = 0;
+                x += 1;
+                if x == source_width {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        })?;
+        Ok(Image {
+            width,
+            height,
+            channels,
+            data: out,
+        })
+    }
+
+    /// Samples of channel `c`.
+    fn channel(&self, c: usize) -> Vec<f32> {
+        self.data
+            .iter()
+            .skip(c)
+            .step_by(self.channels)
+            .copied()
+            .collect()
+    }
+}
+
+/// Smallest bin factor that fits `width` x `height` into the option limits,
+/// capped so the shorter axis keeps at least one pixel.
+fn bin_factor(width: usize, height: usize, options: &PreviewOptions) -> usize {
+    let fit = |size: usize, max: u32| size.div_ceil(max.max(1) as usize);
+    fit(width, options.max_width)
+        .max(fit(height, options.max_height))
+        .min(width.min(height))
+        .max(1)
+}
+
+/// Bin, stretch and encode a frame for the preview topic. Compressed
+/// payloads are decompressed first.
+pub fn generate_preview(
+    header: &FrameHeader,
+    data: &[u8],
+    options: &PreviewOptions,
+) -> Result<Preview, PreviewError> {
+    let mut source = header.clone();
+    let decoded;
+    let data = if header.encoding.is_raw() {
+        data
+    } else {
+        decoded = decompress_payload(&mut source, data, MAX_PAYLOAD_LEN)?;
+        &decoded
+    };
+    let (source_width, source_height) = (source.width as usize, source.height as usize);
+    if source_width == 0 || source_height == 0 {
+        return Err(PreviewError::EmptyFrame);
+    }
+
+    let mosaic = source.bayer.filter(|_| source.channels() == 1);
+    // A frame smaller than one Bayer cell is previewed as mono.
+    let superpixel = mosaic.filter(|_| options.debayer && source_width >= 2 && source_height >= 2);
+    let scale = if superpixel.is_some() { 2 } else { 1 };
+    let mut bin = bin_factor(source_width / scale, source_height / scale, options);
+    // Averaging an undebayered mosaic needs whole Bayer cells per bin.
+    if mosaic.is_some() && superpixel.is_none() && bin > 1 {
+        let fit = source_width.min(source_height);
+        bin += bin % 2;
+        if bin > fit {
+            bin = if fit >= 2 { fit - fit % 2 } else { 1 };
+        }
+    }
+    let image = Image::downsample(&source, data, bin, superpixel)?;
+    let (width, height) = (image.width as u32, image.height as u32);
+
+    let stretch: Vec<Stretch> = (0..image.channels)
+        .map(|c| {
+            Stretch::auto(
+                &image.channel(c),
+                options.target_background,
+                options.shadows_clip,
+            )
+        })
+        .collect();
+    let pixels: Vec<u8> = image
+        .data
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| (stretch[i % image.channels].apply(v) * 255.0 + 0.5) as u8)
+        .collect();
+    let color = image.channels == 3;
+
+    let encoded = match options.format {
+        PreviewFormat::Jpeg => encode_jpeg(&pixels, width, height, color, options.quality)?,
+        PreviewFormat::Png => encode_png(&pixels, width, height, color)?,
+    };
+    Ok(Preview {
+        header: PreviewHeader {
+            correlation_id: source.correlation_id,
+            driver_id: source.driver_id,
+            width,
+            height,
+            source_width: source.width,
+            source_height: source.height,
+            bin: (bin * scale) as u32,
+            format: options.format,
+            color,
+            timestamp_ns: source.timestamp_ns,
+            stretch,
+        },
+        data: encoded,
+    })
+}
+
+fn encode_jpeg(
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    color: bool,
+    quality: u8,
+) -> Result<Vec<u8>, PreviewError> {
+    let (Ok(w), Ok(h)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(PreviewError::Encode(format!(
+            "{width}x{height} exceeds JPEG limits"
+        )));
+    };
+    let color_type = if color {
+        jpeg_encoder::ColorType::Rgb
+    } else {
+        jpeg_encoder::ColorType::Luma
+    };
+    let mut out = Vec::new();
+    jpeg_encoder::Encoder::new(&mut out, quality.clamp(1, 100))
+        .encode(pixels, w, h, color_type)
+        .map_err(|e| PreviewError::Encode(e.to_string()))?;
+    Ok(out)
+}
+
+fn encode_png(
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    color: bool,
+) -> Result<Vec<u8>, PreviewError> {
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width, height);
+    encoder.set_color(if color {
+        png::ColorType::Rgb
+    } else {
+        png::ColorType::Grayscale
+    });
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| PreviewError::Encode(e.to_string()))?;
+    writer
+        .write_image_data(pixels)
+        .map_err(|e| PreviewError::Encode(e.to_string()))?;
+    writer
+        .finish()
+        .map_err(|e| PreviewError::Encode(e.to_string()))?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{compress_payload, FrameEncoding, PixelFormat};
+
+    fn sky(width: u32, height: u32) -> (FrameHeader, Vec<u8>) {
+        let header = FrameHeader {
+            correlation_id: Uuid::now_v7(),
+            width,
+            height,
+            bit_depth: 16,
+            pixel_format: Some(PixelFormat::Mono16Le),
+            ..Default::default()
+        };
+        let mut state = 7u32;
+        let data = (0..width * height)
+            .flat_map(|i| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let star = if i % 501 == 0 { 30_000 } else { 0 };
+                (1000 + (state >> 26) as u16 + star).to_le_bytes()
+            })
+            .collect();
+        (header, data)
+    }
+
+    #[test]
+    fn mtf_fixed_points() {
+        assert_eq!(mtf(0.3, 0.0), 0.0);
+        assert_eq!(mtf(0.3, 1.0), 1.0);
+        assert!((mtf(0.3, 0.3) - 0.5).abs() < 1e-6);
+        assert!((mtf(0.5, 0.42) - 0.42).abs() < 1e-6);
+    }
+
+    #[test]
+    fn auto_stretch_maps_median_to_target() {
+        let samples: Vec<f32> = (0..1000).map(|i| 0.01 + (i % 10) as f32 * 0.001).collect();
+        let s = Stretch::auto(&samples, 0.25, -2.8);
+        assert!(s.shadows > 0.0 && s.shadows < 0.015);
+        assert!((s.apply(0.015) - 0.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn png_preview_fits_and_decodes() {
+        let (header, data) = sky(300, 200);
+        let options = PreviewOptions {
+            max_width: 100,
+            max_height: 100,
+            format: PreviewFormat::Png,
+            ..Default::default()
+        };
+        let preview = generate_preview(&header, &data, &options).unwrap();
+        assert_eq!(preview.header.bin, 3);
+        assert_eq!((preview.header.width, preview.header.height), (100, 66));
+        assert_eq!(preview.header.correlation_id, header.correlation_id);
+        assert!(!preview.header.color);
+
+        let decoder = png::Decoder::new(std::io::Cursor::new(&preview.data));
+        let reader = decoder.read_info().unwrap();
+        assert_eq!((reader.info().width, reader.info().height), (100, 66));
+    }
+
+    #[test]
+    fn debayered_jpeg_is_color() {
+        let (mut header, data) = sky(128, 96);
+        header.pixel_format = Some(PixelFormat::Bayer16);
+        header.bayer = Some(BayerPattern::Rggb);
+        let preview = generate_preview(&header, &data, &PreviewOptions::default()).unwrap();
+        assert!(preview.header.color);
+        assert_eq!(preview.header.stretch.len(), 3);
+        assert_eq!(
+            (
+                preview.header.width,
+                preview.header.height,
+                preview.header.bin
+            ),
+            (64, 48, 2)
+        );
+        assert_eq!(&preview.data[..2], &[0xff, 0xd8]);
+
+        let options = PreviewOptions {
+            debayer: false,
+            max_width: 50,
+            ..Default::default()
+        };
+        let preview = generate_preview(&header, &data, &options).unwrap();
+        assert!(!preview.header.color);
+        assert_eq!(preview.header.bin, 4);
+    }
+
+    #[test]
+    fn compressed_source_and_wire_roundtrip() {
+        let (mut header, data) = sky(64, 64);
+        let packed = compress_payload(&mut header, &data, FrameEncoding::Rice).unwrap();
+        let preview = generate_preview(&header, &packed, &PreviewOptions::default()).unwrap();
+        assert_eq!(preview.header.bin, 1);
+
+        let back = Preview::from_bytes(&preview.to_bytes().unwrap()).unwrap();
+        assert_eq!(back.data, preview.data);
+        assert_eq!(back.header.stretch, preview.header.stretch);
+        assert!(matches!(
+            Preview::from_bytes(&[0, 0]),
+            Err(FrameError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn superpixel_averages_greens_and_bins() {
+        // 4x2 RGGB: two cells, R = 100, G = 200 and 400, B = 300.
+        let header = FrameHeader {
+            width: 4,
+            height: 2,
+            bit_depth: 16,
+            pixel_format: Some(PixelFormat::Bayer16),
+            bayer: Some(BayerPattern::Rggb),
+            ..Default::default()
+        };
+        let data: Vec<u8> = [100u16, 200, 100, 200, 400, 300, 400, 300]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let scaled = |v: f32| v / 65535.0;
+
+        let image = Image::downsample(&header, &data, 1, Some(BayerPattern::Rggb)).unwrap();
+        assert_eq!((image.width, image.height, image.channels), (2, 1, 3));
+        let expected = [scaled(100.0), scaled(300.0), scaled(300.0)];
+        for (got, want) in image.data.iter().zip(expected.iter().cycle()) {
+            assert!((got - want).abs() < 1e-7);
+        }
+
+        let image = Image::downsample(&header, &data, 2, None).unwrap();
+        assert_eq!((image.width, image.height, image.channels), (2, 1, 1));
+        assert!((image.data[0] - scaled(250.0)).abs() < 1e-7);
+    }
+
+    #[test]
+    fn tiny_frames_behave_the_same_with_and_without_debayer() {
+        let bayer = |width: u32, height: u32| FrameHeader {
+            width,
+            height,
+            bit_depth: 16,
+            pixel_format: Some(PixelFormat::Bayer16),
+            bayer: Some(BayerPattern::Rggb),
+            ..Default::default()
+        };
+        for debayer in [true, false] {
+            let options = PreviewOptions {
+                debayer,
+                format: PreviewFormat::Png,
+                ..Default::default()
+            };
+            assert!(matches!(
+                generate_preview(&bayer(0, 0), &[], &options),
+                Err(PreviewError::EmptyFrame)
+            ));
+            let preview = generate_preview(&bayer(1, 1), &[0x00, 0x80], &options).unwrap();
+            assert_eq!(
+                (
+                    preview.header.width,
+                    preview.header.height,
+                    preview.header.bin
+                ),
+                (1, 1, 1)
+            );
+            assert!(!preview.header.color);
+
+            let options = PreviewOptions {
+                max_width: 1,
+                max_height: 1,
+                ..options
+            };
+            let preview = generate_preview(&bayer(3, 3), &[0; 18], &options).unwrap();
+            assert_eq!((preview.header.width, preview.header.height), (1, 1));
+        }
+    }
+
+    #[test]
+    fn narrow_frames_keep_one_pixel_on_the_short_axis() {
+        let frame = |width: u32, height: u32, bayer: Option<BayerPattern>| {
+            let header = FrameHeader {
+                width,
+                height,
+                bit_depth: 16,
+                pixel_format: Some(if bayer.is_some() {
+                    PixelFormat::Bayer16
+                } else {
+                    PixelFormat::Mono16Le
+                }),
+                bayer,
+                ..Default::default()
+            };
+            (header, vec![0; width as usize * height as usize * 2])
+        };
+        let options = PreviewOptions {
+            format: PreviewFormat::Png,
+            ..Default::default()
+        };
+        let size = |p: &Preview| (p.header.width, p.header.height, p.header.bin);
+
+        let (header, data) = frame(4000, 3, None);
+        assert_eq!(
+            size(&generate_preview(&header, &data, &options).unwrap()),
+            (1333, 1, 3)
+        );
+        let (header, data) = frame(10000, 1, None);
+        assert_eq!(
+            size(&generate_preview(&header, &data, &options).unwrap()),
+            (10000, 1, 1)
+        );
+        let (header, data) = frame(5000, 3, Some(BayerPattern::Rggb));
+        assert_eq!(
+            size(&generate_preview(&header, &data, &options).unwrap()),
+            (2500, 1, 2)
+        );
+
+        // Undebayered mosaics only bin by an even factor that fits.
+        let options = PreviewOptions {
+            debayer: false,
+            ..options
+        };
+        assert_eq!(
+            size(&generate_preview(&header, &data, &options).unwrap()),
+            (2500, 1, 2)
+        );
+        let (header, data) = frame(5000, 7, Some(BayerPattern::Rggb));
+        assert_eq!(
+            size(&generate_preview(&header, &data, &options).unwrap()),
+            (833, 1, 6)
+        );
+    }
+}
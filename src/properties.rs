@@ -285,7 +285,7 @@ mod unit_tests {
         let test_val = 5.32_f64;
         let min_range = 10.0_f64;
         let max_range = 100.0_f64;
-        let p = RangeProperty::new(test_val.clone(), Permission::ReadOnly, min_range, max_range);
+        let p = RangeProperty::new(test_val, Permission::ReadOnly, min_range, max_range);
         assert_eq!(p.range.min(), &min_range);
         assert_eq!(p.range.max(), &max_range);
     }
@@ -7,6 +7,9 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::properties::PropertyErrorType;
+use crate::LightspeedError;
+
 /// Generate a new correlation id (uuid v7, time-sortable).
 pub fn new_correlation_id() -> Uuid {
     Uuid::now_v7()
@@ -59,7 +62,7 @@ pub enum ReplyResult<T> {
     Error { error: ErrorEnvelope },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ErrorEnvelope {
     pub code: ErrorCode,
     pub message: String,
@@ -68,6 +71,38 @@ pub struct ErrorEnvelope {
     pub field: Option<String>,
 }
 
+impl From<&LightspeedError> for ErrorEnvelope {
+    fn from(error: &LightspeedError) -> Self {
+        let (code, message) = match error {
+            LightspeedError::PropertyError(e) => (
+                ErrorCode::Validation,
+                match e {
+                    PropertyErrorType::CannotUpdateReadOnlyProp => "property is read-only",
+                    PropertyErrorType::InvalidValue => "invalid value",
+                    PropertyErrorType::InvalidChoice => "invalid choice",
+                    PropertyErrorType::ValueOutOfRange => "value out of range",
+                }
+                .to_string(),
+            ),
+            LightspeedError::IoError(e) => (ErrorCode::DriverUnavailable, e.to_string()),
+            LightspeedError::DeviceConnectionError => {
+                (ErrorCode::DriverUnavailable, "device not connected".into())
+            }
+            LightspeedError::UnknownCommand => (ErrorCode::NotFound, "unknown command".into()),
+            LightspeedError::QueueFull => (ErrorCode::Conflict, "command queue full".into()),
+            LightspeedError::ParseError => (ErrorCode::Validation, "malformed payload".into()),
+            LightspeedError::NotSupported => {
+                (ErrorCode::Validation, "not supported by this device".into())
+            }
+        };
+        Self {
+            code,
+            message,
+            field: None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ErrorCode {
@@ -129,7 +164,9 @@ mod tests {
 
     #[test]
     fn command_roundtrip() {
-        let cmd = Command::new(ExposePayload { duration_ms: 30_000 });
+        let cmd = Command::new(ExposePayload {
+            duration_ms: 30_000,
+        });
         let json = serde_json::to_string(&cmd).unwrap();
         let decoded: Command<ExposePayload> = serde_json::from_str(&json).unwrap();
         assert_eq!(decoded.id, cmd.id);
@@ -164,6 +201,25 @@ mod tests {
         assert!(json.contains(r#""field":"latitude""#));
     }
 
+    #[test]
+    fn lightspeed_errors_map_to_envelopes() {
+        let e = ErrorEnvelope::from(&LightspeedError::PropertyError(
+            PropertyErrorType::ValueOutOfRange,
+        ));
+        assert_eq!(
+            (e.code, e.message.as_str()),
+            (ErrorCode::Validation, "value out of range")
+        );
+        let e = ErrorEnvelope::from(&LightspeedError::QueueFull);
+        assert_eq!(e.code, ErrorCode::Conflict);
+        let io = std::io::Error::new(std::io::ErrorKind::TimedOut, "no reply");
+        let e = ErrorEnvelope::from(&LightspeedError::IoError(io));
+        assert_eq!(
+            (e.code, e.message.as_str()),
+            (ErrorCode::DriverUnavailable, "no reply")
+        );
+    }
+
     #[test]
     fn correlation_ids_are_v7_and_unique() {
         let a = new_correlation_id();
@@ -0,0 +1,351 @@
+//! Lossless Rice coding of integer pixel samples, following the scheme used
+//! by fpack / CFITSIO: the first sample is stored verbatim, then blocks of
+//! 32 first differences are coded with a per-block split parameter `fs`.
+//! A block of all-zero differences costs only its `fs` code, and blocks too
+//! noisy to compress fall back to verbatim differences.
+//!
+//! Differences are taken modulo 2^bits and zigzag-mapped, so any sample
+//! sequence round-trips exactly.
+
+const BLOCK_SIZE: usize = 32;
+
+/// Per sample width coding parameters: bits of the `fs` code, largest `fs`
+/// before falling back to verbatim, and sample bits.
+#[derive(Clone, Copy)]
+struct Params {
+    fs_bits: u32,
+    fs_max: u32,
+    bits: u32,
+}
+
+const PARAMS_8: Params = Params {
+    fs_bits: 3,
+    fs_max: 6,
+    bits: 8,
+};
+
+const PARAMS_16: Params = Params {
+    fs_bits: 4,
+    fs_max: 14,
+    bits: 16,
+};
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    len: u32,
+}
+
+impl BitWriter {
+    fn new(capacity: usize) -> Self {
+        Self {
+            out: Vec::with_capacity(capacity),
+            acc: 0,
+            len: 0,
+        }
+    }
+
+    /// Append the low `n` bits of `value`, MSB first. `n` <= 32.
+    fn write(&mut self, value: u32, n: u32) {
+        if n == 0 {
+            return;
+        }
+        self.acc = (self.acc << n) | (value as u64 & ((1u64 << n) - 1));
+        self.len += n;
+        while self.len >= 8 {
+            self.len -= 8;
+            self.out.push((self.acc >> self.len) as u8);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.out.push((self.acc << (8 - self.len)) as u8);
+        }
+        self.out
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    acc: u64,
+    len: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            acc: 0,
+            len: 0,
+        }
+    }
+
+    fn fill(&mut self, n: u32) -> Option<()> {
+        while self.len < n {
+            let byte = *self.data.get(self.pos)?;
+            self.pos += 1;
+            self.acc = (self.acc << 8) | byte as u64;
+            self.len += 8;
+        }
+        Some(())
+    }
+
+    fn read(&mut self, n: u32) -> Option<u32> {
+        if n == 0 {
+            return Some(0);
+        }
+        self.fill(n)?;
+        self.len -= n;
+        Some(((self.acc >> self.len) & ((1u64 << n) - 1)) as u32)
+    }
+
+    /// Count zero bits up to and including the terminating one bit.
+    fn read_unary(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        loop {
+            if self.read(1)? == 1 {
+                return Some(zeros);
+            }
+            zeros += 1;
+        }
+    }
+}
+
+fn zigzag(diff: i32, bits: u32) -> u32 {
+    let mask = (1u32 << bits) - 1;
+    (((diff << 1) ^ (diff >> 31)) as u32) & mask
+}
+
+fn unzigzag(value: u32) -> i32 {
+    ((value >> 1) as i32) ^ -((value & 1) as i32)
+}
+
+/// Sign-extend the low `bits` of `value`.
+fn wrap(value: i32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    (value << shift) >> shift
+}
+
+fn encode(samples: impl Iterator<Item = u32>, p: Params) -> Vec<u8> {
+    let mut samples = samples;
+    let mut w = BitWriter::new(samples.size_hint().0 * p.bits as usize / 16 + 16);
+    let Some(first) = samples.next() else {
+        return w.finish();
+    };
+    w.write(first, p.bits);
+
+    // The first sample also opens the first block, as a zero difference.
+    let mut samples = std::iter::once(first).chain(samples);
+    let mut last = first;
+    let mut block = [0u32; BLOCK_SIZE];
+    loop {
+        let mut len = 0;
+        let mut sum: u64 = 0;
+        for (slot, sample) in block.iter_mut().zip(samples.by_ref()) {
+            let diff = wrap(sample as i32 - last as i32, p.bits);
+            *slot = zigzag(diff, p.bits);
+            sum += *slot as u64;
+            last = sample;
+            len += 1;
+        }
+        if len == 0 {
+            break;
+        }
+        let block = &block[..len];
+        if sum == 0 {
+            w.write(0, p.fs_bits);
+            continue;
+        }
+        let nblock = block.len() as u64;
+        let mut mean = sum.saturating_sub(nblock / 2 + 1) / nblock;
+        let mut fs = 0;
+        while mean > 0 {
+            mean >>= 1;
+            fs += 1;
+        }
+        if fs >= p.fs_max {
+            w.write(p.fs_max + 1, p.fs_bits);
+            for &value in block {
+                w.write(value, p.bits);
+            }
+        } else {
+            w.write(fs + 1, p.fs_bits);
+            for &value in block {
+                let top = value >> fs;
+                // Unary code of `top`, emitted in pieces to stay within 32 bits.
+                let mut zeros = top;
+                while zeros > 24 {
+                    w.write(0, 24);
+                    zeros -= 24;
+                }
+                w.write(1, zeros + 1);
+                w.write(value, fs);
+            }
+        }
+    }
+    w.finish()
+}
+
+/// Most samples `len` coded bytes can hold: the first sample, then one
+/// `fs` code per all-zero block.
+fn max_samples(len: usize, p: Params) -> usize {
+    (len * 8 / p.fs_bits as usize)
+        .saturating_mul(BLOCK_SIZE)
+        .saturating_add(1)
+}
+
+fn decode(data: &[u8], count: usize, p: Params) -> Option<Vec<u32>> {
+    if count > max_samples(data.len(), p) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(count);
+    if count == 0 {
+        return Some(out);
+    }
+    let mut r = BitReader::new(data);
+    let mut last = r.read(p.bits)? as i32;
+    let mask = (1i32 << p.bits) - 1;
+    while out.len() < count {
+        let nblock = (count - out.len()).min(BLOCK_SIZE);
+        let code = r.read(p.fs_bits)?;
+        for _ in 0..nblock {
+            let value = match code {
+                0 => 0,
+                c if c == p.fs_max + 1 => r.read(p.bits)?,
+                c if c <= p.fs_max => {
+                    let fs = c - 1;
+                    let top = r.read_unary()?;
+                    (top << fs) | r.read(fs)?
+                }
+                _ => return None,
+            };
+            last = last.wrapping_add(unzigzag(value)) & mask;
+            out.push(last as u32);
+        }
+    }
+    Some(out)
+}
+
+/// Rice-code 8-bit samples.
+pub(crate) fn encode_u8(samples: &[u8]) -> Vec<u8> {
+    encode(samples.iter().map(|&s| s as u32), PARAMS_8)
+}
+
+pub(crate) fn decode_u8(data: &[u8], count: usize) -> Option<Vec<u8>> {
+    Some(
+        decode(data, count, PARAMS_8)?
+            .into_iter()
+            .map(|s| s as u8)
+            .collect(),
+    )
+}
+
+/// Rice-code 16-bit samples stored as bytes in the given endianness.
+pub(crate) fn encode_u16(bytes: &[u8], big_endian: bool) -> Vec<u8> {
+    let samples = bytes.chunks_exact(2).map(|b| {
+        let pair = [b[0], b[1]];
+        if big_endian {
+            u16::from_be_bytes(pair) as u32
+        } else {
+            u16::from_le_bytes(pair) as u32
+        }
+    });
+    encode(samples, PARAMS_16)
+}
+
+pub(crate) fn decode_u16(data: &[u8], count: usize, big_endian: bool) -> Option<Vec<u8>> {
+    let samples = decode(data, count, PARAMS_16)?;
+    let mut out = Vec::with_capacity(count * 2);
+    for s in samples {
+        let s = s as u16;
+        out.extend_from_slice(&if big_endian {
+            s.to_be_bytes()
+        } else {
+            s.to_le_bytes()
+        });
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noisy_u16(n: usize) -> Vec<u8> {
+        let mut state = 0x1234_5678u32;
+        let mut out = Vec::with_capacity(n * 2);
+        for i in 0..n {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let value = 1000 + (state >> 24) as u16 + if i % 997 == 0 { 40_000 } else { 0 };
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out
+    }
+
+    #[test]
+    fn roundtrips_u16_noise() {
+        let data = noisy_u16(10_000);
+        let coded = encode_u16(&data, false);
+        assert!(coded.len() < data.len());
+        assert_eq!(decode_u16(&coded, 10_000, false).unwrap(), data);
+    }
+
+    #[test]
+    fn roundtrips_u16_extremes_and_big_endian() {
+        let values: Vec<u16> = (0..100)
+            .map(|i| if i % 2 == 0 { 0 } else { u16::MAX })
+            .collect();
+        let data: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes()).collect();
+        let coded = encode_u16(&data, true);
+        assert_eq!(decode_u16(&coded, 100, true).unwrap(), data);
+    }
+
+    #[test]
+    fn flat_blocks_are_tiny() {
+        let data = vec![7u8; 64_000];
+        let coded = encode_u8(&data);
+        assert!(coded.len() < 1_000);
+        assert_eq!(decode_u8(&coded, 64_000).unwrap(), data);
+    }
+
+    #[test]
+    fn roundtrips_u8_partial_block() {
+        let data: Vec<u8> = (0..45).map(|i| (i * 37 % 256) as u8).collect();
+        assert_eq!(decode_u8(&encode_u8(&data), 45).unwrap(), data);
+        assert!(decode_u8(&[], 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn truncated_input_fails() {
+        let data = noisy_u16(100);
+        let coded = encode_u16(&data, false);
+        assert!(decode_u16(&coded[..coded.len() / 2], 100, false).is_none());
+    }
+
+    #[test]
+    fn corrupt_stream_does_not_overflow() {
+        // First sample 1, then an `fs` = 13 block whose one sample decodes,
+        // from its unary prefix and low bits, to a difference of i32::MAX.
+        let mut w = BitWriter::new(70_000);
+        w.write(1, 16);
+        w.write(14, 4);
+        let mut zeros = (1u32 << 19) - 1;
+        while zeros > 24 {
+            w.write(0, 24);
+            zeros -= 24;
+        }
+        w.write(1, zeros + 1);
+        w.write(0x1FFE, 13);
+        let coded = w.finish();
+        assert_eq!(decode(&coded, 1, PARAMS_16).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn implausible_count_is_rejected() {
+        assert!(decode_u16(&[0; 4], usize::MAX / 2, false).is_none());
+        assert!(decode_u8(&[], 2).is_none());
+    }
+}
@@ -1,19 +1,22 @@
 use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::{
     atomic::{AtomicBool, Ordering},
-    mpsc, Arc,
+    mpsc, Arc, Condvar, Mutex,
 };
 use std::thread;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use log::{error, info, warn};
-use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use rumqttc::{Client, Event, LastWill, MqttOptions, Outgoing, Packet, QoS, RecvTimeoutError};
 use uuid::Uuid;
 
 use crate::device::LightspeedDevice;
-use crate::presence::{DeviceStatus, PresenceState, RunnerStatus};
-use crate::topics;
-use crate::LightspeedError;
+use crate::frame::FrameHeader;
+use crate::frame_transport::{FrameChunker, DEFAULT_CHUNK_SIZE, MAX_PACKET_SIZE};
+use crate::presence::{DeviceInventory, DevicePresence, DeviceStatus, PresenceState, RunnerStatus};
+use crate::topics::{TopicNamespace, TopicRouter};
 
 pub struct RunnerConfig {
     pub mqtt_client_id: String,
@@ -25,6 +28,31 @@ pub struct RunnerConfig {
     pub tick_interval_ms: u64,
     /// MQTT keepalive. Default: 15 s. Detection latency ~= 1.5x this value.
     pub keepalive_secs: u64,
+    /// Pixel bytes per chunk when publishing frames on
+    /// `devices/{uuid}/frame`. Default: 1 MiB. Values above
+    /// [`MAX_CHUNK_SIZE`](crate::frame_transport::MAX_CHUNK_SIZE) are
+    /// clamped so chunks fit the 10 MiB packet limit.
+    pub frame_chunk_size: usize,
+    /// Minimum time between previews published on `devices/{uuid}/preview`
+    /// per device; `take_preview()` is not called more often. Checked once
+    /// per tick, so the effective interval is rounded up to a multiple of
+    /// `tick_interval_ms`. Default: 1000 ms.
+    pub preview_interval_ms: u64,
+    /// Prefix for every topic the runner publishes or subscribes to, e.g.
+    /// `obs/siteA/`. Default: the root namespace.
+    pub namespace: TopicNamespace,
+    /// How long busy devices may keep working after shutdown is requested
+    /// before they are aborted. Default: 30000 ms.
+    pub shutdown_timeout_ms: u64,
+    /// Stable name the runner id is derived from. Default: `None`, which
+    /// uses `{hostname}/{mqtt_client_id}`.
+    pub runner_name: Option<String>,
+    /// File where the runner id is persisted across restarts. When the
+    /// stored id differs from the current one, the previous retained
+    /// `runners/{id}/status` is cleared on startup. Default: `None`, which
+    /// uses `$XDG_STATE_HOME/lightspeed/runner-{mqtt_client_id}.id` (or
+    /// `~/.local/state/...`), see [`RunnerConfig::state_file_path`].
+    pub state_file: Option<PathBuf>,
 }
 
 impl Default for RunnerConfig {
@@ -36,10 +64,97 @@ impl Default for RunnerConfig {
             driver_version: "unknown".to_string(),
             tick_interval_ms: 1000,
             keepalive_secs: 15,
+            frame_chunk_size: DEFAULT_CHUNK_SIZE,
+            preview_interval_ms: 1000,
+            namespace: TopicNamespace::default(),
+            shutdown_timeout_ms: 30_000,
+            runner_name: None,
+            state_file: None,
         }
     }
 }
 
+impl RunnerConfig {
+    /// Deterministic runner id (uuid v5), stable across restarts as long as
+    /// the runner name (or host name and MQTT client id) does not change.
+    pub fn runner_id(&self) -> Uuid {
+        let name = match &self.runner_name {
+            Some(name) => name.clone(),
+            None => format!("{}/{}", hostname(), self.mqtt_client_id),
+        };
+        Uuid::new_v5(&RUNNER_NAMESPACE, name.as_bytes())
+    }
+
+    /// `state_file`, or the default location for this MQTT client id. None
+    /// only if neither `XDG_STATE_HOME` nor `HOME` is set.
+    pub fn state_file_path(&self) -> Option<PathBuf> {
+        if let Some(path) = &self.state_file {
+            return Some(path.clone());
+        }
+        let non_empty = |var| {
+            std::env::var_os(var)
+                .filter(|v| !v.is_empty())
+                .map(PathBuf::from)
+        };
+        let state_dir = non_empty("XDG_STATE_HOME")
+            .or_else(|| non_empty("HOME").map(|home| home.join(".local/state")))?;
+        Some(default_state_file(&state_dir, &self.mqtt_client_id))
+    }
+}
+
+fn default_state_file(state_dir: &Path, mqtt_client_id: &str) -> PathBuf {
+    let name: String = mqtt_client_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    state_dir
+        .join("lightspeed")
+        .join(format!("runner-{name}.id"))
+}
+
+/// Namespace for runner ids derived via [`RunnerConfig::runner_id`].
+const RUNNER_NAMESPACE: Uuid = Uuid::from_u128(0x5c1f_3a2e_8d47_4b6a_9f0e_2b7c_d4e1_a9f3);
+
+/// Best-effort host name lookup without pulling in a libc dependency.
+fn hostname() -> String {
+    if let Ok(name) = std::env::var("HOSTNAME") {
+        if !name.trim().is_empty() {
+            return name.trim().to_string();
+        }
+    }
+    for path in ["/etc/hostname", "/proc/sys/kernel/hostname"] {
+        if let Ok(name) = fs::read_to_string(path) {
+            if !name.trim().is_empty() {
+                return name.trim().to_string();
+            }
+        }
+    }
+    "localhost".to_string()
+}
+
+/// Read the previous runner id from `path` and store `current` in its place.
+/// Returns the previous id only if it differs from `current`.
+fn swap_persisted_runner_id(path: &Path, current: Uuid) -> Option<Uuid> {
+    let previous = fs::read_to_string(path)
+        .ok()
+        .and_then(|s| s.trim().parse::<Uuid>().ok());
+    if previous != Some(current) {
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        if let Err(e) = fs::write(path, current.to_string()) {
+            warn!("Failed to persist runner id to {}: {e}", path.display());
+        }
+    }
+    previous.filter(|prev| *prev != current)
+}
+
 fn epoch_secs() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -47,68 +162,310 @@ fn epoch_secs() -> u64 {
         .unwrap_or(0)
 }
 
+/// Cloneable handle used to request an ordered runner shutdown.
+///
+/// SIGINT, SIGTERM and SIGHUP trigger the same handle; drivers embedding the
+/// runner can also call [`ShutdownHandle::shutdown`] directly.
+#[derive(Clone, Default)]
+pub struct ShutdownHandle(Arc<AtomicBool>);
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request shutdown. Idempotent and safe to call from any thread.
+    pub fn shutdown(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// How often the main loop wakes up to check for shutdown progress.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Extra time granted to `close()` after the shutdown deadline before the
+/// runner stops waiting for a device thread.
+const CLOSE_GRACE: Duration = Duration::from_secs(5);
+
+/// Latest undelivered preview per device. Device threads overwrite their
+/// own slot, so a slow publisher skips stale previews without one device
+/// crowding out the others.
+struct PreviewSlots {
+    state: Mutex<PreviewState>,
+    ready: Condvar,
+}
+
+struct PreviewState {
+    latest: HashMap<Uuid, Vec<u8>>,
+    senders: usize,
+}
+
+struct PreviewSender(Arc<PreviewSlots>);
+
+struct PreviewReceiver(Arc<PreviewSlots>);
+
+fn preview_slots() -> (PreviewSender, PreviewReceiver) {
+    let slots = Arc::new(PreviewSlots {
+        state: Mutex::new(PreviewState {
+            latest: HashMap::new(),
+            senders: 1,
+        }),
+        ready: Condvar::new(),
+    });
+    (PreviewSender(slots.clone()), PreviewReceiver(slots))
+}
+
+impl PreviewSender {
+    /// Replace the pending preview of `uuid`, if any.
+    fn send(&self, uuid: Uuid, preview: Vec<u8>) {
+        self.0.state.lock().unwrap().latest.insert(uuid, preview);
+        self.0.ready.notify_one();
+    }
+}
+
+impl Clone for PreviewSender {
+    fn clone(&self) -> Self {
+        self.0.state.lock().unwrap().senders += 1;
+        Self(self.0.clone())
+    }
+}
+
+impl Drop for PreviewSender {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().senders -= 1;
+        self.0.ready.notify_one();
+    }
+}
+
+impl PreviewReceiver {
+    /// Wait for pending previews and take all of them. `None` once every
+    /// sender is gone and nothing is left to publish.
+    fn recv(&self) -> Option<HashMap<Uuid, Vec<u8>>> {
+        let mut state = self.0.state.lock().unwrap();
+        loop {
+            if !state.latest.is_empty() {
+                return Some(std::mem::take(&mut state.latest));
+            }
+            if state.senders == 0 {
+                return None;
+            }
+            state = self.0.ready.wait(state).unwrap();
+        }
+    }
+}
+
+/// Identity fields shared by every presence payload this runner publishes.
+struct Presence {
+    namespace: TopicNamespace,
+    runner_id: Uuid,
+    device_uuids: Vec<Uuid>,
+    inventories: HashMap<Uuid, DeviceInventory>,
+    started_at: u64,
+    driver_version: String,
+    pid: u32,
+}
+
+impl Presence {
+    fn device_status(&self, uuid: Uuid, presence: DevicePresence) -> DeviceStatus {
+        DeviceStatus {
+            state: presence.state.coarse(),
+            detail: Some(presence.state).filter(|s| *s != s.coarse()),
+            runner_id: self.runner_id,
+            started_at: self.started_at,
+            driver_version: self.driver_version.clone(),
+            pid: self.pid,
+            reason: presence.reason,
+            error: presence.error,
+            inventory: self.inventories.get(&uuid).cloned().unwrap_or_default(),
+        }
+    }
+
+    fn runner_status(&self, state: PresenceState) -> RunnerStatus {
+        RunnerStatus {
+            state,
+            device_uuids: self.device_uuids.clone(),
+            started_at: self.started_at,
+            runner_version: self.driver_version.clone(),
+            pid: self.pid,
+        }
+    }
+
+    fn publish_device(&self, client: &Client, uuid: Uuid, presence: DevicePresence) {
+        if let Ok(payload) = serde_json::to_vec(&self.device_status(uuid, presence)) {
+            if let Err(e) = client.publish(
+                self.namespace.device_status(uuid),
+                QoS::AtLeastOnce,
+                true,
+                payload,
+            ) {
+                error!("Failed to publish device status for {uuid}: {e}");
+            }
+        }
+    }
+
+    fn publish_runner(&self, client: &Client, state: PresenceState) {
+        if let Ok(payload) = serde_json::to_vec(&self.runner_status(state)) {
+            if let Err(e) = client.publish(
+                self.namespace.runner_status(self.runner_id),
+                QoS::AtLeastOnce,
+                true,
+                payload,
+            ) {
+                error!("Failed to publish runner status: {e}");
+            }
+        }
+    }
+}
+
 /// Run devices under a Lightspeed-compatible MQTT broker.
 ///
-/// Blocks until all device threads complete (i.e. until Ctrl-C is received).
+/// Blocks until SIGINT, SIGTERM or SIGHUP is received and the ordered
+/// shutdown has completed. See [`run_with_shutdown`].
 pub fn run<D: LightspeedDevice>(devices: Vec<D>, config: RunnerConfig) {
-    let runner_id = Uuid::now_v7();
-    let started_at = epoch_secs();
-    let pid = std::process::id();
+    run_with_shutdown(devices, config, ShutdownHandle::new())
+}
+
+/// Like [`run`], but shutdown can also be requested through `shutdown`.
+///
+/// Shutdown is ordered:
+/// 1. command topics are unsubscribed and incoming commands are dropped;
+/// 2. each device keeps ticking while [`LightspeedDevice::is_busy`] until
+///    `shutdown_timeout_ms` elapses, then [`LightspeedDevice::abort`] is
+///    called if it is still busy;
+/// 3. [`LightspeedDevice::close`] is called and the device's Offline status
+///    is published;
+/// 4. once every device is closed the runner publishes its own Offline
+///    status and disconnects.
+pub fn run_with_shutdown<D: LightspeedDevice>(
+    devices: Vec<D>,
+    config: RunnerConfig,
+    shutdown: ShutdownHandle,
+) {
+    let runner_id = config.runner_id();
+    let previous_runner_id = config
+        .state_file_path()
+        .and_then(|path| swap_persisted_runner_id(&path, runner_id));
 
     let (state_tx, state_rx) = mpsc::sync_channel::<(Uuid, String)>(64);
+    let (presence_tx, presence_rx) = mpsc::channel::<(Uuid, DevicePresence)>();
+    // Small bound: a device handing over frames faster than they can be
+    // published blocks in `tick()` instead of piling frames up in memory.
+    let (frame_tx, frame_rx) = mpsc::sync_channel::<(Uuid, FrameHeader, Vec<u8>)>(2);
+    // Previews are disposable: at most one waits per device, a newer one
+    // replaces it while the publisher is busy.
+    let (preview_tx, preview_rx) = preview_slots();
+    let preview_interval = Duration::from_millis(config.preview_interval_ms);
     let tick_interval = Duration::from_millis(config.tick_interval_ms);
+    let shutdown_timeout = Duration::from_millis(config.shutdown_timeout_ms);
 
-    let mut dispatchers: HashMap<
-        Uuid,
-        Box<dyn Fn(&str, &[u8]) -> Result<(), LightspeedError> + Send + Sync>,
-    > = HashMap::new();
-    let mut subscribe_topics: Vec<String> = Vec::new();
+    let mut router = TopicRouter::new();
     let mut device_uuids: Vec<Uuid> = Vec::new();
+    let mut inventories: HashMap<Uuid, DeviceInventory> = HashMap::new();
+    let host = hostname();
 
     for device in &devices {
         let uuid = device.id();
         device_uuids.push(uuid);
-        dispatchers.insert(uuid, device.dispatcher());
+        inventories.insert(
+            uuid,
+            DeviceInventory {
+                name: Some(device.name().to_string()),
+                device_type: Some(device.dev_type()),
+                model: device.model().map(str::to_string),
+                firmware_version: device.firmware_version().map(str::to_string),
+                serial_number: device.serial_number().map(str::to_string),
+                hostname: Some(host.clone()),
+                port: device.port().map(str::to_string),
+            },
+        );
+        let dispatch = Arc::new(device.dispatcher());
         for suffix in device.command_topics() {
-            subscribe_topics.push(topics::device_cmd(uuid, suffix));
+            let dispatch = dispatch.clone();
+            let action = suffix.to_string();
+            let filter = config.namespace.device_cmd(uuid, suffix);
+            if let Err(e) = router.route(filter, move |_, payload| dispatch(&action, payload)) {
+                error!("Invalid command topic {suffix} for {uuid}: {e:?}");
+            }
         }
         info!("Registered device: {} ({})", device.name(), uuid);
     }
 
-    let shutdown = Arc::new(AtomicBool::new(false));
+    let presence = Presence {
+        namespace: config.namespace.clone(),
+        runner_id,
+        device_uuids: device_uuids.clone(),
+        inventories,
+        started_at: epoch_secs(),
+        driver_version: config.driver_version.clone(),
+        pid: std::process::id(),
+    };
 
     // Spawn one thread per device.
     let mut handles = Vec::new();
     for mut device in devices {
+        let uuid = device.id();
         let state_tx = state_tx.clone();
+        let presence_tx = presence_tx.clone();
+        let frame_tx = frame_tx.clone();
+        let preview_tx = preview_tx.clone();
         let shutdown = shutdown.clone();
-        let handle = thread::spawn(move || loop {
-            if shutdown.load(Ordering::Acquire) {
-                device.close();
-                break;
+        let handle = thread::spawn(move || {
+            let mut last_presence: Option<DevicePresence> = None;
+            let mut last_preview: Option<Instant> = None;
+            let mut tick = |device: &mut D| {
+                let start = Instant::now();
+                device.tick(&state_tx);
+                let presence = device.presence();
+                if last_presence.as_ref() != Some(&presence) {
+                    last_presence = Some(presence.clone());
+                    let _ = presence_tx.send((uuid, presence));
+                }
+                if let Some((header, data)) = device.take_frame() {
+                    let _ = frame_tx.send((uuid, header, data));
+                }
+                if last_preview.is_none_or(|t| start.duration_since(t) >= preview_interval) {
+                    if let Some(preview) = device.take_preview() {
+                        last_preview = Some(start);
+                        preview_tx.send(uuid, preview);
+                    }
+                }
+                let elapsed = start.elapsed();
+                if elapsed < tick_interval {
+                    thread::sleep(tick_interval - elapsed);
+                }
+            };
+            while !shutdown.is_shutdown() {
+                tick(&mut device);
             }
-            let start = Instant::now();
-            device.tick(&state_tx);
-            let elapsed = start.elapsed();
-            if elapsed < tick_interval {
-                thread::sleep(tick_interval - elapsed);
+            let deadline = Instant::now() + shutdown_timeout;
+            while device.is_busy() && Instant::now() < deadline {
+                tick(&mut device);
             }
+            if device.is_busy() {
+                warn!("Device {uuid} still busy at shutdown deadline, aborting");
+                device.abort();
+            }
+            device.close();
         });
-        handles.push(handle);
+        handles.push((uuid, handle));
     }
+    // Only device threads hold senders from here on, so the publisher
+    // threads below exit once every device has closed and its output has
+    // been drained.
+    drop(state_tx);
+    drop(frame_tx);
+    drop(preview_tx);
 
     // MQTT client setup with LWT.
-    let lwt_payload = serde_json::to_vec(&RunnerStatus {
-        state: PresenceState::Offline,
-        device_uuids: device_uuids.clone(),
-        started_at,
-        runner_version: config.driver_version.clone(),
-        pid,
-    })
-    .expect("failed to serialize LWT payload");
+    let lwt_payload = serde_json::to_vec(&presence.runner_status(PresenceState::Offline))
+        .expect("failed to serialize LWT payload");
 
     let last_will = LastWill::new(
-        topics::runner_status(runner_id),
+        config.namespace.runner_status(runner_id),
         lwt_payload,
         QoS::AtLeastOnce,
         true, // retained
@@ -120,137 +477,297 @@ pub fn run<D: LightspeedDevice>(devices: Vec<D>, config: RunnerConfig) {
         config.broker_port,
     );
     opts.set_keep_alive(Duration::from_secs(config.keepalive_secs));
-    opts.set_max_packet_size(10 * 1024 * 1024, 10 * 1024 * 1024);
+    opts.set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE);
     opts.set_last_will(last_will);
 
     let (client, mut connection) = Client::new(opts, 10);
 
     // Subscribe to all device command topics.
-    for topic in &subscribe_topics {
+    for topic in router.filters() {
         if let Err(e) = client.subscribe(topic, QoS::AtLeastOnce) {
             error!("Failed to subscribe to {topic}: {e}");
         }
     }
 
-    // Publish runner Online status (retained).
-    let runner_online = RunnerStatus {
-        state: PresenceState::Online,
-        device_uuids: device_uuids.clone(),
-        started_at,
-        runner_version: config.driver_version.clone(),
-        pid,
-    };
-    if let Ok(payload) = serde_json::to_vec(&runner_online) {
-        if let Err(e) = client.publish(topics::runner_status(runner_id), QoS::AtLeastOnce, true, payload) {
-            error!("Failed to publish runner status: {e}");
+    // Clear the retained status left behind by a previous runner identity.
+    if let Some(prev) = previous_runner_id {
+        info!("Clearing retained status of previous runner {prev}");
+        if let Err(e) = client.publish(
+            config.namespace.runner_status(prev),
+            QoS::AtLeastOnce,
+            true,
+            Vec::new(),
+        ) {
+            error!("Failed to clear previous runner status: {e}");
         }
     }
 
-    // Publish per-device Online status (retained).
+    // Publish runner Online and per-device Starting status (retained). Each
+    // device thread reports its own presence after the first tick.
+    presence.publish_runner(&client, PresenceState::Online);
     for uuid in &device_uuids {
-        let status = DeviceStatus {
-            state: PresenceState::Online,
-            runner_id,
-            started_at,
-            driver_version: config.driver_version.clone(),
-            pid,
-        };
-        if let Ok(payload) = serde_json::to_vec(&status) {
-            if let Err(e) = client.publish(topics::device_status(*uuid), QoS::AtLeastOnce, true, payload) {
-                error!("Failed to publish device status for {uuid}: {e}");
-            }
-        }
+        presence.publish_device(&client, *uuid, DevicePresence::new(PresenceState::Starting));
     }
 
     // State-publish thread.
     let pub_client = client.clone();
-    thread::spawn(move || {
-        while let Ok((uuid, json)) = state_rx.recv() {
-            let topic = topics::device_state(uuid);
-            if let Err(e) = pub_client.publish(&topic, QoS::AtLeastOnce, false, json.as_bytes()) {
-                error!("Publish failed for {uuid}: {e}");
+    let pub_namespace = config.namespace.clone();
+    let mut publishers = Vec::new();
+    publishers.push((
+        "state",
+        thread::spawn(move || {
+            while let Ok((uuid, json)) = state_rx.recv() {
+                let topic = pub_namespace.device_state(uuid);
+                if let Err(e) = pub_client.publish(&topic, QoS::AtLeastOnce, false, json.as_bytes())
+                {
+                    error!("Publish failed for {uuid}: {e}");
+                }
             }
-        }
-    });
+        }),
+    ));
 
-    // Ctrl-C handler: publish graceful Offline statuses, then disconnect.
-    let shutdown_flag = shutdown.clone();
-    let client_for_ctrlc = client.clone();
-    let device_uuids_for_ctrlc = device_uuids.clone();
-    let driver_version_for_ctrlc = config.driver_version.clone();
-    ctrlc::set_handler(move || {
-        info!("Shutdown signal received");
-        shutdown_flag.store(true, Ordering::Release);
-
-        // Best-effort graceful Offline statuses.
-        for uuid in &device_uuids_for_ctrlc {
-            let status = DeviceStatus {
-                state: PresenceState::Offline,
-                runner_id,
-                started_at,
-                driver_version: driver_version_for_ctrlc.clone(),
-                pid,
-            };
-            if let Ok(payload) = serde_json::to_vec(&status) {
-                let _ = client_for_ctrlc.publish(
-                    topics::device_status(*uuid),
-                    QoS::AtLeastOnce,
-                    true,
-                    payload,
-                );
-            }
-        }
-        let runner_offline = RunnerStatus {
-            state: PresenceState::Offline,
-            device_uuids: device_uuids_for_ctrlc.clone(),
-            started_at,
-            runner_version: driver_version_for_ctrlc.clone(),
-            pid,
-        };
-        if let Ok(payload) = serde_json::to_vec(&runner_offline) {
-            let _ = client_for_ctrlc.publish(
-                topics::runner_status(runner_id),
-                QoS::AtLeastOnce,
-                true,
-                payload,
-            );
-        }
-        let _ = client_for_ctrlc.disconnect();
-    })
-    .expect("Failed to register Ctrl-C handler");
-
-    // Main MQTT event loop.
-    for event in connection.iter() {
-        match event {
-            Ok(Event::Incoming(Packet::Publish(p))) => {
-                let topic = p.topic.as_str();
-                match topics::parse_device_topic(topic) {
-                    Some((uuid, action)) if !action.is_empty() => {
-                        if let Some(dispatch) = dispatchers.get(&uuid) {
-                            if let Err(e) = dispatch(action, &p.payload) {
-                                error!("Dispatch error for {uuid}/{action}: {e:?}");
-                            }
-                        } else {
-                            warn!("No device for UUID {uuid}");
-                        }
+    // Frame-publish thread.
+    let frame_client = client.clone();
+    let frame_namespace = config.namespace.clone();
+    let chunker = FrameChunker::new(config.frame_chunk_size);
+    if chunker.chunk_size() != config.frame_chunk_size {
+        warn!(
+            "frame_chunk_size {} out of range, using {}",
+            config.frame_chunk_size,
+            chunker.chunk_size()
+        );
+    }
+    publishers.push((
+        "frame",
+        thread::spawn(move || {
+            while let Ok((uuid, header, data)) = frame_rx.recv() {
+                let topic = frame_namespace.device_frame(uuid);
+                let chunks = match chunker.chunks(&header, &data) {
+                    Ok(chunks) => chunks,
+                    Err(e) => {
+                        error!("Failed to chunk frame for {uuid}: {e:?}");
+                        continue;
+                    }
+                };
+                for chunk in chunks {
+                    if let Err(e) = frame_client.publish(&topic, QoS::AtLeastOnce, false, chunk) {
+                        error!("Frame publish failed for {uuid}: {e}");
+                        break;
                     }
-                    Some(_) => {
-                        // devices/{uuid} with no action — ignore (it's our own state publish loopback)
+                }
+            }
+        }),
+    ));
+
+    // Preview-publish thread.
+    let preview_client = client.clone();
+    let preview_namespace = config.namespace.clone();
+    publishers.push((
+        "preview",
+        thread::spawn(move || {
+            while let Some(previews) = preview_rx.recv() {
+                for (uuid, preview) in previews {
+                    let topic = preview_namespace.device_preview(uuid);
+                    if let Err(e) = preview_client.publish(&topic, QoS::AtMostOnce, false, preview)
+                    {
+                        error!("Preview publish failed for {uuid}: {e}");
                     }
-                    None => warn!("Unexpected topic: {topic}"),
                 }
             }
-            Ok(_) => {}
-            Err(e) => {
-                if shutdown.load(Ordering::Acquire) {
+        }),
+    ));
+
+    // Signal handler only flips the shutdown flag; the main loop below
+    // performs the ordered teardown.
+    let signal_shutdown = shutdown.clone();
+    if let Err(e) = ctrlc::set_handler(move || {
+        info!("Shutdown signal received");
+        signal_shutdown.shutdown();
+    }) {
+        warn!("Failed to register signal handler: {e}");
+    }
+
+    // Main MQTT event loop. It keeps polling during shutdown so the Offline
+    // publishes and the final disconnect actually reach the broker.
+    let mut close_deadline: Option<Instant> = None;
+    let mut disconnecting = false;
+    let mut open = handles;
+    let mut late = Vec::new();
+    loop {
+        match connection.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(Event::Incoming(Packet::Publish(p)))) if close_deadline.is_none() => {
+                let topic = p.topic.as_str();
+                match router.dispatch(topic, &p.payload) {
+                    Ok(0) => warn!("Unexpected topic: {topic}"),
+                    Ok(_) => {}
+                    Err(e) => error!("Dispatch error for {topic}: {e:?}"),
+                }
+            }
+            Ok(Ok(Event::Outgoing(Outgoing::Disconnect))) => break,
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => {
+                if disconnecting {
                     break;
                 }
                 error!("MQTT error: {e}");
             }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        // Presence changes reported by device threads. Late reports from a
+        // device that already closed must not overwrite its Offline status.
+        while let Ok((uuid, device_presence)) = presence_rx.try_recv() {
+            if close_deadline.is_none() || open.iter().any(|(u, _)| *u == uuid) {
+                presence.publish_device(&client, uuid, device_presence);
+            }
+        }
+
+        if !shutdown.is_shutdown() || disconnecting {
+            continue;
+        }
+
+        // Step 1: stop accepting commands.
+        let deadline = *close_deadline.get_or_insert_with(|| {
+            info!("Shutting down: waiting for devices to finish");
+            for topic in router.filters() {
+                let _ = client.unsubscribe(topic);
+            }
+            Instant::now() + shutdown_timeout + CLOSE_GRACE
+        });
+
+        // Steps 2-3: publish Offline for every device whose thread has closed.
+        let (closed, still_open): (Vec<_>, Vec<_>) =
+            open.into_iter().partition(|(_, h)| h.is_finished());
+        open = still_open;
+        for (uuid, handle) in closed {
+            if handle.join().is_err() {
+                error!("Device thread for {uuid} panicked");
+            }
+            presence.publish_device(&client, uuid, DevicePresence::new(PresenceState::Offline));
+        }
+        if !open.is_empty() && Instant::now() >= deadline {
+            for (uuid, handle) in open.drain(..) {
+                warn!("Device {uuid} did not close in time");
+                presence.publish_device(&client, uuid, DevicePresence::new(PresenceState::Offline));
+                late.push((uuid, handle));
+            }
+        }
+        if !open.is_empty() {
+            continue;
+        }
+
+        // Step 4: let the publishers drain frames and state already handed
+        // over, within what is left of the deadline.
+        if publishers.iter().any(|(_, h)| !h.is_finished()) && Instant::now() < deadline {
+            continue;
+        }
+        for (name, handle) in publishers.drain(..) {
+            if !handle.is_finished() {
+                warn!("{name} publisher did not drain before the shutdown deadline");
+            } else if handle.join().is_err() {
+                error!("{name} publisher thread panicked");
+            }
+        }
+
+        // Step 5: runner Offline, then disconnect.
+        presence.publish_runner(&client, PresenceState::Offline);
+        if let Err(e) = client.disconnect() {
+            error!("Failed to disconnect: {e}");
+            break;
+        }
+        disconnecting = true;
+    }
+
+    // Device threads that missed the deadline are joined if they have closed
+    // by now; one still stuck in the driver is logged and left behind.
+    for (uuid, handle) in late {
+        if !handle.is_finished() {
+            error!("Device thread for {uuid} is still running, leaving it detached");
+        } else if handle.join().is_err() {
+            error!("Device thread for {uuid} panicked");
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runner_id_is_deterministic() {
+        let config = RunnerConfig {
+            runner_name: Some("observatory-pi".into()),
+            ..Default::default()
+        };
+        assert_eq!(config.runner_id(), config.runner_id());
+        assert_eq!(config.runner_id().get_version_num(), 5);
+    }
+
+    #[test]
+    fn runner_id_depends_on_name() {
+        let a = RunnerConfig {
+            runner_name: Some("rig-a".into()),
+            ..Default::default()
+        };
+        let b = RunnerConfig {
+            runner_name: Some("rig-b".into()),
+            ..Default::default()
+        };
+        assert_ne!(a.runner_id(), b.runner_id());
+    }
+
+    #[test]
+    fn shutdown_handle_is_shared_between_clones() {
+        let handle = ShutdownHandle::new();
+        let clone = handle.clone();
+        assert!(!handle.is_shutdown());
+        clone.shutdown();
+        assert!(handle.is_shutdown());
+    }
+
+    #[test]
+    fn persisted_runner_id_reports_previous_once() {
+        let path = std::env::temp_dir().join(format!("astrotools-runner-{}", Uuid::now_v7()));
+        let old = Uuid::now_v7();
+        let new = Uuid::now_v7();
+        assert_eq!(swap_persisted_runner_id(&path, old), None);
+        assert_eq!(swap_persisted_runner_id(&path, new), Some(old));
+        assert_eq!(swap_persisted_runner_id(&path, new), None);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn state_file_defaults_per_client_id() {
+        let dir = Path::new("/var/state");
+        assert_eq!(
+            default_state_file(dir, "qhy/cam 1"),
+            Path::new("/var/state/lightspeed/runner-qhy_cam_1.id")
+        );
+        let config = RunnerConfig {
+            state_file: Some(PathBuf::from("/tmp/id")),
+            ..Default::default()
+        };
+        assert_eq!(config.state_file_path(), Some(PathBuf::from("/tmp/id")));
+    }
+
+    #[test]
+    fn preview_slots_keep_latest_per_device() {
+        let (tx, rx) = preview_slots();
+        let (a, b) = (Uuid::now_v7(), Uuid::now_v7());
+        tx.send(a, vec![1]);
+        tx.send(b, vec![2]);
+        tx.send(a, vec![3]);
+        let previews = rx.recv().unwrap();
+        assert_eq!(previews.len(), 2);
+        assert_eq!(previews[&a], vec![3]);
+        assert_eq!(previews[&b], vec![2]);
 
-    for handle in handles {
-        let _ = handle.join();
+        let device = tx.clone();
+        drop(tx);
+        device.send(b, vec![4]);
+        drop(device);
+        assert_eq!(rx.recv().unwrap()[&b], vec![4]);
+        assert!(rx.recv().is_none());
     }
 }
@@ -0,0 +1,356 @@
+//! SER writer for recorded video streams.
+//!
+//! SER is the planetary/lucky-imaging container read by AutoStakkert!,
+//! Siril, PIPP and friends: a 178-byte header, the frames back to back and a
+//! trailer with one UTC timestamp per frame. All frames of a file share the
+//! geometry and pixel layout of the first one.
+//!
+//! Samples are written as bytes when the frame has up to 8 significant bits
+//! and as little-endian 16-bit words otherwise, with `PixelDepthPerPlane`
+//! set to the significant bits (e.g. 12). Interleaved RGB frames use colour
+//! id `RGB`, Bayer frames the matching `BAYER_*` id.
+
+use std::fmt;
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::frame::{
+    decompress_payload, unpack_samples, BayerPattern, FrameError, FrameHeader, PixelFormat,
+    MAX_PAYLOAD_LEN,
+};
+
+/// Size of the fixed SER header.
+pub const HEADER_LEN: usize = 178;
+
+const FILE_ID: &[u8; 14] = b"LUCAM-RECORDER";
+/// Header offsets patched by [`SerWriter::finish`].
+const FRAME_COUNT_OFFSET: u64 = 38;
+const DATE_TIME_OFFSET: u64 = 162;
+/// 100 ns ticks between 0001-01-01 and the unix epoch, the SER time base.
+const UNIX_EPOCH_TICKS: i64 = 621_355_968_000_000_000;
+
+#[derive(Debug)]
+pub enum SerError {
+    /// The frame could not be read.
+    Frame(FrameError),
+    /// The frame's geometry or layout differs from the recording's.
+    Mismatch(String),
+    /// SER counts frames in a signed 32-bit field.
+    TooManyFrames,
+    Io(io::Error),
+}
+
+impl fmt::Display for SerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerError::Frame(e) => write!(f, "ser source: {e}"),
+            SerError::Mismatch(e) => write!(f, "frame does not match ser recording: {e}"),
+            SerError::TooManyFrames => f.write_str("too many frames for a ser file"),
+            SerError::Io(e) => write!(f, "ser i/o error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SerError {}
+
+impl From<FrameError> for SerError {
+    fn from(error: FrameError) -> Self {
+        SerError::Frame(error)
+    }
+}
+
+impl From<io::Error> for SerError {
+    fn from(error: io::Error) -> Self {
+        SerError::Io(error)
+    }
+}
+
+/// SER `ColorID`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerColor {
+    Mono,
+    Bayer(BayerPattern),
+    Rgb,
+}
+
+impl SerColor {
+    pub fn from_frame(header: &FrameHeader) -> Self {
+        match (header.channels(), header.bayer) {
+            (3, _) => SerColor::Rgb,
+            (_, Some(pattern)) => SerColor::Bayer(pattern),
+            _ => SerColor::Mono,
+        }
+    }
+
+    pub fn id(&self) -> i32 {
+        match self {
+            SerColor::Mono => 0,
+            SerColor::Bayer(BayerPattern::Rggb) => 8,
+            SerColor::Bayer(BayerPattern::Grbg) => 9,
+            SerColor::Bayer(BayerPattern::Gbrg) => 10,
+            SerColor::Bayer(BayerPattern::Bggr) => 11,
+            SerColor::Rgb => 100,
+        }
+    }
+}
+
+/// Free-text header fields, truncated to 40 bytes each.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SerMetadata {
+    pub observer: String,
+    pub instrument: String,
+    pub telescope: String,
+}
+
+/// Streams frames into a SER file. Call [`SerWriter::finish`] at the end;
+/// it writes the timestamp trailer and the final frame count.
+pub struct SerWriter<W: Write + Seek> {
+    inner: W,
+    width: u32,
+    height: u32,
+    channels: u32,
+    bits: u32,
+    color: SerColor,
+    timestamps: Vec<i64>,
+}
+
+impl<W: Write + Seek> SerWriter<W> {
+    /// Write the header for a recording of frames shaped like `template`.
+    pub fn new(
+        mut inner: W,
+        template: &FrameHeader,
+        metadata: &SerMetadata,
+    ) -> Result<Self, SerError> {
+        let bits = template.significant_bits();
+        if bits == 0 || bits > 16 {
+            return Err(FrameError::PixelLayout.into());
+        }
+        let color = SerColor::from_frame(template);
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(FILE_ID);
+        header.extend_from_slice(&0i32.to_le_bytes());
+        header.extend_from_slice(&color.id().to_le_bytes());
+        // The spec says 1 for little-endian, but the common readers and
+        // writers use 0, so that is what they expect.
+        header.extend_from_slice(&0i32.to_le_bytes());
+        header.extend_from_slice(&(template.width as i32).to_le_bytes());
+        header.extend_from_slice(&(template.height as i32).to_le_bytes());
+        header.extend_from_slice(&(bits as i32).to_le_bytes());
+        header.extend_from_slice(&0i32.to_le_bytes());
+        for text in [
+            &metadata.observer,
+            &metadata.instrument,
+            &metadata.telescope,
+        ] {
+            header.extend_from_slice(&fixed_text(text));
+        }
+        header.extend_from_slice(&[0; 16]);
+        debug_assert_eq!(header.len(), HEADER_LEN);
+        inner.write_all(&header)?;
+        Ok(Self {
+            inner,
+            width: template.width,
+            height: template.height,
+            channels: template.channels(),
+            bits,
+            color,
+            timestamps: Vec::new(),
+        })
+    }
+
+    /// Append one frame, stamped with `header.timestamp_ns`.
+    pub fn write_frame(&mut self, header: &FrameHeader, data: &[u8]) -> Result<(), SerError> {
+        if (header.width, header.height) != (self.width, self.height) {
+            return Err(SerError::Mismatch(format!(
+                "{}x{} frame in a {}x{} recording",
+                header.width, header.height, self.width, self.height
+            )));
+        }
+        if header.significant_bits() != self.bits
+            || header.channels() != self.channels
+            || SerColor::from_frame(header) != self.color
+        {
+            return Err(SerError::Mismatch("pixel layout changed".into()));
+        }
+        if self.timestamps.len() >= i32::MAX as usize {
+            return Err(SerError::TooManyFrames);
+        }
+
+        let mut source = header.clone();
+        let decoded;
+        let data = if header.encoding.is_raw() {
+            data
+        } else {
+            decoded = decompress_payload(&mut source, data, MAX_PAYLOAD_LEN)?;
+            &decoded
+        };
+        if is_ser_layout(&source) {
+            let expected = source.payload_len();
+            if expected != data.len() as u64 {
+                return Err(FrameError::PayloadLength {
+                    expected,
+                    actual: data.len() as u64,
+                }
+                .into());
+            }
+            self.inner.write_all(data)?;
+        } else {
+            let samples = unpack_samples(&source, data)?;
+            let bytes: Vec<u8> = if self.bits <= 8 {
+                samples.iter().map(|&s| s as u8).collect()
+            } else {
+                samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+            };
+            self.inner.write_all(&bytes)?;
+        }
+        self.timestamps.push(ticks(header.timestamp_ns));
+        Ok(())
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    /// Write the timestamp trailer, patch the header and return the writer.
+    pub fn finish(mut self) -> Result<W, SerError> {
+        for t in &self.timestamps {
+            self.inner.write_all(&t.to_le_bytes())?;
+        }
+        let end = self.inner.stream_position()?;
+        self.inner.seek(SeekFrom::Start(FRAME_COUNT_OFFSET))?;
+        self.inner
+            .write_all(&(self.timestamps.len() as i32).to_le_bytes())?;
+        // No time zone is known here, so local and UTC start times match.
+        let start = self.timestamps.first().copied().unwrap_or(0);
+        self.inner.seek(SeekFrom::Start(DATE_TIME_OFFSET))?;
+        self.inner.write_all(&start.to_le_bytes())?;
+        self.inner.write_all(&start.to_le_bytes())?;
+        self.inner.seek(SeekFrom::Start(end))?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// True if the raw payload already is SER sample data: bytes, or
+/// LSB-aligned little-endian words.
+fn is_ser_layout(header: &FrameHeader) -> bool {
+    match header.pixel_format {
+        Some(
+            PixelFormat::Mono8
+            | PixelFormat::Bayer8
+            | PixelFormat::Rgb24
+            | PixelFormat::Mono12Le
+            | PixelFormat::Mono16Le
+            | PixelFormat::Bayer16
+            | PixelFormat::Rgb48Le,
+        ) => true,
+        Some(_) => false,
+        None => header.bit_depth == 8 || header.bit_depth == 16,
+    }
+}
+
+fn fixed_text(text: &str) -> [u8; 40] {
+    let mut out = [0; 40];
+    let bytes = text.as_bytes();
+    let len = bytes.len().min(out.len());
+    out[..len].copy_from_slice(&bytes[..len]);
+    out
+}
+
+/// Unix nanoseconds to SER ticks.
+fn ticks(timestamp_ns: u64) -> i64 {
+    UNIX_EPOCH_TICKS + (timestamp_ns / 100) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn i32_at(buf: &[u8], at: usize) -> i32 {
+        i32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+    }
+
+    fn i64_at(buf: &[u8], at: usize) -> i64 {
+        i64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+    }
+
+    fn header(pixel_format: PixelFormat, timestamp_ns: u64) -> FrameHeader {
+        FrameHeader {
+            width: 4,
+            height: 2,
+            bit_depth: 16,
+            bayer: Some(BayerPattern::Gbrg),
+            pixel_format: Some(pixel_format),
+            timestamp_ns,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn writes_header_frames_and_trailer() {
+        let metadata = SerMetadata {
+            observer: "devDucks".into(),
+            instrument: "x".repeat(50),
+            ..Default::default()
+        };
+        let first = header(PixelFormat::Bayer16, 1_000_000_000);
+        let mut ser = SerWriter::new(Cursor::new(Vec::new()), &first, &metadata).unwrap();
+        let data: Vec<u8> = (0..16).collect();
+        ser.write_frame(&first, &data).unwrap();
+        ser.write_frame(&header(PixelFormat::Bayer16, 1_000_000_100), &data)
+            .unwrap();
+        let out = ser.finish().unwrap().into_inner();
+
+        assert_eq!(&out[..14], b"LUCAM-RECORDER");
+        assert_eq!(i32_at(&out, 18), 10);
+        assert_eq!(
+            (i32_at(&out, 26), i32_at(&out, 30), i32_at(&out, 34)),
+            (4, 2, 16)
+        );
+        assert_eq!(i32_at(&out, 38), 2);
+        assert_eq!(&out[42..50], b"devDucks");
+        assert_eq!(out[121], b'x');
+        assert_eq!(out[122], 0);
+        assert_eq!(i64_at(&out, 170), UNIX_EPOCH_TICKS + 10_000_000);
+
+        assert_eq!(out.len(), HEADER_LEN + 2 * 16 + 2 * 8);
+        assert_eq!(&out[HEADER_LEN..HEADER_LEN + 16], &data[..]);
+        assert_eq!(i64_at(&out, out.len() - 8), UNIX_EPOCH_TICKS + 10_000_001);
+    }
+
+    #[test]
+    fn converts_packed_and_big_endian_samples() {
+        let mut packed = header(PixelFormat::Mono12Packed, 0);
+        packed.bayer = None;
+        let mut ser =
+            SerWriter::new(Cursor::new(Vec::new()), &packed, &SerMetadata::default()).unwrap();
+        // 0xabc, 0x123 repeated.
+        let data = [0xab, 0x3c, 0x12].repeat(4);
+        ser.write_frame(&packed, &data).unwrap();
+        let out = ser.finish().unwrap().into_inner();
+        assert_eq!((i32_at(&out, 18), i32_at(&out, 34)), (0, 12));
+        assert_eq!(&out[HEADER_LEN..HEADER_LEN + 4], &[0xbc, 0x0a, 0x23, 0x01]);
+    }
+
+    #[test]
+    fn rejects_mismatched_frames() {
+        let first = header(PixelFormat::Bayer16, 0);
+        let mut ser =
+            SerWriter::new(Cursor::new(Vec::new()), &first, &SerMetadata::default()).unwrap();
+        let mut wider = first.clone();
+        wider.width = 8;
+        assert!(matches!(
+            ser.write_frame(&wider, &[0; 32]),
+            Err(SerError::Mismatch(_))
+        ));
+        assert!(matches!(
+            ser.write_frame(&header(PixelFormat::Bayer8, 0), &[0; 8]),
+            Err(SerError::Mismatch(_))
+        ));
+        assert!(matches!(
+            ser.write_frame(&first, &[0; 4]),
+            Err(SerError::Frame(FrameError::PayloadLength { .. }))
+        ));
+        assert_eq!(ser.frame_count(), 0);
+    }
+}
@@ -94,7 +94,7 @@ mod tests {
 
     #[test]
     fn returns_empty_when_no_ports() {
-        let result = find_serial_devices_with("ABC", || vec![]);
+        let result = find_serial_devices_with("ABC", Vec::new);
         assert!(result.is_empty());
     }
 }
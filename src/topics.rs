@@ -12,17 +12,41 @@
 //! server/{area}/{action}                      server API endpoints
 //! clients/{client_id}/replies/{correlation_id}  request/response reply topic
 //! ```
+//!
+//! [`Topic`] is the typed form of this table: every topic above parses into
+//! a `Topic` via `FromStr` and formats back to the same string via `Display`.
+//!
+//! Several observatories (or a production and a test rig) can share one
+//! broker by putting their tree under a [`TopicNamespace`], e.g.
+//! `obs/siteA/devices/{device_uuid}`. The free functions in this module build
+//! and parse topics in the root namespace.
+//!
+//! Subscription filters for each topic kind are built by the `*_filter`
+//! functions; [`topic_matches`] implements MQTT 3.1.1 filter matching and
+//! [`TopicRouter`] dispatches incoming messages to handlers by filter.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
 
 use uuid::Uuid;
 
+use crate::LightspeedError;
+
 pub const DEVICES_PREFIX: &str = "devices";
 pub const RUNNERS_PREFIX: &str = "runners";
-pub const SERVER_PREFIX:  &str = "server";
+pub const SERVER_PREFIX: &str = "server";
 pub const CLIENTS_PREFIX: &str = "clients";
 
-pub const STATUS_SUFFIX:  &str = "status";
-pub const FRAME_SUFFIX:   &str = "frame";
+pub const STATUS_SUFFIX: &str = "status";
+pub const FRAME_SUFFIX: &str = "frame";
 pub const PREVIEW_SUFFIX: &str = "preview";
+pub const REPLIES_SEGMENT: &str = "replies";
+
+/// Single-level MQTT wildcard.
+pub const SINGLE_LEVEL_WILDCARD: &str = "+";
+/// Multi-level MQTT wildcard.
+pub const MULTI_LEVEL_WILDCARD: &str = "#";
 
 pub fn device_state(uuid: Uuid) -> String {
     format!("{DEVICES_PREFIX}/{uuid}")
@@ -53,7 +77,437 @@ pub fn server_endpoint(area: &str, action: &str) -> String {
 }
 
 pub fn client_reply(client_id: &str, correlation_id: Uuid) -> String {
-    format!("{CLIENTS_PREFIX}/{client_id}/replies/{correlation_id}")
+    format!("{CLIENTS_PREFIX}/{client_id}/{REPLIES_SEGMENT}/{correlation_id}")
+}
+
+/// `devices/+`: state of every device.
+pub fn device_state_filter() -> String {
+    format!("{DEVICES_PREFIX}/{SINGLE_LEVEL_WILDCARD}")
+}
+
+/// `devices/+/{action}`: one command on every device.
+pub fn device_cmd_filter(action: &str) -> String {
+    format!("{DEVICES_PREFIX}/{SINGLE_LEVEL_WILDCARD}/{action}")
+}
+
+/// `devices/+/status`: presence of every device.
+pub fn device_status_filter() -> String {
+    format!("{DEVICES_PREFIX}/{SINGLE_LEVEL_WILDCARD}/{STATUS_SUFFIX}")
+}
+
+/// `devices/+/frame`: frames of every device.
+pub fn device_frame_filter() -> String {
+    format!("{DEVICES_PREFIX}/{SINGLE_LEVEL_WILDCARD}/{FRAME_SUFFIX}")
+}
+
+/// `devices/+/preview`: previews of every device.
+pub fn device_preview_filter() -> String {
+    format!("{DEVICES_PREFIX}/{SINGLE_LEVEL_WILDCARD}/{PREVIEW_SUFFIX}")
+}
+
+/// `devices/{uuid}/#`: every topic of one device, its state included.
+pub fn device_filter(uuid: Uuid) -> String {
+    format!("{DEVICES_PREFIX}/{uuid}/{MULTI_LEVEL_WILDCARD}")
+}
+
+/// `runners/+/status`: presence of every runner.
+pub fn runner_status_filter() -> String {
+    format!("{RUNNERS_PREFIX}/{SINGLE_LEVEL_WILDCARD}/{STATUS_SUFFIX}")
+}
+
+/// `server/{area}/#`: every endpoint of one server area.
+pub fn server_endpoint_filter(area: &str) -> String {
+    format!("{SERVER_PREFIX}/{area}/{MULTI_LEVEL_WILDCARD}")
+}
+
+/// `clients/{client_id}/replies/+`: every reply addressed to one client.
+pub fn client_reply_filter(client_id: &str) -> String {
+    format!("{CLIENTS_PREFIX}/{client_id}/{REPLIES_SEGMENT}/{SINGLE_LEVEL_WILDCARD}")
+}
+
+/// Prefix prepended to every topic of the tree. The default is the empty
+/// (root) namespace.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct TopicNamespace {
+    /// Empty, or the namespace segments followed by a trailing `/`.
+    prefix: String,
+}
+
+impl TopicNamespace {
+    /// Build a namespace from e.g. `"obs/siteA"` or `"obs/siteA/"`. Segments
+    /// must be non-empty, must not contain `+` or `#`, and the first one must
+    /// not start with `$` (reserved by brokers). An empty string is the root
+    /// namespace.
+    pub fn new(prefix: &str) -> Result<Self, LightspeedError> {
+        let prefix = prefix.strip_suffix('/').unwrap_or(prefix);
+        if prefix.is_empty() {
+            return Ok(Self::default());
+        }
+        if prefix.starts_with('$') {
+            return Err(LightspeedError::ParseError);
+        }
+        validate_action(prefix)?;
+        Ok(Self {
+            prefix: format!("{prefix}/"),
+        })
+    }
+
+    /// The namespace prefix, empty or ending in `/`.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.prefix.is_empty()
+    }
+
+    /// Format `topic` inside this namespace.
+    pub fn topic(&self, topic: &Topic) -> String {
+        format!("{}{topic}", self.prefix)
+    }
+
+    pub fn device_state(&self, uuid: Uuid) -> String {
+        format!("{}{}", self.prefix, device_state(uuid))
+    }
+
+    pub fn device_cmd(&self, uuid: Uuid, action: &str) -> String {
+        format!("{}{}", self.prefix, device_cmd(uuid, action))
+    }
+
+    pub fn device_status(&self, uuid: Uuid) -> String {
+        format!("{}{}", self.prefix, device_status(uuid))
+    }
+
+    pub fn device_frame(&self, uuid: Uuid) -> String {
+        format!("{}{}", self.prefix, device_frame(uuid))
+    }
+
+    pub fn device_preview(&self, uuid: Uuid) -> String {
+        format!("{}{}", self.prefix, device_preview(uuid))
+    }
+
+    pub fn runner_status(&self, runner_id: Uuid) -> String {
+        format!("{}{}", self.prefix, runner_status(runner_id))
+    }
+
+    pub fn server_endpoint(&self, area: &str, action: &str) -> String {
+        format!("{}{}", self.prefix, server_endpoint(area, action))
+    }
+
+    pub fn client_reply(&self, client_id: &str, correlation_id: Uuid) -> String {
+        format!("{}{}", self.prefix, client_reply(client_id, correlation_id))
+    }
+
+    pub fn device_state_filter(&self) -> String {
+        format!("{}{}", self.prefix, device_state_filter())
+    }
+
+    pub fn device_cmd_filter(&self, action: &str) -> String {
+        format!("{}{}", self.prefix, device_cmd_filter(action))
+    }
+
+    pub fn device_status_filter(&self) -> String {
+        format!("{}{}", self.prefix, device_status_filter())
+    }
+
+    pub fn device_frame_filter(&self) -> String {
+        format!("{}{}", self.prefix, device_frame_filter())
+    }
+
+    pub fn device_preview_filter(&self) -> String {
+        format!("{}{}", self.prefix, device_preview_filter())
+    }
+
+    pub fn device_filter(&self, uuid: Uuid) -> String {
+        format!("{}{}", self.prefix, device_filter(uuid))
+    }
+
+    pub fn runner_status_filter(&self) -> String {
+        format!("{}{}", self.prefix, runner_status_filter())
+    }
+
+    pub fn server_endpoint_filter(&self, area: &str) -> String {
+        format!("{}{}", self.prefix, server_endpoint_filter(area))
+    }
+
+    pub fn client_reply_filter(&self, client_id: &str) -> String {
+        format!("{}{}", self.prefix, client_reply_filter(client_id))
+    }
+
+    /// Strip the namespace prefix, returning the root-relative topic.
+    pub fn strip<'a>(&self, topic: &'a str) -> Option<&'a str> {
+        topic.strip_prefix(self.prefix.as_str())
+    }
+
+    /// Parse a topic of this namespace.
+    pub fn parse(&self, topic: &str) -> Result<Topic, LightspeedError> {
+        self.strip(topic)
+            .ok_or(LightspeedError::ParseError)?
+            .parse()
+    }
+
+    /// Namespaced counterpart of [`parse_device_topic`].
+    pub fn parse_device_topic<'a>(&self, topic: &'a str) -> Option<(Uuid, &'a str)> {
+        parse_device_topic(self.strip(topic)?)
+    }
+
+    /// Namespaced counterpart of [`parse_runner_topic`].
+    pub fn parse_runner_topic<'a>(&self, topic: &'a str) -> Option<(Uuid, &'a str)> {
+        parse_runner_topic(self.strip(topic)?)
+    }
+}
+
+/// A fully parsed lightspeed topic.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Topic {
+    DeviceState {
+        uuid: Uuid,
+    },
+    DeviceCommand {
+        uuid: Uuid,
+        action: String,
+    },
+    DeviceStatus {
+        uuid: Uuid,
+    },
+    DeviceFrame {
+        uuid: Uuid,
+    },
+    DevicePreview {
+        uuid: Uuid,
+    },
+    RunnerStatus {
+        runner_id: Uuid,
+    },
+    ServerEndpoint {
+        area: String,
+        action: String,
+    },
+    ClientReply {
+        client_id: String,
+        correlation_id: Uuid,
+    },
+}
+
+impl Topic {
+    /// Build a validated `DeviceCommand`. See [`validate_action`].
+    pub fn device_cmd(uuid: Uuid, action: &str) -> Result<Self, LightspeedError> {
+        validate_action(action)?;
+        if matches!(action, STATUS_SUFFIX | FRAME_SUFFIX | PREVIEW_SUFFIX) {
+            return Err(LightspeedError::ParseError);
+        }
+        Ok(Topic::DeviceCommand {
+            uuid,
+            action: action.to_string(),
+        })
+    }
+
+    /// Build a validated `ServerEndpoint`. `area` must be a single segment.
+    pub fn server_endpoint(area: &str, action: &str) -> Result<Self, LightspeedError> {
+        validate_segment(area)?;
+        validate_action(action)?;
+        Ok(Topic::ServerEndpoint {
+            area: area.to_string(),
+            action: action.to_string(),
+        })
+    }
+
+    /// Build a validated `ClientReply`. `client_id` must be a single segment.
+    pub fn client_reply(client_id: &str, correlation_id: Uuid) -> Result<Self, LightspeedError> {
+        validate_segment(client_id)?;
+        Ok(Topic::ClientReply {
+            client_id: client_id.to_string(),
+            correlation_id,
+        })
+    }
+
+    /// Device the topic belongs to, for any of the `Device*` variants.
+    pub fn device_uuid(&self) -> Option<Uuid> {
+        match self {
+            Topic::DeviceState { uuid }
+            | Topic::DeviceCommand { uuid, .. }
+            | Topic::DeviceStatus { uuid }
+            | Topic::DeviceFrame { uuid }
+            | Topic::DevicePreview { uuid } => Some(*uuid),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Topic::DeviceState { uuid } => f.write_str(&device_state(*uuid)),
+            Topic::DeviceCommand { uuid, action } => f.write_str(&device_cmd(*uuid, action)),
+            Topic::DeviceStatus { uuid } => f.write_str(&device_status(*uuid)),
+            Topic::DeviceFrame { uuid } => f.write_str(&device_frame(*uuid)),
+            Topic::DevicePreview { uuid } => f.write_str(&device_preview(*uuid)),
+            Topic::RunnerStatus { runner_id } => f.write_str(&runner_status(*runner_id)),
+            Topic::ServerEndpoint { area, action } => f.write_str(&server_endpoint(area, action)),
+            Topic::ClientReply {
+                client_id,
+                correlation_id,
+            } => f.write_str(&client_reply(client_id, *correlation_id)),
+        }
+    }
+}
+
+impl FromStr for Topic {
+    type Err = LightspeedError;
+
+    fn from_str(topic: &str) -> Result<Self, Self::Err> {
+        let (root, rest) = topic.split_once('/').ok_or(LightspeedError::ParseError)?;
+        match root {
+            DEVICES_PREFIX => {
+                let (id, suffix) = match rest.split_once('/') {
+                    Some((id, suffix)) => (id, Some(suffix)),
+                    None => (rest, None),
+                };
+                let uuid = id
+                    .parse::<Uuid>()
+                    .map_err(|_| LightspeedError::ParseError)?;
+                match suffix {
+                    None => Ok(Topic::DeviceState { uuid }),
+                    Some(STATUS_SUFFIX) => Ok(Topic::DeviceStatus { uuid }),
+                    Some(FRAME_SUFFIX) => Ok(Topic::DeviceFrame { uuid }),
+                    Some(PREVIEW_SUFFIX) => Ok(Topic::DevicePreview { uuid }),
+                    Some(action) => Topic::device_cmd(uuid, action),
+                }
+            }
+            RUNNERS_PREFIX => match rest.split_once('/') {
+                Some((id, STATUS_SUFFIX)) => Ok(Topic::RunnerStatus {
+                    runner_id: id.parse().map_err(|_| LightspeedError::ParseError)?,
+                }),
+                _ => Err(LightspeedError::ParseError),
+            },
+            SERVER_PREFIX => {
+                let (area, action) = rest.split_once('/').ok_or(LightspeedError::ParseError)?;
+                Topic::server_endpoint(area, action)
+            }
+            CLIENTS_PREFIX => {
+                let mut parts = rest.split('/');
+                match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                    (Some(client_id), Some(REPLIES_SEGMENT), Some(id), None) => {
+                        let correlation_id = id.parse().map_err(|_| LightspeedError::ParseError)?;
+                        Topic::client_reply(client_id, correlation_id)
+                    }
+                    _ => Err(LightspeedError::ParseError),
+                }
+            }
+            _ => Err(LightspeedError::ParseError),
+        }
+    }
+}
+
+/// An action is one or more `/`-separated segments, none of them empty and
+/// none containing the MQTT wildcards `+` or `#`.
+pub fn validate_action(action: &str) -> Result<(), LightspeedError> {
+    action.split('/').try_for_each(validate_segment)
+}
+
+fn validate_segment(segment: &str) -> Result<(), LightspeedError> {
+    if segment.is_empty() || segment.contains(['/', '+', '#']) {
+        return Err(LightspeedError::ParseError);
+    }
+    Ok(())
+}
+
+/// Check that `filter` is a valid MQTT topic filter: non-empty, `+` and `#`
+/// only as whole levels, `#` only as the last level.
+pub fn validate_filter(filter: &str) -> Result<(), LightspeedError> {
+    if filter.is_empty() {
+        return Err(LightspeedError::ParseError);
+    }
+    let mut levels = filter.split('/').peekable();
+    while let Some(level) = levels.next() {
+        match level {
+            SINGLE_LEVEL_WILDCARD => {}
+            MULTI_LEVEL_WILDCARD if levels.peek().is_none() => {}
+            _ if level.contains(['+', '#']) => return Err(LightspeedError::ParseError),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// MQTT 3.1.1 topic filter matching (section 4.7).
+///
+/// `+` matches exactly one level, `#` matches its parent level and any
+/// number of child levels. Topics starting with `$` never match a filter
+/// starting with a wildcard. Invalid filters match nothing.
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    if validate_filter(filter).is_err() || topic.is_empty() {
+        return false;
+    }
+    if topic.starts_with('$') && filter.starts_with(['+', '#']) {
+        return false;
+    }
+    let mut filter_levels = filter.split('/');
+    let mut topic_levels = topic.split('/');
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            (Some(MULTI_LEVEL_WILDCARD), _) => return true,
+            (Some(SINGLE_LEVEL_WILDCARD), Some(_)) => {}
+            (Some(f), Some(t)) if f == t => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// Handler invoked by [`TopicRouter`] with the full topic and the payload.
+pub type Handler = Arc<dyn Fn(&str, &[u8]) -> Result<(), LightspeedError> + Send + Sync>;
+
+/// Maps topic filters to handlers. Every handler whose filter matches an
+/// incoming topic is invoked, in registration order.
+#[derive(Default, Clone)]
+pub struct TopicRouter {
+    routes: Vec<(String, Handler)>,
+}
+
+impl TopicRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `handler` for `filter`. Fails if the filter is invalid.
+    pub fn route<F>(&mut self, filter: impl Into<String>, handler: F) -> Result<(), LightspeedError>
+    where
+        F: Fn(&str, &[u8]) -> Result<(), LightspeedError> + Send + Sync + 'static,
+    {
+        let filter = filter.into();
+        validate_filter(&filter)?;
+        self.routes.push((filter, Arc::new(handler)));
+        Ok(())
+    }
+
+    /// Registered filters, in registration order, e.g. to subscribe to them.
+    pub fn filters(&self) -> impl Iterator<Item = &str> {
+        self.routes.iter().map(|(filter, _)| filter.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Invoke every handler matching `topic`. Returns how many handlers
+    /// matched, or the first handler error (remaining handlers still run).
+    pub fn dispatch(&self, topic: &str, payload: &[u8]) -> Result<usize, LightspeedError> {
+        let mut matched = 0;
+        let mut first_error = None;
+        for (filter, handler) in &self.routes {
+            if topic_matches(filter, topic) {
+                matched += 1;
+                if let Err(e) = handler(topic, payload) {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(matched),
+        }
+    }
 }
 
 /// Parsed device topic: returns `(device_uuid, action)`. `action` is empty
@@ -70,6 +524,19 @@ pub fn parse_device_topic(topic: &str) -> Option<(Uuid, &str)> {
     Some((uuid, action))
 }
 
+/// Parsed runner topic: returns `(runner_id, suffix)`, e.g. `status`.
+///
+/// Returns `None` if the topic is not a runner topic or the UUID is malformed.
+pub fn parse_runner_topic(topic: &str) -> Option<(Uuid, &str)> {
+    let mut parts = topic.splitn(3, '/');
+    if parts.next()? != RUNNERS_PREFIX {
+        return None;
+    }
+    let runner_id = parts.next()?.parse::<Uuid>().ok()?;
+    let suffix = parts.next().unwrap_or("");
+    Some((runner_id, suffix))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -77,17 +544,35 @@ mod tests {
     #[test]
     fn builds_device_topics() {
         let id = Uuid::nil();
-        assert_eq!(device_state(id), "devices/00000000-0000-0000-0000-000000000000");
-        assert_eq!(device_cmd(id, "expose"), "devices/00000000-0000-0000-0000-000000000000/expose");
-        assert_eq!(device_status(id), "devices/00000000-0000-0000-0000-000000000000/status");
-        assert_eq!(device_frame(id), "devices/00000000-0000-0000-0000-000000000000/frame");
-        assert_eq!(device_preview(id), "devices/00000000-0000-0000-0000-000000000000/preview");
+        assert_eq!(
+            device_state(id),
+            "devices/00000000-0000-0000-0000-000000000000"
+        );
+        assert_eq!(
+            device_cmd(id, "expose"),
+            "devices/00000000-0000-0000-0000-000000000000/expose"
+        );
+        assert_eq!(
+            device_status(id),
+            "devices/00000000-0000-0000-0000-000000000000/status"
+        );
+        assert_eq!(
+            device_frame(id),
+            "devices/00000000-0000-0000-0000-000000000000/frame"
+        );
+        assert_eq!(
+            device_preview(id),
+            "devices/00000000-0000-0000-0000-000000000000/preview"
+        );
     }
 
     #[test]
     fn builds_runner_status() {
         let id = Uuid::nil();
-        assert_eq!(runner_status(id), "runners/00000000-0000-0000-0000-000000000000/status");
+        assert_eq!(
+            runner_status(id),
+            "runners/00000000-0000-0000-0000-000000000000/status"
+        );
     }
 
     #[test]
@@ -128,10 +613,303 @@ mod tests {
         assert!(parse_device_topic("devices/not-a-uuid/expose").is_none());
     }
 
+    #[test]
+    fn parses_runner_status_topic() {
+        let id = Uuid::now_v7();
+        let topic = runner_status(id);
+        let (parsed_id, suffix) = parse_runner_topic(&topic).unwrap();
+        assert_eq!(parsed_id, id);
+        assert_eq!(suffix, STATUS_SUFFIX);
+        assert!(parse_runner_topic(&device_status(id)).is_none());
+    }
+
+    #[test]
+    fn topic_roundtrips_every_kind() {
+        let id = Uuid::now_v7();
+        let cid = Uuid::now_v7();
+        let all = [
+            device_state(id),
+            device_cmd(id, "expose"),
+            device_cmd(id, "sub/action"),
+            device_status(id),
+            device_frame(id),
+            device_preview(id),
+            runner_status(id),
+            server_endpoint("profiles", "list"),
+            client_reply("client-1", cid),
+        ];
+        for topic in all {
+            let parsed: Topic = topic.parse().unwrap();
+            assert_eq!(parsed.to_string(), topic);
+        }
+    }
+
+    #[test]
+    fn topic_parses_into_variants() {
+        let id = Uuid::now_v7();
+        assert_eq!(
+            device_status(id).parse::<Topic>().unwrap(),
+            Topic::DeviceStatus { uuid: id }
+        );
+        assert_eq!(
+            device_cmd(id, "expose").parse::<Topic>().unwrap(),
+            Topic::DeviceCommand {
+                uuid: id,
+                action: "expose".into()
+            }
+        );
+        assert_eq!(
+            runner_status(id).parse::<Topic>().unwrap(),
+            Topic::RunnerStatus { runner_id: id }
+        );
+        assert_eq!(
+            client_reply("c", id).parse::<Topic>().unwrap(),
+            Topic::ClientReply {
+                client_id: "c".into(),
+                correlation_id: id
+            }
+        );
+    }
+
+    #[test]
+    fn topic_rejects_invalid_actions() {
+        let id = Uuid::now_v7();
+        assert!(Topic::device_cmd(id, "").is_err());
+        assert!(Topic::device_cmd(id, "a//b").is_err());
+        assert!(Topic::device_cmd(id, "expose/").is_err());
+        assert!(Topic::device_cmd(id, "+").is_err());
+        assert!(Topic::device_cmd(id, "set/#").is_err());
+        assert!(Topic::device_cmd(id, "status").is_err());
+        assert!(format!("devices/{id}/").parse::<Topic>().is_err());
+        assert!(Topic::server_endpoint("a/b", "list").is_err());
+        assert!(Topic::client_reply("c+", id).is_err());
+    }
+
+    #[test]
+    fn topic_rejects_malformed() {
+        assert!("devices".parse::<Topic>().is_err());
+        assert!("devices/not-a-uuid".parse::<Topic>().is_err());
+        assert!("runners/abc/status".parse::<Topic>().is_err());
+        assert!(format!("runners/{}/other", Uuid::nil())
+            .parse::<Topic>()
+            .is_err());
+        assert!("server/profiles".parse::<Topic>().is_err());
+        assert!(format!("clients/c/replies/{}/x", Uuid::nil())
+            .parse::<Topic>()
+            .is_err());
+        assert!("other/topic".parse::<Topic>().is_err());
+    }
+
+    #[test]
+    fn namespace_prefixes_builders() {
+        let id = Uuid::nil();
+        let ns = TopicNamespace::new("obs/siteA").unwrap();
+        assert_eq!(ns, TopicNamespace::new("obs/siteA/").unwrap());
+        assert_eq!(
+            ns.device_state(id),
+            "obs/siteA/devices/00000000-0000-0000-0000-000000000000"
+        );
+        assert_eq!(
+            ns.device_cmd(id, "expose"),
+            "obs/siteA/devices/00000000-0000-0000-0000-000000000000/expose"
+        );
+        assert_eq!(
+            ns.runner_status(id),
+            "obs/siteA/runners/00000000-0000-0000-0000-000000000000/status"
+        );
+        assert_eq!(
+            ns.server_endpoint("profiles", "list"),
+            "obs/siteA/server/profiles/list"
+        );
+        assert_eq!(
+            ns.topic(&Topic::DeviceFrame { uuid: id }),
+            ns.device_frame(id)
+        );
+    }
+
+    #[test]
+    fn namespace_parses_only_its_own_topics() {
+        let id = Uuid::now_v7();
+        let a = TopicNamespace::new("obs/siteA").unwrap();
+        let b = TopicNamespace::new("obs/siteB").unwrap();
+        let topic = a.device_cmd(id, "expose");
+        assert_eq!(a.parse_device_topic(&topic), Some((id, "expose")));
+        assert!(b.parse_device_topic(&topic).is_none());
+        assert!(parse_device_topic(&topic).is_none());
+        assert_eq!(
+            a.parse(&a.device_status(id)).unwrap(),
+            Topic::DeviceStatus { uuid: id }
+        );
+        assert!(b.parse(&a.device_status(id)).is_err());
+        assert_eq!(
+            a.parse_runner_topic(&a.runner_status(id)),
+            Some((id, STATUS_SUFFIX))
+        );
+    }
+
+    #[test]
+    fn root_namespace_matches_free_functions() {
+        let id = Uuid::now_v7();
+        let root = TopicNamespace::default();
+        assert!(root.is_root());
+        assert_eq!(TopicNamespace::new("").unwrap(), root);
+        assert_eq!(root.device_status(id), device_status(id));
+        assert_eq!(root.client_reply("c", id), client_reply("c", id));
+    }
+
+    #[test]
+    fn namespace_rejects_invalid_prefixes() {
+        assert!(TopicNamespace::new("obs//siteA").is_err());
+        assert!(TopicNamespace::new("/obs").is_err());
+        assert!(TopicNamespace::new("obs/+").is_err());
+        assert!(TopicNamespace::new("#").is_err());
+        assert!(TopicNamespace::new("$SYS").is_err());
+    }
+
+    #[test]
+    fn builds_filters() {
+        let id = Uuid::nil();
+        assert_eq!(device_state_filter(), "devices/+");
+        assert_eq!(device_cmd_filter("expose"), "devices/+/expose");
+        assert_eq!(device_status_filter(), "devices/+/status");
+        assert_eq!(device_frame_filter(), "devices/+/frame");
+        assert_eq!(device_preview_filter(), "devices/+/preview");
+        assert_eq!(
+            device_filter(id),
+            "devices/00000000-0000-0000-0000-000000000000/#"
+        );
+        assert_eq!(runner_status_filter(), "runners/+/status");
+        assert_eq!(server_endpoint_filter("profiles"), "server/profiles/#");
+        assert_eq!(client_reply_filter("c"), "clients/c/replies/+");
+        let ns = TopicNamespace::new("obs/siteA").unwrap();
+        assert_eq!(ns.device_status_filter(), "obs/siteA/devices/+/status");
+    }
+
+    #[test]
+    fn filters_match_their_topics() {
+        let id = Uuid::now_v7();
+        assert!(topic_matches(&device_state_filter(), &device_state(id)));
+        assert!(topic_matches(
+            &device_cmd_filter("expose"),
+            &device_cmd(id, "expose")
+        ));
+        assert!(topic_matches(&device_status_filter(), &device_status(id)));
+        assert!(topic_matches(&device_frame_filter(), &device_frame(id)));
+        assert!(topic_matches(&device_preview_filter(), &device_preview(id)));
+        assert!(topic_matches(&device_filter(id), &device_state(id)));
+        assert!(topic_matches(&device_filter(id), &device_status(id)));
+        assert!(topic_matches(&runner_status_filter(), &runner_status(id)));
+        assert!(topic_matches(
+            &server_endpoint_filter("profiles"),
+            &server_endpoint("profiles", "list")
+        ));
+        assert!(topic_matches(
+            &client_reply_filter("c"),
+            &client_reply("c", id)
+        ));
+
+        assert!(!topic_matches(&device_state_filter(), &device_status(id)));
+        assert!(!topic_matches(&device_status_filter(), &runner_status(id)));
+        assert!(!topic_matches(
+            &client_reply_filter("c"),
+            &client_reply("d", id)
+        ));
+    }
+
+    #[test]
+    fn topic_matching_follows_spec() {
+        assert!(topic_matches(
+            "sport/tennis/player1/#",
+            "sport/tennis/player1"
+        ));
+        assert!(topic_matches(
+            "sport/tennis/player1/#",
+            "sport/tennis/player1/ranking"
+        ));
+        assert!(topic_matches(
+            "sport/tennis/player1/#",
+            "sport/tennis/player1/score/wimbledon"
+        ));
+        assert!(topic_matches("sport/#", "sport"));
+        assert!(topic_matches("#", "sport/tennis"));
+        assert!(topic_matches("sport/tennis/+", "sport/tennis/player1"));
+        assert!(!topic_matches(
+            "sport/tennis/+",
+            "sport/tennis/player1/ranking"
+        ));
+        assert!(!topic_matches("sport/+", "sport"));
+        assert!(topic_matches("sport/+", "sport/"));
+        assert!(topic_matches("+/+", "/finance"));
+        assert!(topic_matches("/+", "/finance"));
+        assert!(!topic_matches("+", "/finance"));
+        assert!(!topic_matches("#", "$SYS/broker"));
+        assert!(!topic_matches("+/broker", "$SYS/broker"));
+        assert!(topic_matches("$SYS/#", "$SYS/broker"));
+        assert!(!topic_matches("a/b", "a/b/c"));
+        assert!(!topic_matches("a/b/c", "a/b"));
+    }
+
+    #[test]
+    fn rejects_invalid_filters() {
+        assert!(validate_filter("").is_err());
+        assert!(validate_filter("sport/tennis#").is_err());
+        assert!(validate_filter("sport/tennis/#/ranking").is_err());
+        assert!(validate_filter("sport+").is_err());
+        assert!(validate_filter("+/tennis/#").is_ok());
+        assert!(!topic_matches("sport/#/x", "sport/a/x"));
+    }
+
+    #[test]
+    fn router_dispatches_to_matching_handlers() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let id = Uuid::now_v7();
+        let status_hits = Arc::new(AtomicUsize::new(0));
+        let device_hits = Arc::new(AtomicUsize::new(0));
+        let mut router = TopicRouter::new();
+        let hits = status_hits.clone();
+        router
+            .route(device_status_filter(), move |_, _| {
+                hits.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+            .unwrap();
+        let hits = device_hits.clone();
+        router
+            .route(device_filter(id), move |_, _| {
+                hits.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+            .unwrap();
+        assert!(router.route("bad/#/filter", |_, _| Ok(())).is_err());
+        assert_eq!(router.filters().count(), 2);
+
+        assert_eq!(router.dispatch(&device_status(id), b"").unwrap(), 2);
+        assert_eq!(router.dispatch(&device_state(id), b"").unwrap(), 1);
+        assert_eq!(router.dispatch(&runner_status(id), b"").unwrap(), 0);
+        assert_eq!(status_hits.load(Ordering::Relaxed), 1);
+        assert_eq!(device_hits.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn router_reports_handler_errors() {
+        let mut router = TopicRouter::new();
+        router
+            .route("a/+", |_, _| Err(LightspeedError::UnknownCommand))
+            .unwrap();
+        assert!(matches!(
+            router.dispatch("a/b", b""),
+            Err(LightspeedError::UnknownCommand)
+        ));
+    }
+
     #[test]
     fn builds_client_reply() {
         let cid = Uuid::nil();
         let s = client_reply("client-1", cid);
-        assert_eq!(s, "clients/client-1/replies/00000000-0000-0000-0000-000000000000");
+        assert_eq!(
+            s,
+            "clients/client-1/replies/00000000-0000-0000-0000-000000000000"
+        );
     }
 }
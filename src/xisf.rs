@@ -0,0 +1,655 @@
+//! XISF 1.0 writer for captured frames, for PixInsight users.
+//!
+//! Files are monolithic: the `XISF0100` signature, the XML header length,
+//! the XML header, then a single attached data block aligned to
+//! [`BLOCK_ALIGN`]. Pixels are stored planar and little-endian as `UInt8` or
+//! `UInt16`. The header carries the caller's FITS keywords (usually
+//! [`FitsHeader::from_frame`]) as `FITSKeyword` elements, plus the matching
+//! XISF properties (`Instrument:ExposureTime`, `Instrument:Sensor:Temperature`,
+//! ...) and a `ColorFilterArray` for Bayer frames.
+//!
+//! The data block can be compressed with zlib or, with the `lz4` feature,
+//! LZ4, optionally after byte shuffling, which usually helps 16-bit data.
+
+use std::fmt;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::fits::{format_date, format_real, is_structural, FitsHeader, Value};
+use crate::frame::{
+    decompress_payload, for_each_sample, FrameError, FrameHeader, FrameType, PixelFormat,
+    MAX_PAYLOAD_LEN,
+};
+
+/// File signature of monolithic XISF 1.0 files.
+pub const SIGNATURE: &[u8; 8] = b"XISF0100";
+/// The attached data block starts at a multiple of this many bytes.
+pub const BLOCK_ALIGN: usize = 4096;
+
+#[derive(Debug)]
+pub enum XisfError {
+    /// The source frame could not be read.
+    Frame(FrameError),
+    /// A FITS keyword that cannot be represented, e.g. a NaN value.
+    InvalidCard(String),
+    /// The compression codec is not compiled in.
+    Unsupported(XisfCompression),
+    Io(io::Error),
+}
+
+impl fmt::Display for XisfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XisfError::Frame(e) => write!(f, "xisf source: {e}"),
+            XisfError::InvalidCard(keyword) => {
+                write!(f, "invalid fits keyword for xisf: {keyword}")
+            }
+            XisfError::Unsupported(codec) => write!(f, "unsupported xisf compression: {codec:?}"),
+            XisfError::Io(e) => write!(f, "xisf i/o error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for XisfError {}
+
+impl From<FrameError> for XisfError {
+    fn from(error: FrameError) -> Self {
+        XisfError::Frame(error)
+    }
+}
+
+impl From<io::Error> for XisfError {
+    fn from(error: io::Error) -> Self {
+        XisfError::Io(error)
+    }
+}
+
+/// Codec of the attached data block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum XisfCompression {
+    #[default]
+    None,
+    /// zlib (RFC 1950).
+    Zlib,
+    /// LZ4 block format. Needs the `lz4` feature.
+    Lz4,
+}
+
+impl XisfCompression {
+    fn codec(&self) -> &'static str {
+        match self {
+            XisfCompression::None => "",
+            XisfCompression::Zlib => "zlib",
+            XisfCompression::Lz4 => "lz4",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct XisfOptions {
+    pub compression: XisfCompression,
+    /// Byte-shuffle samples before compressing. Ignored without compression
+    /// and for 8-bit data.
+    pub shuffle: bool,
+    /// `XISF:CreatorApplication` metadata.
+    pub creator: String,
+}
+
+impl Default for XisfOptions {
+    fn default() -> Self {
+        Self {
+            compression: XisfCompression::None,
+            shuffle: true,
+            creator: format!("astrotools {}", env!("CARGO_PKG_VERSION")),
+        }
+    }
+}
+
+/// Group byte `b` of every `item_size`-byte item together. Trailing bytes
+/// that do not form a whole item are copied as-is.
+fn shuffle(data: &[u8], item_size: usize) -> Vec<u8> {
+    let count = data.len() / item_size;
+    let mut out = Vec::with_capacity(data.len());
+    for b in 0..item_size {
+        out.extend(data.chunks_exact(item_size).map(|item| item[b]));
+    }
+    out.extend_from_slice(&data[count * item_size..]);
+    out
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// A FITS card value as it would appear in a FITS header.
+fn fits_value(value: &Value, keyword: &str) -> Result<String, XisfError> {
+    Ok(match value {
+        Value::Logical(v) => if *v { "T" } else { "F" }.to_string(),
+        Value::Integer(v) => v.to_string(),
+        Value::Real(v) => {
+            format_real(*v, keyword).map_err(|_| XisfError::InvalidCard(keyword.to_string()))?
+        }
+        Value::Text(v) => format!("'{}'", v.replace('\'', "''")),
+    })
+}
+
+/// `Image` attribute value of a frame type.
+fn image_type(frame_type: FrameType) -> &'static str {
+    match frame_type {
+        FrameType::Light => "Light",
+        FrameType::Dark => "Dark",
+        FrameType::Flat => "Flat",
+        FrameType::Bias => "Bias",
+    }
+}
+
+fn property(xml: &mut String, id: &str, kind: &str, value: impl fmt::Display) {
+    let _ = writeln!(
+        xml,
+        r#"    <Property id="{id}" type="{kind}" value="{}"/>"#,
+        escape(&value.to_string())
+    );
+}
+
+fn string_property(xml: &mut String, id: &str, value: &str) {
+    let _ = writeln!(
+        xml,
+        r#"    <Property id="{id}" type="String">{}</Property>"#,
+        escape(value)
+    );
+}
+
+/// Everything the XML header needs besides the attachment position.
+struct Layout<'a> {
+    header: &'a FrameHeader,
+    fits: &'a FitsHeader,
+    options: &'a XisfOptions,
+    channels: usize,
+    sample_format: &'static str,
+    item_size: usize,
+    raw_len: usize,
+    block_len: usize,
+}
+
+impl Layout<'_> {
+    fn xml(&self, position: usize) -> Result<String, XisfError> {
+        let h = self.header;
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(concat!(
+            r#"<xisf version="1.0" xmlns="http://www.pixinsight.com/xisf" "#,
+            r#"xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" "#,
+            r#"xsi:schemaLocation="http://www.pixinsight.com/xisf http://pixinsight.com/xisf/xisf-1.0.xsd">"#,
+            "\n"
+        ));
+
+        let _ = write!(
+            xml,
+            r#"  <Image geometry="{}:{}:{}" sampleFormat="{}" colorSpace="{}" location="attachment:{position}:{}""#,
+            h.width,
+            h.height,
+            self.channels,
+            self.sample_format,
+            if self.channels == 3 { "RGB" } else { "Gray" },
+            self.block_len,
+        );
+        if self.options.compression != XisfCompression::None {
+            let codec = self.options.compression.codec();
+            if self.options.shuffle && self.item_size > 1 {
+                let _ = write!(
+                    xml,
+                    r#" compression="{codec}+sh:{}:{}""#,
+                    self.raw_len, self.item_size
+                );
+            } else {
+                let _ = write!(xml, r#" compression="{codec}:{}""#, self.raw_len);
+            }
+        }
+        if let Some(frame_type) = h.frame_type {
+            let _ = write!(xml, r#" imageType="{}""#, image_type(frame_type));
+        }
+        xml.push_str(">\n");
+
+        if let Some(pattern) = h.bayer.filter(|_| self.channels == 1) {
+            let _ = writeln!(
+                xml,
+                r#"    <ColorFilterArray pattern="{pattern}" width="2" height="2"/>"#
+            );
+        }
+        if h.timestamp_ns != 0 {
+            property(
+                &mut xml,
+                "Observation:Time:Start",
+                "TimePoint",
+                format!("{}Z", format_date(h.timestamp_ns)),
+            );
+        }
+        if let Some(v) = h.exposure_secs {
+            property(&mut xml, "Instrument:ExposureTime", "Float32", v);
+        }
+        if let Some(v) = h.gain {
+            property(&mut xml, "Instrument:Camera:Gain", "Float32", v);
+        }
+        if let Some(v) = h.bin_x {
+            property(&mut xml, "Instrument:Camera:XBinning", "Int32", v);
+        }
+        if let Some(v) = h.bin_y {
+            property(&mut xml, "Instrument:Camera:YBinning", "Int32", v);
+        }
+        if let Some(v) = h.sensor_temp_c {
+            property(&mut xml, "Instrument:Sensor:Temperature", "Float32", v);
+        }
+        if let Some(v) = h.pixel_size_x_um {
+            property(
+                &mut xml,
+                "Instrument:Sensor:XPixelSize",
+                "Float32",
+                v * h.bin_x.unwrap_or(1) as f64,
+            );
+        }
+        if let Some(v) = h.pixel_size_y_um {
+            property(
+                &mut xml,
+                "Instrument:Sensor:YPixelSize",
+                "Float32",
+                v * h.bin_y.unwrap_or(1) as f64,
+            );
+        }
+        if let Some(v) = &h.filter {
+            string_property(&mut xml, "Instrument:Filter:Name", v);
+        }
+        if let Some(v) = self.fits.get("INSTRUME").and_then(Value::as_str) {
+            string_property(&mut xml, "Instrument:Camera:Name", v);
+        }
+
+        for card in self
+            .fits
+            .cards()
+            .iter()
+            .filter(|c| !is_structural(&c.keyword))
+        {
+            let value = match &card.value {
+                Some(value) => fits_value(value, &card.keyword)?,
+                None => String::new(),
+            };
+            let _ = writeln!(
+                xml,
+                r#"    <FITSKeyword name="{}" value="{}" comment="{}"/>"#,
+                escape(&card.keyword),
+                escape(&value),
+                escape(card.comment.as_deref().unwrap_or("")),
+            );
+        }
+        xml.push_str("  </Image>\n  <Metadata>\n");
+        string_property(&mut xml, "XISF:CreatorApplication", &self.options.creator);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        property(
+            &mut xml,
+            "XISF:CreationTime",
+            "TimePoint",
+            format!("{}Z", format_date(now.as_nanos() as u64)),
+        );
+        if self.options.compression != XisfCompression::None {
+            string_property(
+                &mut xml,
+                "XISF:CompressionCodecs",
+                self.options.compression.codec(),
+            );
+        }
+        xml.push_str("  </Metadata>\n</xisf>\n");
+        Ok(xml)
+    }
+}
+
+fn compress(data: &[u8], compression: XisfCompression) -> Result<Vec<u8>, XisfError> {
+    match compression {
+        XisfCompression::None => Ok(data.to_vec()),
+        XisfCompression::Zlib => {
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            Ok(encoder.finish()?)
+        }
+        #[cfg(feature = "lz4")]
+        XisfCompression::Lz4 => Ok(lz4_flex::block::compress(data)),
+        #[allow(unreachable_patterns)]
+        _ => Err(XisfError::Unsupported(compression)),
+    }
+}
+
+/// Whether the raw payload already is the XISF data block: one channel of
+/// bytes or LSB-aligned little-endian words.
+fn is_le_mono(header: &FrameHeader) -> bool {
+    match header.pixel_format {
+        Some(format) => matches!(
+            format,
+            PixelFormat::Mono8
+                | PixelFormat::Bayer8
+                | PixelFormat::Mono12Le
+                | PixelFormat::Mono16Le
+                | PixelFormat::Bayer16
+        ),
+        None => header.bit_depth != 12 && header.bit_depth <= 16,
+    }
+}
+
+/// Unpack a raw payload straight into planar little-endian samples of
+/// `item_size` bytes, without an intermediate sample buffer.
+fn planar_le_bytes(
+    header: &FrameHeader,
+    data: &[u8],
+    item_size: usize,
+) -> Result<Vec<u8>, FrameError> {
+    let channels = header.channels() as usize;
+    let pixels = header.width as usize * header.height as usize;
+    let mut planar = vec![0; pixels * channels * item_size];
+    let mut i = 0;
+    for_each_sample(header, data, |sample| {
+        let offset = ((i % channels) * pixels + i / channels) * item_size;
+        planar[offset..offset + item_size].copy_from_slice(&sample.to_le_bytes()[..item_size]);
+        i += 1;
+    })?;
+    Ok(planar)
+}
+
+/// Write a captured frame as a monolithic XISF file. 8-bit data is stored as
+/// `UInt8`, anything deeper as `UInt16`; compressed frame payloads are
+/// decompressed first. `fits` is usually [`FitsHeader::from_frame`] plus
+/// caller keywords such as `INSTRUME`.
+pub fn write_frame<W: Write>(
+    w: &mut W,
+    header: &FrameHeader,
+    data: &[u8],
+    fits: &FitsHeader,
+    options: &XisfOptions,
+) -> Result<(), XisfError> {
+    let mut source = header.clone();
+    let decoded;
+    let data = if header.encoding.is_raw() {
+        data
+    } else {
+        decoded = decompress_payload(&mut source, data, MAX_PAYLOAD_LEN)?;
+        &decoded
+    };
+    let channels = source.channels() as usize;
+    let (sample_format, item_size) = if source.significant_bits() <= 8 {
+        ("UInt8", 1)
+    } else {
+        ("UInt16", 2)
+    };
+    let planar;
+    let raw = if is_le_mono(&source) {
+        if source.payload_len() != data.len() as u64 {
+            return Err(FrameError::PayloadLength {
+                expected: source.payload_len(),
+                actual: data.len() as u64,
+            }
+            .into());
+        }
+        data
+    } else {
+        planar = planar_le_bytes(&source, data, item_size)?;
+        &planar
+    };
+
+    let compressed;
+    let block = if options.compression == XisfCompression::None {
+        raw
+    } else {
+        compressed = if options.shuffle && item_size > 1 {
+            compress(&shuffle(raw, item_size), options.compression)?
+        } else {
+            compress(raw, options.compression)?
+        };
+        &compressed
+    };
+
+    let layout = Layout {
+        header: &source,
+        fits,
+        options,
+        channels,
+        sample_format,
+        item_size,
+        raw_len: raw.len(),
+        block_len: block.len(),
+    };
+    // The XML holds the block position, which depends on the XML length.
+    let mut position = BLOCK_ALIGN;
+    let xml = loop {
+        let xml = layout.xml(position)?;
+        let needed = SIGNATURE.len() + 8 + xml.len();
+        if needed <= position {
+            break xml;
+        }
+        position = needed.div_ceil(BLOCK_ALIGN) * BLOCK_ALIGN;
+    };
+
+    w.write_all(SIGNATURE)?;
+    w.write_all(&(xml.len() as u32).to_le_bytes())?;
+    w.write_all(&[0; 4])?;
+    w.write_all(xml.as_bytes())?;
+    w.write_all(&vec![0; position - SIGNATURE.len() - 8 - xml.len()])?;
+    w.write_all(block)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::BayerPattern;
+    use std::io::Read;
+
+    fn frame() -> (FrameHeader, Vec<u8>) {
+        let header = FrameHeader {
+            width: 4,
+            height: 2,
+            bit_depth: 16,
+            pixel_format: Some(PixelFormat::Bayer16),
+            bayer: Some(BayerPattern::Rggb),
+            timestamp_ns: 1_700_000_000_000_000_000,
+            exposure_secs: Some(120.0),
+            sensor_temp_c: Some(-10.0),
+            frame_type: Some(FrameType::Light),
+            filter: Some("<Ha & OIII>".into()),
+            ..Default::default()
+        };
+        let data = (0..8u16)
+            .flat_map(|i| (i * 1000 + 1).to_le_bytes())
+            .collect();
+        (header, data)
+    }
+
+    /// Split a file into its XML header and the attached block it points to.
+    fn parse(file: &[u8]) -> (String, Vec<u8>) {
+        assert_eq!(&file[..8], SIGNATURE);
+        let len = u32::from_le_bytes(file[8..12].try_into().unwrap()) as usize;
+        let xml = String::from_utf8(file[16..16 + len].to_vec()).unwrap();
+        let location = xml.split("location=\"attachment:").nth(1).unwrap();
+        let mut parts = location.split(['"', ':']);
+        let position: usize = parts.next().unwrap().parse().unwrap();
+        let size: usize = parts.next().unwrap().parse().unwrap();
+        (xml, file[position..position + size].to_vec())
+    }
+
+    #[test]
+    fn writes_uncompressed_16_bit_bayer() {
+        let (header, data) = frame();
+        let mut fits = FitsHeader::from_frame(&header);
+        fits.set("INSTRUME", "ZWO ASI2600MC", "");
+        let mut out = Vec::new();
+        write_frame(&mut out, &header, &data, &fits, &XisfOptions::default()).unwrap();
+
+        let (xml, block) = parse(&out);
+        assert_eq!(block, data);
+        assert!(xml.contains(r#"geometry="4:2:1" sampleFormat="UInt16" colorSpace="Gray""#));
+        assert!(xml.contains(r#"location="attachment:4096:16""#));
+        assert!(xml.contains(r#"imageType="Light""#));
+        assert!(xml.contains(r#"<ColorFilterArray pattern="RGGB" width="2" height="2"/>"#));
+        assert!(xml.contains(r#"id="Instrument:ExposureTime" type="Float32" value="120""#));
+        assert!(xml.contains(
+            r#"id="Observation:Time:Start" type="TimePoint" value="2023-11-14T22:13:20.000000Z""#
+        ));
+        assert!(xml.contains(r#"<Property id="Instrument:Filter:Name" type="String">&lt;Ha &amp; OIII&gt;</Property>"#));
+        assert!(xml.contains(
+            r#"<Property id="Instrument:Camera:Name" type="String">ZWO ASI2600MC</Property>"#
+        ));
+        assert!(xml.contains(
+            r#"<FITSKeyword name="EXPTIME" value="120.0" comment="[s] exposure duration"/>"#
+        ));
+        assert!(xml.contains(r#"<FITSKeyword name="BAYERPAT" value="&apos;RGGB&apos;""#));
+        assert!(!xml.contains("compression="));
+    }
+
+    #[test]
+    fn zlib_shuffled_roundtrip() {
+        let (header, data) = frame();
+        let options = XisfOptions {
+            compression: XisfCompression::Zlib,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_frame(&mut out, &header, &data, &FitsHeader::new(), &options).unwrap();
+
+        let (xml, block) = parse(&out);
+        assert!(xml.contains(r#"compression="zlib+sh:16:2""#));
+        let mut shuffled = Vec::new();
+        flate2::read::ZlibDecoder::new(block.as_slice())
+            .read_to_end(&mut shuffled)
+            .unwrap();
+        assert_eq!(shuffled, shuffle(&data, 2));
+        assert_eq!(&shuffled[..8], &[1, 233, 209, 185, 161, 137, 113, 89]);
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn lz4_unshuffled_roundtrip() {
+        let (header, data) = frame();
+        let options = XisfOptions {
+            compression: XisfCompression::Lz4,
+            shuffle: false,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_frame(&mut out, &header, &data, &FitsHeader::new(), &options).unwrap();
+        let (xml, block) = parse(&out);
+        assert!(xml.contains(r#"compression="lz4:16""#));
+        assert_eq!(lz4_flex::block::decompress(&block, 16).unwrap(), data);
+    }
+
+    #[test]
+    fn rgb_is_planar_and_large_headers_move_the_block() {
+        let header = FrameHeader {
+            width: 2,
+            height: 1,
+            bit_depth: 8,
+            pixel_format: Some(PixelFormat::Rgb24),
+            ..Default::default()
+        };
+        let mut fits = FitsHeader::new();
+        for i in 0..100 {
+            fits.comment(&format!("padding comment number {i}"));
+        }
+        let mut out = Vec::new();
+        write_frame(
+            &mut out,
+            &header,
+            &[1, 2, 3, 4, 5, 6],
+            &fits,
+            &XisfOptions::default(),
+        )
+        .unwrap();
+        let (xml, block) = parse(&out);
+        assert!(xml.contains(r#"geometry="2:1:3" sampleFormat="UInt8" colorSpace="RGB""#));
+        assert!(xml.len() > BLOCK_ALIGN);
+        let position = (16 + xml.len()).div_ceil(BLOCK_ALIGN) * BLOCK_ALIGN;
+        assert!(xml.contains(&format!(r#"location="attachment:{position}:6""#)));
+        assert_eq!(block, [1, 4, 2, 5, 3, 6]);
+    }
+
+    #[test]
+    fn deep_samples_are_converted_to_planar_little_endian() {
+        let header = FrameHeader {
+            width: 2,
+            height: 1,
+            bit_depth: 16,
+            pixel_format: Some(PixelFormat::Rgb48Le),
+            ..Default::default()
+        };
+        let data: Vec<u8> = (1..=6u16).flat_map(|v| (v * 257).to_le_bytes()).collect();
+        let mut out = Vec::new();
+        write_frame(
+            &mut out,
+            &header,
+            &data,
+            &FitsHeader::new(),
+            &XisfOptions::default(),
+        )
+        .unwrap();
+        let (xml, block) = parse(&out);
+        assert!(xml.contains(r#"geometry="2:1:3" sampleFormat="UInt16" colorSpace="RGB""#));
+        let expected: Vec<u8> = [1, 4, 2, 5, 3, 6u16]
+            .iter()
+            .flat_map(|v| (v * 257).to_le_bytes())
+            .collect();
+        assert_eq!(block, expected);
+
+        let header = FrameHeader {
+            pixel_format: Some(PixelFormat::Mono16Be),
+            ..header
+        };
+        let mut out = Vec::new();
+        write_frame(
+            &mut out,
+            &header,
+            &[1, 2, 3, 4],
+            &FitsHeader::new(),
+            &XisfOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(parse(&out).1, [2, 1, 4, 3]);
+        assert!(matches!(
+            write_frame(
+                &mut Vec::new(),
+                &header,
+                &[1, 2, 3],
+                &FitsHeader::new(),
+                &XisfOptions::default()
+            ),
+            Err(XisfError::Frame(FrameError::PayloadLength { .. }))
+        ));
+        let header = FrameHeader {
+            pixel_format: Some(PixelFormat::Mono16Le),
+            ..header
+        };
+        assert!(matches!(
+            write_frame(
+                &mut Vec::new(),
+                &header,
+                &[1, 2, 3],
+                &FitsHeader::new(),
+                &XisfOptions::default()
+            ),
+            Err(XisfError::Frame(FrameError::PayloadLength { .. }))
+        ));
+    }
+
+    #[test]
+    fn shuffle_keeps_trailing_bytes() {
+        assert_eq!(shuffle(&[1, 2, 3, 4, 5, 6, 7], 3), [1, 4, 2, 5, 3, 6, 7]);
+    }
+}